@@ -0,0 +1,371 @@
+//! Anonymization of selected columns.
+//!
+//! Transforms are set per column in the configuration file and applied to parsed rows,
+//! before they are written:
+//!
+//! ```toml
+//! salt = "a long random secret"
+//!
+//! [tables.user.columns.email]
+//! transform = "hash"
+//! ```
+//!
+//! Hashes and fake values only depend on the value and the salt, so that the same value
+//! gets the same replacement in every table and joins keep working.
+
+use std::collections::BTreeMap;
+
+use color_eyre::eyre::{bail, Result};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::line_parser::{ColumnType, ColumnValue, Schema};
+
+/// Key-value metadata listing the transforms applied, as a JSON object of column names
+pub const TRANSFORMS_KEY: &str = "anonymization.transforms";
+
+/// Transform of the values of a column
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Transform {
+    /// Hex SHA-256 of the salt and the value, written as a string
+    Hash,
+    /// `john.doe@example.com` to `j*******@e******.com`
+    MaskEmail,
+    /// `+33 6 12 34 56 78` to `+** * ** ** 56 78`, keeping the last 4 digits
+    MaskPhone,
+    /// Deterministic fake value: a pronounceable word of the same length, an email at
+    /// `example.com` or a number of as many digits
+    Fake,
+    /// First day of the month of a date, at midnight
+    TruncateMonth,
+    Null,
+}
+
+impl Transform {
+    pub fn name(self) -> &'static str {
+        match self {
+            Transform::Hash => "hash",
+            Transform::MaskEmail => "mask_email",
+            Transform::MaskPhone => "mask_phone",
+            Transform::Fake => "fake",
+            Transform::TruncateMonth => "truncate_month",
+            Transform::Null => "null",
+        }
+    }
+
+    fn supports(self, column_type: &ColumnType) -> bool {
+        match self {
+            Transform::Hash | Transform::Null => true,
+            Transform::MaskEmail | Transform::MaskPhone => *column_type == ColumnType::String,
+            Transform::Fake => matches!(column_type, ColumnType::String | ColumnType::Integer),
+            Transform::TruncateMonth => *column_type == ColumnType::Timestamp,
+        }
+    }
+}
+
+/// Transforms of the columns of a table
+#[derive(Clone, Debug)]
+pub struct ColumnTransforms {
+    /// position of the column in `CREATE TABLE` order, and its transform
+    columns: Vec<(usize, Transform)>,
+    salt: String,
+    /// transform names by column, recorded in the metadata of the files
+    names: BTreeMap<String, &'static str>,
+}
+
+impl ColumnTransforms {
+    /// Transforms of the columns of `schema`, given by MySQL name. Hashes and fake values
+    /// need a `salt`.
+    pub fn new(
+        table_name: &str,
+        schema: &Schema,
+        transforms: &[(&str, Transform)],
+        salt: Option<&str>,
+    ) -> Result<ColumnTransforms> {
+        let mut columns = Vec::new();
+        let mut names = BTreeMap::new();
+        for &(column_name, transform) in transforms {
+            let Some(position) = schema
+                .columns
+                .iter()
+                .position(|c| c.column_name.eq_ignore_ascii_case(column_name))
+            else {
+                bail!("Cannot anonymize unknown column `{column_name}` of `{table_name}`");
+            };
+            let column = &schema.columns[position];
+            if !transform.supports(&column.column_type) {
+                bail!(
+                    "Cannot apply {} to column `{column_name}` of `{table_name}` ({:?})",
+                    transform.name(),
+                    column.column_type
+                );
+            }
+            if matches!(transform, Transform::Hash | Transform::Fake) && salt.is_none() {
+                bail!(
+                    "{} of column `{column_name}` of `{table_name}` needs a salt: set `--salt`",
+                    transform.name()
+                );
+            }
+            columns.push((position, transform));
+            names.insert(column.column_name.clone(), transform.name());
+        }
+        Ok(ColumnTransforms {
+            columns,
+            salt: salt.unwrap_or_default().to_string(),
+            names,
+        })
+    }
+
+    /// Columns after their transforms: hashes are strings and nulled columns nullable
+    pub fn schema(&self, schema: &mut Schema) {
+        for &(position, transform) in &self.columns {
+            let column = &mut schema.columns[position];
+            match transform {
+                Transform::Hash => column.column_type = ColumnType::String,
+                Transform::Null => column.nullable = true,
+                _ => {}
+            }
+        }
+    }
+
+    pub fn rows(&self, rows: &mut [Vec<ColumnValue>]) {
+        for row in rows {
+            for &(position, transform) in &self.columns {
+                let value = std::mem::replace(&mut row[position], ColumnValue::Null);
+                row[position] = self.apply(transform, value);
+            }
+        }
+    }
+
+    /// Transforms applied, as a JSON object such as `{"email":"hash"}`
+    pub fn metadata(&self) -> String {
+        serde_json::to_string(&self.names).unwrap()
+    }
+
+    fn apply(&self, transform: Transform, value: ColumnValue) -> ColumnValue {
+        let text = match &value {
+            ColumnValue::Null => return ColumnValue::Null,
+            ColumnValue::String(s) | ColumnValue::Decimal(s) => s.clone(),
+            ColumnValue::Integer(i) => i.to_string(),
+            ColumnValue::Float(f) => f.to_string(),
+            ColumnValue::Boolean(b) => b.to_string(),
+        };
+        match transform {
+            Transform::Hash => ColumnValue::String(
+                self.digest(&text)
+                    .iter()
+                    .map(|byte| format!("{byte:02x}"))
+                    .collect(),
+            ),
+            Transform::MaskEmail => ColumnValue::String(mask_email(&text)),
+            Transform::MaskPhone => ColumnValue::String(mask_phone(&text)),
+            Transform::Fake => {
+                let digest = self.digest(&text);
+                match value {
+                    ColumnValue::Integer(i) => ColumnValue::Integer(fake_integer(i, &digest)),
+                    _ => ColumnValue::String(fake_string(&text, &digest)),
+                }
+            }
+            Transform::TruncateMonth => match text.get(..7) {
+                Some(month) => ColumnValue::String(format!("{month}-01 00:00:00")),
+                None => value,
+            },
+            Transform::Null => ColumnValue::Null,
+        }
+    }
+
+    fn digest(&self, text: &str) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(self.salt.as_bytes());
+        hasher.update(text.as_bytes());
+        hasher.finalize().into()
+    }
+}
+
+/// Keep the first character of every part of an email, its `@`, dots and top-level domain
+fn mask_email(email: &str) -> String {
+    let mask = |part: &str| -> String {
+        part.chars()
+            .enumerate()
+            .map(|(i, c)| if i == 0 { c } else { '*' })
+            .collect()
+    };
+    let Some((local, domain)) = email.rsplit_once('@') else {
+        return mask(email);
+    };
+    let domain = match domain.rsplit_once('.') {
+        Some((name, tld)) => format!(
+            "{}.{tld}",
+            name.split('.').map(mask).collect::<Vec<_>>().join(".")
+        ),
+        None => mask(domain),
+    };
+    format!("{}@{domain}", mask(local))
+}
+
+/// Mask the digits of a phone number but the last 4 ones, keeping its format
+fn mask_phone(phone: &str) -> String {
+    let digits = phone.chars().filter(char::is_ascii_digit).count();
+    let mut seen = 0;
+    phone
+        .chars()
+        .map(|c| {
+            if !c.is_ascii_digit() {
+                return c;
+            }
+            seen += 1;
+            if digits <= 4 || seen <= digits - 4 {
+                '*'
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+/// Pronounceable word of as many characters as `text`, or an email at `example.com`
+fn fake_string(text: &str, digest: &[u8; 32]) -> String {
+    const CONSONANTS: &[u8] = b"bcdfghjklmnprstvz";
+    const VOWELS: &[u8] = b"aeiou";
+    let word = |length: usize| -> String {
+        (0..length.max(1))
+            .map(|i| {
+                let byte = digest[i % digest.len()].wrapping_add((i / digest.len()) as u8);
+                let letters = if i % 2 == 0 { CONSONANTS } else { VOWELS };
+                letters[byte as usize % letters.len()] as char
+            })
+            .collect()
+    };
+    match text.split_once('@') {
+        Some((local, _)) => format!("{}@example.com", word(local.chars().count())),
+        None => {
+            let mut fake = word(text.chars().count());
+            if text.starts_with(char::is_uppercase) {
+                fake[..1].make_ascii_uppercase();
+            }
+            fake
+        }
+    }
+}
+
+/// Number of as many digits as `value`, with the same sign
+fn fake_integer(value: i64, digest: &[u8; 32]) -> i64 {
+    let digits = value.unsigned_abs().checked_ilog10().unwrap_or(0) + 1;
+    let random = u64::from_le_bytes(digest[..8].try_into().unwrap());
+    let fake = if digits >= 19 {
+        random % i64::MAX as u64
+    } else {
+        let low = if digits == 1 {
+            0
+        } else {
+            10u64.pow(digits - 1)
+        };
+        low + random % (10u64.pow(digits) - low)
+    } as i64;
+    if value < 0 {
+        -fake
+    } else {
+        fake
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{mask_email, mask_phone, ColumnTransforms, Transform};
+    use crate::line_parser::{parse_line, ColumnType, ColumnValue, Line};
+
+    #[test]
+    fn transform_rows() {
+        let Line::CreateTable(_, mut schema) = parse_line(
+            "CREATE TABLE user (id INT NOT NULL, email TEXT, phone TEXT, name TEXT, created_at DATETIME, note TEXT NOT NULL)",
+        )
+        .unwrap() else {
+            panic!("Not a CREATE TABLE statement");
+        };
+        let transforms = ColumnTransforms::new(
+            "user",
+            &schema,
+            &[
+                ("id", Transform::Hash),
+                ("email", Transform::MaskEmail),
+                ("phone", Transform::MaskPhone),
+                ("name", Transform::Fake),
+                ("created_at", Transform::TruncateMonth),
+                ("note", Transform::Null),
+            ],
+            Some("secret"),
+        )
+        .unwrap();
+        transforms.schema(&mut schema);
+        assert_eq!(schema.columns[0].column_type, ColumnType::String);
+        assert!(schema.columns[5].nullable);
+
+        let row = || {
+            vec![
+                ColumnValue::Integer(42),
+                ColumnValue::String("john.doe@example.com".into()),
+                ColumnValue::String("+33 6 12 34 56 78".into()),
+                ColumnValue::String("Alice".into()),
+                ColumnValue::String("2024-05-17 10:11:12".into()),
+                ColumnValue::String("likes cats".into()),
+            ]
+        };
+        let mut rows = vec![row(), row(), vec![ColumnValue::Null; 6]];
+        transforms.rows(&mut rows);
+        // same values, same replacements
+        assert_eq!(rows[0], rows[1]);
+        let ColumnValue::String(hash) = &rows[0][0] else {
+            panic!("Hashes are strings");
+        };
+        assert_eq!(hash.len(), 64);
+        assert_eq!(
+            rows[0][1],
+            ColumnValue::String("j*******@e******.com".into())
+        );
+        assert_eq!(rows[0][2], ColumnValue::String("+** * ** ** 56 78".into()));
+        let ColumnValue::String(name) = &rows[0][3] else {
+            panic!("Fake names are strings");
+        };
+        assert_eq!(name.len(), 5);
+        assert!(name.starts_with(char::is_uppercase));
+        assert_ne!(name, "Alice");
+        assert_eq!(
+            rows[0][4],
+            ColumnValue::String("2024-05-01 00:00:00".into())
+        );
+        assert_eq!(rows[0][5], ColumnValue::Null);
+        assert!(rows[2].iter().all(|value| *value == ColumnValue::Null));
+        assert_eq!(
+            transforms.metadata(),
+            r#"{"created_at":"truncate_month","email":"mask_email","id":"hash","name":"fake","note":"null","phone":"mask_phone"}"#
+        );
+
+        // a different salt, different hashes
+        let other =
+            ColumnTransforms::new("user", &schema, &[("id", Transform::Hash)], Some("s")).unwrap();
+        let mut other_rows = vec![row()];
+        other.rows(&mut other_rows);
+        assert_ne!(other_rows[0][0], rows[0][0]);
+
+        assert!(ColumnTransforms::new("user", &schema, &[("id", Transform::Hash)], None).is_err());
+        assert!(
+            ColumnTransforms::new("user", &schema, &[("missing", Transform::Null)], None).is_err()
+        );
+        assert!(ColumnTransforms::new(
+            "user",
+            &schema,
+            &[("created_at", Transform::MaskEmail)],
+            None
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn masks() {
+        assert_eq!(mask_email("a@b.co.uk"), "a@b.c*.uk");
+        assert_eq!(mask_email("nobody"), "n*****");
+        assert_eq!(mask_phone("0612345678"), "******5678");
+        assert_eq!(mask_phone("1234"), "****");
+    }
+}
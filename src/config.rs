@@ -0,0 +1,511 @@
+//! Configuration file, holding per table and per column settings.
+//!
+//! ```toml
+//! [parquet]
+//! compression = "zstd(9)"
+//!
+//! [tables.user]
+//! name = "users"
+//! sort_by = ["company_lid", "id"]
+//! exclude_columns = ["password_hash"]
+//! where = "created_at >= '2024-01-01' AND status != 'deleted'"
+//! partition_by = "created_at:month"
+//! sample = "10%"
+//!
+//! [tables.user.parquet]
+//! compression = "lz4"
+//! data_page_size = "1M"
+//! bloom_filter_keys = true
+//!
+//! [tables.user.rename]
+//! shortName = "short_name"
+//!
+//! [tables.user.columns.phone]
+//! transform = "mask_phone"
+//!
+//! [tables.user.columns.email.parquet]
+//! encoding = "delta_byte_array"
+//! dictionary = false
+//! bloom_filter = true
+//! bloom_filter_fpp = 0.01
+//! ```
+
+use std::{collections::HashMap, fs::read_to_string, path::Path};
+
+use color_eyre::eyre::{bail, Context, Result};
+use serde::Deserialize;
+
+use crate::{
+    anonymize::{ColumnTransforms, Transform},
+    filter::{ColumnProjection, TableFilter, TableRows},
+    line_parser::Schema,
+    naming::{self, ColumnCase},
+    overrides::SchemaOverrides,
+    parquet_writer::WriterSettings,
+    partition::PartitionBy,
+    predicate::Predicate,
+    sample::{RowSample, SampleSize},
+    sink::{Format, TextOptions},
+};
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Output format, overridden by the command line
+    #[serde(default)]
+    pub format: Format,
+    /// CSV and NDJSON settings, overridden by the command line
+    #[serde(default)]
+    pub text: TextOptions,
+    /// Global writer settings, overridden by the command line
+    #[serde(default)]
+    pub parquet: WriterSettings,
+    #[serde(default)]
+    pub tables: HashMap<String, TableConfig>,
+    /// Partitions written at the same time per table, the least recently used one is
+    /// closed when a new one is needed
+    pub max_open_partitions: Option<usize>,
+    /// Index database tables once loaded: their PRIMARY KEY, UNIQUE KEY and KEY indexes
+    #[serde(default)]
+    pub create_indexes: bool,
+    /// Case of output column and table names, overridden by the command line
+    #[serde(default)]
+    pub column_case: ColumnCase,
+    /// Secret mixed into hashed and fake values, overridden by the command line
+    pub salt: Option<String>,
+    /// Sample of every table, overridden per table
+    pub sample: Option<SampleSize>,
+    /// Rows written per table at most, overridden per table
+    pub limit: Option<usize>,
+    /// Output types of columns, from the command line
+    #[serde(skip)]
+    pub overrides: SchemaOverrides,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TableConfig {
+    /// Name of the output table, files and directories, instead of the MySQL one
+    pub name: Option<String>,
+    /// Output names of columns, by MySQL name: unlike other settings, names are case
+    /// sensitive, so that columns differing only in case can be told apart
+    #[serde(default)]
+    pub rename: HashMap<String, String>,
+    #[serde(default)]
+    pub parquet: WriterSettings,
+    /// Sort rows by these columns, spilling to disk when they do not fit in memory
+    #[serde(default)]
+    pub sort_by: Vec<String>,
+    /// Only keep rows matching this SQL condition, e.g. `tenant_id = 42`
+    #[serde(rename = "where")]
+    pub where_clause: Option<String>,
+    /// Only keep these columns
+    #[serde(default)]
+    pub include_columns: Vec<String>,
+    /// Drop these columns
+    #[serde(default)]
+    pub exclude_columns: Vec<String>,
+    /// Deterministic sample of the rows, on a hash of the PRIMARY KEY: `"1%"` or a
+    /// number of rows
+    pub sample: Option<SampleSize>,
+    /// Rows written at most
+    pub limit: Option<usize>,
+    /// Hive-style partitioning: `column` or `column:year|month|day|hour`
+    pub partition_by: Option<PartitionBy>,
+    /// Keep the partition column in the files, its value being in their path anyway
+    #[serde(default)]
+    pub keep_partition_column: bool,
+    #[serde(default)]
+    pub columns: HashMap<String, ColumnConfig>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ColumnConfig {
+    #[serde(default)]
+    pub parquet: WriterSettings,
+    /// Anonymization of the values of the column
+    pub transform: Option<Transform>,
+}
+
+impl Config {
+    pub fn load(file: &Path) -> Result<Config> {
+        let content =
+            read_to_string(file).with_context(|| format!("Cannot read {}", file.display()))?;
+        let config: Config =
+            toml::from_str(&content).with_context(|| format!("Invalid {}", file.display()))?;
+        for (table_name, table) in &config.tables {
+            for (column_name, column) in &table.columns {
+                if !column.parquet.is_column_setting() {
+                    bail!(
+                        "Only compression, encoding, dictionary, statistics and bloom filters \
+                         can be set for column {table_name}.{column_name}"
+                    );
+                }
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn table(&self, table_name: &str) -> Option<&TableConfig> {
+        self.tables.get(table_name)
+    }
+
+    /// Output name of a table, also the name of its files or directory
+    pub fn output_name(&self, table_name: &str) -> String {
+        let name = self
+            .table(table_name)
+            .and_then(|table| table.name.clone())
+            .unwrap_or_else(|| self.column_case.apply(table_name));
+        naming::file_name(&name)
+    }
+
+    /// Prepare the `schema` of `table_name` for writing: change the types of anonymized
+    /// columns, drop excluded columns and name the output fields. Returns the processing
+    /// of its rows, its limit being counted in the limits of `tables`.
+    pub fn table_rows(
+        &self,
+        table_name: &str,
+        schema: &mut Schema,
+        tables: &TableFilter,
+    ) -> Result<TableRows> {
+        let table = self.table(table_name);
+        let predicate = table
+            .and_then(|table| table.where_clause.as_deref())
+            .map(|condition| Predicate::new(condition, schema))
+            .transpose()
+            .with_context(|| format!("Cannot filter the rows of `{table_name}`"))?;
+        // after the predicate, which compares the values as parsed
+        self.overrides.apply(table_name, schema)?;
+        let mut sample = table.and_then(|table| table.sample).or(self.sample);
+        let mut limit = table.and_then(|table| table.limit).or(self.limit);
+        if tables.subset.is_some() && (predicate.is_some() || sample.is_some() || limit.is_some()) {
+            // the subset is collected from every row: filtering rows afterwards would
+            // leave rows referencing dropped ones
+            bail!(
+                "`where`, `sample` and `limit` of `{table_name}` cannot be combined with --subset"
+            );
+        }
+        if let (Some(SampleSize::Rows(rows)), Some(max)) = (sample, limit) {
+            // the sample is only known at the end: no statement can be skipped
+            sample = Some(SampleSize::Rows(rows.min(max)));
+            limit = None;
+        }
+        let sample = sample.map(|size| RowSample::new(schema, size));
+        let transforms = self.transforms(table_name, schema)?;
+        if let Some(transforms) = &transforms {
+            transforms.schema(schema);
+        }
+        let projection = self.projection(table_name, schema)?;
+        if let Some(projection) = &projection {
+            projection.schema(schema);
+        }
+        schema.check_types(table_name)?;
+        self.name_columns(table_name, schema)?;
+        Ok(TableRows {
+            subset: tables
+                .subset
+                .as_ref()
+                .map(|subset| subset.table(table_name)),
+            predicate,
+            sample,
+            limit: limit.map(|limit| tables.limits.table(table_name, limit)),
+            transforms,
+            projection,
+        })
+    }
+
+    /// Fail when columns are selected or excluded in a table which was not in the
+    /// dump, `read` telling whether a table was read
+    pub fn check_projected_tables(
+        &self,
+        tables: &TableFilter,
+        read: impl Fn(&str) -> bool,
+    ) -> Result<()> {
+        for (table_name, table) in &self.tables {
+            if (!table.include_columns.is_empty() || !table.exclude_columns.is_empty())
+                && tables.accepts(table_name)
+                && !read(table_name)
+            {
+                bail!("Columns of table `{table_name}` are selected, but it is not in the dump");
+            }
+        }
+        Ok(())
+    }
+
+    /// Anonymized columns of `table_name`, `None` when there is none
+    fn transforms(&self, table_name: &str, schema: &Schema) -> Result<Option<ColumnTransforms>> {
+        let Some(table) = self.table(table_name) else {
+            return Ok(None);
+        };
+        let mut transforms: Vec<(&str, Transform)> = table
+            .columns
+            .iter()
+            .filter_map(|(name, column)| Some((name.as_str(), column.transform?)))
+            .collect();
+        if transforms.is_empty() {
+            return Ok(None);
+        }
+        if let Some((name, _)) = transforms
+            .iter()
+            .find(|(name, _)| self.overrides.contains(table_name, name))
+        {
+            bail!("Column `{name}` of `{table_name}` cannot be both anonymized and overridden");
+        }
+        transforms.sort_by_key(|(name, _)| *name);
+        ColumnTransforms::new(table_name, schema, &transforms, self.salt.as_deref()).map(Some)
+    }
+
+    /// Columns of `table_name` kept in the output, `None` when every column is
+    fn projection(&self, table_name: &str, schema: &Schema) -> Result<Option<ColumnProjection>> {
+        match self.table(table_name) {
+            Some(table) => ColumnProjection::new(
+                table_name,
+                schema,
+                &table.include_columns,
+                &table.exclude_columns,
+            ),
+            None => Ok(None),
+        }
+    }
+
+    /// Name the output fields of the columns of `schema`, failing when two columns would
+    /// have the same name.
+    pub fn name_columns(&self, table_name: &str, schema: &mut Schema) -> Result<()> {
+        let table = self.table(table_name);
+        if let Some(table) = table {
+            for column_name in table.rename.keys() {
+                if !schema.columns.iter().any(|c| &c.column_name == column_name) {
+                    bail!("Cannot rename unknown column `{column_name}` of `{table_name}`");
+                }
+            }
+        }
+        for column in &mut schema.columns {
+            column.field_name = table
+                .and_then(|table| table.rename.get(&column.column_name))
+                .cloned()
+                .unwrap_or_else(|| self.column_case.apply(&column.column_name));
+        }
+        for (i, column) in schema.columns.iter().enumerate() {
+            if let Some(other) = schema.columns[..i]
+                .iter()
+                .find(|other| other.field_name == column.field_name)
+            {
+                bail!(
+                    "Columns `{}` and `{}` of `{table_name}` would both be named `{}`: \
+                     rename one of them in [tables.{table_name}.rename]",
+                    other.column_name,
+                    column.column_name,
+                    column.field_name
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Extension of output files
+    pub fn extension(&self) -> String {
+        self.format.extension(&self.text)
+    }
+
+    /// Writer settings of a table, falling back to the global ones
+    pub fn writer_settings(&self, table_name: &str) -> WriterSettings {
+        self.table(table_name)
+            .map(|table| table.parquet.or(&self.parquet))
+            .unwrap_or_else(|| self.parquet.clone())
+    }
+}
+
+/// Parse a `table:column1,column2` command line argument
+pub fn parse_table_columns(value: &str) -> Result<(String, Vec<String>), String> {
+    let Some((table, columns)) = value.split_once(':') else {
+        return Err(format!("Expected TABLE:COLUMN[,COLUMN...], got `{value}`"));
+    };
+    let columns: Vec<String> = columns.split(',').map(|c| c.trim().to_string()).collect();
+    if table.is_empty() || columns.iter().any(String::is_empty) {
+        return Err(format!("Expected TABLE:COLUMN[,COLUMN...], got `{value}`"));
+    }
+    Ok((table.to_string(), columns))
+}
+
+impl TableConfig {
+    /// Settings of a column, looked up by its MySQL name, case insensitively.
+    pub fn column(&self, column_name: &str) -> Option<&ColumnConfig> {
+        self.columns.get(column_name).or_else(|| {
+            self.columns
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(column_name))
+                .map(|(_, column)| column)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use parquet::basic::{Compression, Encoding, ZstdLevel};
+
+    use super::{parse_table_columns, Config};
+    use crate::filter::TableFilter;
+    use crate::line_parser::{parse_line, Line};
+    use crate::naming::ColumnCase;
+    use crate::subset::Subset;
+
+    #[test]
+    fn parse_config() {
+        let config: Config = toml::from_str(
+            r#"
+            [parquet]
+            compression = "zstd(9)"
+
+            [tables.user.parquet]
+            compression = "lz4"
+            data_page_size = "1M"
+            writer_version = "2"
+
+            [tables.user.columns.email.parquet]
+            encoding = "delta_byte_array"
+            dictionary = false
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.parquet.compression,
+            Some(Compression::ZSTD(ZstdLevel::try_new(9).unwrap()))
+        );
+        let user = config.table("user").unwrap();
+        assert_eq!(user.parquet.compression, Some(Compression::LZ4));
+        assert_eq!(user.parquet.data_page_size, Some(1 << 20));
+        let email = user.column("EMAIL").unwrap();
+        assert_eq!(email.parquet.encoding, Some(Encoding::DELTA_BYTE_ARRAY));
+        assert_eq!(email.parquet.dictionary, Some(false));
+        assert!(config.table("other").is_none());
+    }
+
+    #[test]
+    fn invalid_config() {
+        assert!(toml::from_str::<Config>("[parquet]\ncompression = \"zip\"").is_err());
+        assert!(toml::from_str::<Config>("[parquet]\ncompresion = \"lz4\"").is_err());
+        assert!(toml::from_str::<Config>("[tables.user]\nfoo = 1").is_err());
+    }
+
+    #[test]
+    fn name_columns() {
+        let Line::CreateTable(_, schema) = parse_line(
+            "CREATE TABLE `User` (`id` INT, `shortName` TEXT, `ShortName` TEXT, `Email` TEXT)",
+        )
+        .unwrap() else {
+            panic!("Not a CREATE TABLE statement");
+        };
+        let field_names = |config: &Config| {
+            let mut schema = schema.clone();
+            config
+                .name_columns("User", &mut schema)
+                .map(|()| schema.columns.into_iter().map(|c| c.field_name).collect())
+                .map_err(|e| e.to_string())
+        };
+
+        let mut config = Config::default();
+        let error: Result<Vec<String>, _> = field_names(&config);
+        assert!(error.unwrap_err().contains("`shortName` and `ShortName`"));
+        assert_eq!(config.output_name("User"), "user");
+
+        config.column_case = ColumnCase::Preserve;
+        assert_eq!(
+            field_names(&config).unwrap(),
+            ["id", "shortName", "ShortName", "Email"]
+        );
+        assert_eq!(config.output_name("User"), "User");
+        assert_eq!(config.output_name("a/b"), "a_b");
+
+        config.column_case = ColumnCase::Snake;
+        config.tables = toml::from_str(
+            r#"
+            [User]
+            name = "users"
+            rename = { shortName = "nickname" }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            field_names(&config).unwrap(),
+            ["id", "nickname", "short_name", "email"]
+        );
+        assert_eq!(config.output_name("User"), "users");
+
+        config.tables.get_mut("User").unwrap().rename =
+            [("phone".to_string(), "tel".to_string())].into();
+        assert!(field_names(&config)
+            .unwrap_err()
+            .contains("unknown column `phone`"));
+    }
+
+    #[test]
+    fn table_columns() {
+        assert_eq!(
+            parse_table_columns("user:company_lid, id"),
+            Ok(("user".into(), vec!["company_lid".into(), "id".into()]))
+        );
+        assert!(parse_table_columns("user").is_err());
+        assert!(parse_table_columns("user:").is_err());
+        assert!(parse_table_columns(":id").is_err());
+    }
+
+    #[test]
+    fn subset_without_row_filters() {
+        let Line::CreateTable(_, schema) = parse_line("CREATE TABLE user (id INT)").unwrap() else {
+            panic!("Not a CREATE TABLE statement");
+        };
+        let tables = TableFilter {
+            subset: Some(Subset::default()),
+            ..Default::default()
+        };
+        let config = Config::default();
+        assert!(config
+            .table_rows("user", &mut schema.clone(), &tables)
+            .is_ok());
+        let config: Config = toml::from_str("[tables.user]\nwhere = \"id > 1\"").unwrap();
+        assert_eq!(
+            config
+                .table_rows("user", &mut schema.clone(), &tables)
+                .unwrap_err()
+                .to_string(),
+            "`where`, `sample` and `limit` of `user` cannot be combined with --subset"
+        );
+        let config: Config = toml::from_str("limit = 10").unwrap();
+        assert!(config
+            .table_rows("user", &mut schema.clone(), &tables)
+            .is_err());
+    }
+
+    #[test]
+    fn exclude_unsupported_columns() {
+        let Line::CreateTable(_, schema) =
+            parse_line("CREATE TABLE doc (id INT, body JSON)").unwrap()
+        else {
+            panic!("Not a CREATE TABLE statement");
+        };
+        let tables = TableFilter::default();
+        let error = Config::default()
+            .table_rows("doc", &mut schema.clone(), &tables)
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Unsupported data types in table `doc`: `body` JSON"
+        );
+        let config: Config = toml::from_str("[tables.doc]\nexclude_columns = [\"body\"]").unwrap();
+        let mut output_schema = schema.clone();
+        config
+            .table_rows("doc", &mut output_schema, &tables)
+            .unwrap();
+        assert_eq!(output_schema.columns.len(), 1);
+
+        assert!(config.check_projected_tables(&tables, |_| true).is_ok());
+        assert_eq!(
+            config
+                .check_projected_tables(&tables, |_| false)
+                .unwrap_err()
+                .to_string(),
+            "Columns of table `doc` are selected, but it is not in the dump"
+        );
+    }
+}
@@ -0,0 +1,331 @@
+//! Selection of the tables, rows and columns to convert.
+//!
+//! Statements of other tables are skipped by the reader from their first line, before
+//! being parsed or even buffered. Other rows and columns are dropped from parsed rows
+//! before they reach the writers.
+
+use std::str::FromStr;
+
+use color_eyre::eyre::{bail, Result};
+use regex::Regex;
+
+use parquet::format::KeyValue;
+
+use crate::{
+    anonymize::{ColumnTransforms, TRANSFORMS_KEY},
+    line_parser::{ColumnValue, Schema},
+    predicate::Predicate,
+    sample::{RowLimits, RowSample, TableLimit},
+    subset::{Subset, SubsetRows},
+};
+
+/// Table name pattern: a glob (`log_*`, `user_?`, `[ab]*`) or, between slashes, a regular
+/// expression (`/^log_\d+$/`) matching the whole name
+#[derive(Clone, Debug)]
+pub enum TablePattern {
+    Glob(glob::Pattern),
+    Regex(Regex),
+}
+
+impl FromStr for TablePattern {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value
+            .strip_prefix('/')
+            .and_then(|value| value.strip_suffix('/'))
+        {
+            Some(regex) => Regex::new(&format!("^(?:{regex})$"))
+                .map(TablePattern::Regex)
+                .map_err(|e| format!("Invalid regular expression `{regex}`: {e}")),
+            None => glob::Pattern::new(value)
+                .map(TablePattern::Glob)
+                .map_err(|e| format!("Invalid glob pattern `{value}`: {e}")),
+        }
+    }
+}
+
+impl TablePattern {
+    pub fn matches(&self, table_name: &str) -> bool {
+        match self {
+            TablePattern::Glob(pattern) => pattern.matches(table_name),
+            TablePattern::Regex(regex) => regex.is_match(table_name),
+        }
+    }
+}
+
+/// Tables matching any `include` pattern, or every table when there is none, and no
+/// `exclude` pattern
+#[derive(Clone, Debug, Default)]
+pub struct TableFilter {
+    pub include: Vec<TablePattern>,
+    pub exclude: Vec<TablePattern>,
+    /// tables whose `INSERT` statements are skipped once their limit is reached
+    pub limits: RowLimits,
+    /// rows of the `--subset`, collected by a first pass
+    pub subset: Option<Subset>,
+    /// skip every `INSERT` statement, only reading schemas
+    pub schema_only: bool,
+}
+
+impl TableFilter {
+    pub fn accepts(&self, table_name: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|p| p.matches(table_name)))
+            && !self.exclude.iter().any(|p| p.matches(table_name))
+    }
+
+    /// Whether the statement starting with `line` is kept, statements on no table being
+    /// kept
+    pub fn accepts_statement(&self, line: &str) -> bool {
+        let Some(table_name) = statement_table(line) else {
+            return true;
+        };
+        self.accepts(table_name)
+            && !(line.starts_with("INSERT INTO")
+                && (self.schema_only
+                    || self.limits.is_reached(table_name)
+                    || self
+                        .subset
+                        .as_ref()
+                        .is_some_and(|subset| subset.is_empty(table_name))))
+    }
+}
+
+/// Processing of the parsed rows of a table before they are written: rows out of the
+/// subset or not matching its `WHERE` condition are dropped, then rows out of its sample
+/// or beyond its limit, anonymized columns are transformed, and excluded columns dropped.
+#[derive(Clone, Debug, Default)]
+pub struct TableRows {
+    pub subset: Option<SubsetRows>,
+    pub predicate: Option<Predicate>,
+    pub sample: Option<RowSample>,
+    pub limit: Option<TableLimit>,
+    pub transforms: Option<ColumnTransforms>,
+    pub projection: Option<ColumnProjection>,
+}
+
+impl TableRows {
+    pub fn apply(&self, rows: &mut Vec<Vec<ColumnValue>>) {
+        if let Some(subset) = &self.subset {
+            subset.rows(rows);
+        }
+        if let Some(predicate) = &self.predicate {
+            rows.retain(|row| predicate.matches(row));
+        }
+        if let Some(sample) = &self.sample {
+            sample.rows(rows);
+        }
+        if let Some(limit) = &self.limit {
+            limit.rows(rows);
+        }
+        self.output(rows);
+    }
+
+    /// Whether rows are only written once every row has been seen, by
+    /// [`TableRows::sampled_rows`]
+    pub fn is_buffered(&self) -> bool {
+        self.sample.as_ref().is_some_and(RowSample::is_buffered)
+    }
+
+    /// Rows of a row count sample, once every row has been seen
+    pub fn sampled_rows(&self) -> Vec<Vec<ColumnValue>> {
+        let mut rows = self
+            .sample
+            .as_ref()
+            .map(RowSample::take)
+            .unwrap_or_default();
+        self.output(&mut rows);
+        rows
+    }
+
+    fn output(&self, rows: &mut [Vec<ColumnValue>]) {
+        if let Some(transforms) = &self.transforms {
+            transforms.rows(rows);
+        }
+        if let Some(projection) = &self.projection {
+            projection.rows(rows);
+        }
+    }
+
+    /// Key-value metadata of the output files, recording the transforms applied
+    pub fn key_value_metadata(&self) -> Vec<KeyValue> {
+        self.transforms
+            .iter()
+            .map(|transforms| KeyValue::new(TRANSFORMS_KEY.to_string(), transforms.metadata()))
+            .collect()
+    }
+}
+
+/// Columns of a table kept in the output, in `CREATE TABLE` order
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ColumnProjection {
+    /// whether each column of the `CREATE TABLE` statement is kept
+    keep: Vec<bool>,
+}
+
+impl ColumnProjection {
+    /// Projection of the columns of `table_name` keeping the `include` ones, or all of
+    /// them when empty, but the `exclude` ones. `None` when every column is kept.
+    pub fn new(
+        table_name: &str,
+        schema: &Schema,
+        include: &[String],
+        exclude: &[String],
+    ) -> Result<Option<ColumnProjection>> {
+        for column_name in include.iter().chain(exclude) {
+            if !schema
+                .columns
+                .iter()
+                .any(|c| c.column_name.eq_ignore_ascii_case(column_name))
+            {
+                bail!("Unknown column `{column_name}` in table `{table_name}`");
+            }
+        }
+        let keep: Vec<bool> = schema
+            .columns
+            .iter()
+            .map(|column| {
+                let listed = |names: &[String]| {
+                    names
+                        .iter()
+                        .any(|name| name.eq_ignore_ascii_case(&column.column_name))
+                };
+                (include.is_empty() || listed(include)) && !listed(exclude)
+            })
+            .collect();
+        if keep.iter().all(|keep| *keep) {
+            return Ok(None);
+        }
+        if !keep.contains(&true) {
+            bail!("Every column of table `{table_name}` is excluded");
+        }
+        Ok(Some(ColumnProjection { keep }))
+    }
+
+    pub fn schema(&self, schema: &mut Schema) {
+        retain(&mut schema.columns, &self.keep);
+    }
+
+    /// Drop the values of excluded columns, in place
+    pub fn rows(&self, rows: &mut [Vec<ColumnValue>]) {
+        for row in rows {
+            retain(row, &self.keep);
+        }
+    }
+}
+
+fn retain<T>(values: &mut Vec<T>, keep: &[bool]) {
+    let mut keep = keep.iter();
+    values.retain(|_| keep.next().copied().unwrap_or(true));
+}
+
+/// Table of an `INSERT INTO` or `CREATE TABLE` statement, read from its first line
+fn statement_table(line: &str) -> Option<&str> {
+    let name = line
+        .strip_prefix("INSERT INTO ")
+        .or_else(|| line.strip_prefix("CREATE TABLE IF NOT EXISTS "))
+        .or_else(|| line.strip_prefix("CREATE TABLE "))?
+        .trim_start();
+    match name.strip_prefix('`') {
+        Some(quoted) => quoted.split_once('`').map(|(name, _)| name),
+        None => name
+            .split(|c: char| c.is_whitespace() || c == '(')
+            .next()
+            .filter(|name| !name.is_empty()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{statement_table, ColumnProjection, TableFilter, TablePattern};
+    use crate::line_parser::{parse_line, ColumnValue, Line};
+
+    #[test]
+    fn filter_tables() {
+        let patterns = |patterns: &[&str]| -> Vec<TablePattern> {
+            patterns.iter().map(|p| p.parse().unwrap()).collect()
+        };
+        let filter = TableFilter {
+            include: patterns(&["user*", r"/log_\d+/"]),
+            exclude: patterns(&["user_archive"]),
+            ..TableFilter::default()
+        };
+        assert!(filter.accepts("user"));
+        assert!(filter.accepts("user_company"));
+        assert!(!filter.accepts("user_archive"));
+        assert!(filter.accepts("log_2024"));
+        assert!(!filter.accepts("log_2024_old"));
+        assert!(!filter.accepts("company"));
+
+        assert!(filter.accepts_statement("INSERT INTO `user` VALUES (1);"));
+        assert!(!filter.accepts_statement("INSERT INTO `company` VALUES (1);"));
+        assert!(!filter.accepts_statement("CREATE TABLE `user_archive` ("));
+        assert!(filter.accepts_statement("SET NAMES utf8mb4;"));
+        filter.limits.start("user", 0);
+        assert!(!filter.accepts_statement("INSERT INTO `user` VALUES (1);"));
+        assert!(filter.accepts_statement("CREATE TABLE `user` ("));
+        assert!(TableFilter::default().accepts("anything"));
+
+        assert!("/[a-/".parse::<TablePattern>().is_err());
+        assert!("[a-".parse::<TablePattern>().is_err());
+    }
+
+    #[test]
+    fn statement_tables() {
+        assert_eq!(
+            statement_table("INSERT INTO `user` VALUES (1,2);"),
+            Some("user")
+        );
+        assert_eq!(
+            statement_table("INSERT INTO user VALUES (1,2);"),
+            Some("user")
+        );
+        assert_eq!(statement_table("CREATE TABLE `a b` ("), Some("a b"));
+        assert_eq!(
+            statement_table("CREATE TABLE IF NOT EXISTS user(id int);"),
+            Some("user")
+        );
+        assert_eq!(statement_table("LOCK TABLES `user` WRITE;"), None);
+    }
+
+    #[test]
+    fn project_columns() {
+        let Line::CreateTable(_, schema) =
+            parse_line("CREATE TABLE user (id INT, email TEXT, password TEXT)").unwrap()
+        else {
+            panic!("Not a CREATE TABLE statement");
+        };
+        let names = |include: &[&str], exclude: &[&str]| {
+            let include: Vec<String> = include.iter().map(|c| c.to_string()).collect();
+            let exclude: Vec<String> = exclude.iter().map(|c| c.to_string()).collect();
+            ColumnProjection::new("user", &schema, &include, &exclude).map(|projection| {
+                let mut schema = schema.clone();
+                if let Some(projection) = projection {
+                    projection.schema(&mut schema);
+                }
+                schema
+                    .columns
+                    .into_iter()
+                    .map(|c| c.column_name)
+                    .collect::<Vec<_>>()
+            })
+        };
+        assert_eq!(names(&[], &[]).unwrap(), ["id", "email", "password"]);
+        assert_eq!(names(&["password", "ID"], &[]).unwrap(), ["id", "password"]);
+        assert_eq!(names(&[], &["password"]).unwrap(), ["id", "email"]);
+        assert_eq!(names(&["id", "email"], &["email"]).unwrap(), ["id"]);
+        assert!(names(&["name"], &[]).is_err());
+        assert!(names(&[], &["id", "email", "password"]).is_err());
+
+        let projection = ColumnProjection::new("user", &schema, &[], &["email".into()])
+            .unwrap()
+            .unwrap();
+        let mut rows = vec![vec![
+            ColumnValue::Integer(1),
+            ColumnValue::String("a@b.c".into()),
+            ColumnValue::Null,
+        ]];
+        projection.rows(&mut rows);
+        assert_eq!(rows, [[ColumnValue::Integer(1), ColumnValue::Null]]);
+    }
+}
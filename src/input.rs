@@ -0,0 +1,216 @@
+//! Input opening and decompression.
+
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Cursor, Read},
+};
+
+use bzip2::read::MultiBzDecoder;
+use clap::ValueEnum;
+use color_eyre::eyre::{bail, Context, Result};
+use flate2::read::MultiGzDecoder;
+use xz2::read::XzDecoder;
+
+const BUFFER_CAPACITY: usize = 8192 * 1000;
+
+/// Compression of an input file
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Compression {
+    /// Detect compression from the first bytes of the input
+    Auto,
+    /// Plain SQL
+    None,
+    Gzip,
+    Zstd,
+    Xz,
+    Bzip2,
+    Lz4,
+}
+
+impl Compression {
+    /// Guess compression from magic bytes, falling back to plain text.
+    fn sniff(header: &[u8]) -> Compression {
+        if header.starts_with(&[0x1f, 0x8b]) {
+            Compression::Gzip
+        } else if header.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Compression::Zstd
+        } else if header.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Compression::Xz
+        } else if header.starts_with(b"BZh") {
+            Compression::Bzip2
+        } else if header.starts_with(&[0x04, 0x22, 0x4d, 0x18]) {
+            Compression::Lz4
+        } else {
+            Compression::None
+        }
+    }
+}
+
+/// Expand glob patterns into file names, keeping command line order.
+///
+/// Files matching a pattern are sorted lexicographically, so zero padded chunk numbers
+/// such as `db.table.00001.sql.gz` are processed in order.
+pub fn expand(patterns: &[String]) -> Result<Vec<String>> {
+    let mut files = Vec::new();
+    for pattern in patterns {
+        if !pattern.contains(['*', '?', '[']) {
+            files.push(pattern.clone());
+            continue;
+        }
+        let mut matches = glob::glob(pattern)
+            .with_context(|| format!("Invalid glob pattern {pattern}"))?
+            .map(|path| Ok(path?.to_string_lossy().into_owned()))
+            .collect::<Result<Vec<_>>>()?;
+        if matches.is_empty() {
+            bail!("No input file matches {pattern}");
+        }
+        matches.sort();
+        files.extend(matches);
+    }
+    Ok(files)
+}
+
+/// Open `file`, or stdin when `None`, decompressing it if needed.
+pub fn open(file: Option<&str>, compression: Compression) -> Result<Box<dyn BufRead>> {
+    match file {
+        Some(file) => decompress(
+            File::open(file).with_context(|| format!("Cannot open {file}"))?,
+            compression,
+        )
+        .with_context(|| format!("Cannot read {file}")),
+        None => decompress(io::stdin().lock(), compression).context("Cannot read stdin"),
+    }
+}
+
+/// Wrap `reader` in the decoder matching `compression`.
+pub fn decompress<R: Read + 'static>(
+    mut reader: R,
+    compression: Compression,
+) -> Result<Box<dyn BufRead>> {
+    // read the magic bytes ahead and chain them back in front of the stream:
+    // stdin is not seekable and may return short reads.
+    let mut header = Vec::with_capacity(6);
+    (&mut reader).take(6).read_to_end(&mut header)?;
+    let compression = match compression {
+        Compression::Auto => Compression::sniff(&header),
+        compression => compression,
+    };
+    let reader = Cursor::new(header).chain(reader);
+    Ok(match compression {
+        Compression::Auto | Compression::None => {
+            Box::new(BufReader::with_capacity(BUFFER_CAPACITY, reader))
+        }
+        Compression::Gzip => Box::new(BufReader::with_capacity(
+            BUFFER_CAPACITY,
+            MultiGzDecoder::new(reader),
+        )),
+        Compression::Zstd => Box::new(BufReader::with_capacity(
+            BUFFER_CAPACITY,
+            zstd::Decoder::new(reader)?,
+        )),
+        Compression::Xz => Box::new(BufReader::with_capacity(
+            BUFFER_CAPACITY,
+            XzDecoder::new_multi_decoder(reader),
+        )),
+        Compression::Bzip2 => Box::new(BufReader::with_capacity(
+            BUFFER_CAPACITY,
+            MultiBzDecoder::new(reader),
+        )),
+        Compression::Lz4 => Box::new(BufReader::with_capacity(
+            BUFFER_CAPACITY,
+            lz4_flex::frame::FrameDecoder::new(reader),
+        )),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::testing::TempDir;
+    use std::{
+        fs::File,
+        io::{Cursor, Read, Write},
+    };
+
+    use super::{decompress, expand, Compression};
+
+    const SQL: &str = "INSERT INTO `user` VALUES (1,'foo');\n";
+
+    fn read_all(data: Vec<u8>, compression: Compression) -> String {
+        let mut ret = String::new();
+        decompress(Cursor::new(data), compression)
+            .unwrap()
+            .read_to_string(&mut ret)
+            .unwrap();
+        ret
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn sniff_compression() {
+        let mut xz = xz2::write::XzEncoder::new(Vec::new(), 6);
+        xz.write_all(SQL.as_bytes()).unwrap();
+        let mut bzip2 = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+        bzip2.write_all(SQL.as_bytes()).unwrap();
+        let mut lz4 = lz4_flex::frame::FrameEncoder::new(Vec::new());
+        lz4.write_all(SQL.as_bytes()).unwrap();
+
+        for data in [
+            SQL.as_bytes().to_vec(),
+            gzip(SQL.as_bytes()),
+            zstd::encode_all(SQL.as_bytes(), 3).unwrap(),
+            xz.finish().unwrap(),
+            bzip2.finish().unwrap(),
+            lz4.finish().unwrap(),
+        ] {
+            assert_eq!(read_all(data, Compression::Auto), SQL);
+        }
+    }
+
+    #[test]
+    fn multi_member_gzip() {
+        let mut data = gzip(SQL.as_bytes());
+        data.extend(gzip(SQL.as_bytes()));
+        assert_eq!(read_all(data, Compression::Auto), SQL.repeat(2));
+    }
+
+    #[test]
+    fn explicit_compression() {
+        // shorter than the magic bytes
+        assert_eq!(read_all(b"--".to_vec(), Compression::None), "--");
+        assert_eq!(read_all(gzip(b"").to_vec(), Compression::Gzip), "");
+        assert_eq!(
+            read_all(gzip(SQL.as_bytes()), Compression::Gzip),
+            SQL.to_string()
+        );
+    }
+
+    #[test]
+    fn expand_globs() {
+        let dir = TempDir::new("glob");
+        for name in [
+            "db.t.00002.sql",
+            "db.t.00000.sql",
+            "db.t.00001.sql",
+            "other.sql",
+        ] {
+            File::create(dir.join(name)).unwrap();
+        }
+        let dir = dir.to_string_lossy();
+        let files = expand(&[format!("{dir}/other.sql"), format!("{dir}/db.t.*.sql")]).unwrap();
+        assert_eq!(
+            files,
+            vec![
+                format!("{dir}/other.sql"),
+                format!("{dir}/db.t.00000.sql"),
+                format!("{dir}/db.t.00001.sql"),
+                format!("{dir}/db.t.00002.sql"),
+            ]
+        );
+        assert!(expand(&[format!("{dir}/nothing*.sql")]).is_err());
+    }
+}
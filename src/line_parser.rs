@@ -1,12 +1,17 @@
+use std::{borrow::Cow, collections::HashMap, fmt};
+
 use arrow::datatypes::{DataType, Field, SchemaBuilder, TimeUnit};
 use color_eyre::eyre::{bail, Context, OptionExt, Result};
 use sqlparser::{
-    ast::{Expr, SetExpr, UnaryOperator, Value},
+    ast::{ExactNumberInfo, Expr, SetExpr, UnaryOperator, Value},
     dialect::MySqlDialect,
     parser::Parser,
 };
 
+use crate::overrides::Cast;
+
 #[derive(Clone, Debug, PartialEq)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum Line {
     CreateTable(String, Schema),
     /// must be in the save order as the schema
@@ -15,37 +20,180 @@ pub enum Line {
     NOP,
 }
 
+/// Key-value metadata holding the `CREATE TABLE` statement of a table
+pub const CREATE_TABLE_KEY: &str = "mysql.create_table";
+/// Arrow field metadata: MySQL type as normalized by sqlparser, e.g.
+/// `DECIMAL(10,2) UNSIGNED`, see [`ColumnDef::mysql_type`]
+pub const TYPE_KEY: &str = "mysql.type";
+/// Arrow field metadata: `COMMENT` of the column
+pub const COMMENT_KEY: &str = "mysql.comment";
+/// Arrow field metadata: `DEFAULT` expression of the column, e.g. `'0'` or `NULL`
+pub const DEFAULT_KEY: &str = "mysql.default";
+/// Arrow field metadata: `primary` or `unique` when the column is part of such a key
+pub const KEY_KEY: &str = "mysql.key";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Schema {
+    pub columns: Vec<ColumnDef>,
+    /// original `CREATE TABLE` statement
+    pub create_table: String,
+    pub foreign_keys: Vec<ForeignKey>,
+    /// `UNIQUE KEY` and `KEY` indexes, the PRIMARY KEY being told by [`ColumnDef::key`]
+    pub indexes: Vec<Index>,
+    /// Columns of an unsupported type, parsed as strings until excluded: see
+    /// [`Schema::check_types`]
+    pub unsupported: Vec<String>,
+}
+
+impl Schema {
+    /// Fail with [`UnsupportedTypes`] when columns of an unsupported type are left, once
+    /// excluded columns are removed
+    pub fn check_types(&self, table_name: &str) -> Result<()> {
+        let columns: Vec<(String, String)> = self
+            .columns
+            .iter()
+            .filter(|column| self.unsupported.contains(&column.column_name))
+            .map(|column| (column.column_name.clone(), column.mysql_type.clone()))
+            .collect();
+        if columns.is_empty() {
+            return Ok(());
+        }
+        Err(UnsupportedTypes {
+            table_name: table_name.to_string(),
+            columns,
+        }
+        .into())
+    }
+}
+
+/// Secondary index of a table, `KEY name (columns)` or `UNIQUE KEY name (columns)`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Index {
+    pub name: Option<String>,
+    pub columns: Vec<String>,
+    pub unique: bool,
+}
+
+/// `FOREIGN KEY (columns) REFERENCES referenced_table (referenced_columns)`
 #[derive(Clone, Debug, PartialEq, Eq)]
-pub struct Schema(pub Vec<ColumnDef>);
+pub struct ForeignKey {
+    pub columns: Vec<String>,
+    pub referenced_table: String,
+    pub referenced_columns: Vec<String>,
+}
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct ColumnDef {
     pub column_name: String,
+    /// name of the output field, see [`crate::config::Config::name_columns`]
+    pub field_name: String,
     pub nullable: bool,
     pub column_type: ColumnType,
+    pub key: ColumnKey,
+    /// MySQL type as normalized by sqlparser, e.g. `VARCHAR(255)` for `varchar(255)`:
+    /// the `CHARACTER SET` and `COLLATE` options of the column are not part of it
+    pub mysql_type: String,
+    pub comment: Option<String>,
+    /// `DEFAULT` expression, as SQL
+    pub default: Option<String>,
+    /// conversion of the values to `column_type`, for columns whose type is overridden
+    pub cast: Option<Cast>,
+}
+
+impl ColumnDef {
+    /// Unit of the values of a timestamp column: seconds unless cast from epoch milli or
+    /// microseconds
+    pub fn time_unit(&self) -> TimeUnit {
+        self.cast.as_ref().map_or(TimeUnit::Second, Cast::time_unit)
+    }
+}
+
+/// Columns of a `CREATE TABLE` statement whose type is not supported, all listed rather
+/// than only the first one
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnsupportedTypes {
+    pub table_name: String,
+    /// name and MySQL type of the columns
+    pub columns: Vec<(String, String)>,
+}
+
+impl fmt::Display for UnsupportedTypes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Unsupported data types in table `{}`:", self.table_name)?;
+        for (i, (column_name, mysql_type)) in self.columns.iter().enumerate() {
+            let separator = if i == 0 { "" } else { "," };
+            write!(f, "{separator} `{column_name}` {mysql_type}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for UnsupportedTypes {}
+
+/// Membership of a column in a key of its table
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColumnKey {
+    #[default]
+    None,
+    /// part of the PRIMARY KEY
+    Primary,
+    /// part of a UNIQUE KEY
+    Unique,
 }
 
 impl Schema {
     pub fn to_arrow_schema(&self) -> arrow::datatypes::Schema {
         let mut builder = SchemaBuilder::new();
-        for ColumnDef {
-            column_name,
-            nullable,
-            column_type,
-        } in &self.0
-        {
+        for column in &self.columns {
+            let ColumnDef {
+                column_name: _,
+                field_name,
+                nullable,
+                column_type,
+                key,
+                mysql_type,
+                comment,
+                default,
+                cast: _,
+            } = column;
+            let time_unit = column.time_unit();
+            let mut metadata = HashMap::from([(TYPE_KEY.to_string(), mysql_type.clone())]);
+            if let Some(comment) = comment {
+                metadata.insert(COMMENT_KEY.to_string(), comment.clone());
+            }
+            if let Some(default) = default {
+                metadata.insert(DEFAULT_KEY.to_string(), default.clone());
+            }
+            match key {
+                ColumnKey::None => {}
+                ColumnKey::Primary => {
+                    metadata.insert(KEY_KEY.to_string(), "primary".to_string());
+                }
+                ColumnKey::Unique => {
+                    metadata.insert(KEY_KEY.to_string(), "unique".to_string());
+                }
+            }
             // TODO propagate the "NOT NULL" here!
-            builder.push(Field::new(
-                column_name.to_lowercase(),
-                match column_type {
-                    ColumnType::String => DataType::Utf8,
-                    ColumnType::Integer => DataType::Int64,
-                    ColumnType::Float => DataType::Float64,
-                    ColumnType::Timestamp => DataType::Timestamp(TimeUnit::Second, None),
-                    ColumnType::Boolean => todo!(),
-                },
-                *nullable,
-            ));
+            builder.push(
+                Field::new(
+                    field_name,
+                    match column_type {
+                        ColumnType::String => DataType::Utf8,
+                        ColumnType::Integer => DataType::Int64,
+                        ColumnType::Float => DataType::Float64,
+                        ColumnType::Timestamp => DataType::Timestamp(time_unit, None),
+                        ColumnType::Boolean => DataType::Boolean,
+                        ColumnType::Decimal(precision, scale) if *precision <= 38 => {
+                            DataType::Decimal128(*precision, *scale)
+                        }
+                        ColumnType::Decimal(precision, scale) => {
+                            DataType::Decimal256(*precision, *scale)
+                        }
+                    },
+                    *nullable,
+                )
+                .with_metadata(metadata),
+            );
         }
         builder.finish()
     }
@@ -62,6 +210,8 @@ pub enum ColumnType {
     Timestamp,
     /// BOOLEAN
     Boolean,
+    /// DECIMAL(precision, scale), NUMERIC
+    Decimal(u8, i8),
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -70,14 +220,111 @@ pub enum ColumnValue {
     /// INTEGER, BIGINT
     Integer(i64),
     Float(f64),
+    /// Number written with more digits than `Integer` or `Float` hold exactly,
+    /// kept as written (e.g. `DECIMAL(30, 4)` values)
+    Decimal(String),
     /// BOOLEAN
     Boolean(bool),
     Null,
 }
+/// Remove the prefix length of indexed columns, as in `KEY name_index (name(144))`,
+/// which sqlparser does not support
+fn cleanup_key(line: &str) -> Cow<'_, str> {
+    if line.contains("KEY ") {
+        let mut ret = String::new();
+        let mut depth = 0;
+        for c in line.chars() {
+            if c == '(' {
+                depth += 1;
+            }
+            if c == ')' {
+                depth -= 1;
+                if depth == 1 {
+                    continue;
+                }
+            }
+            if depth >= 2 {
+                continue;
+            }
+            ret.push(c);
+        }
+        ret.into()
+    } else {
+        line.into()
+    }
+}
+
+/// Value of a literal: number, possibly negative, string, boolean or `NULL`
+pub fn literal(value: &Expr) -> Result<ColumnValue> {
+    match value {
+        Expr::UnaryOp { op, expr } if *op == UnaryOperator::Minus => {
+            // case of negative numbers...
+            let Expr::Value(Value::Number(num, _)) = expr.as_ref() else {
+                bail!("Unknown expr with a minus operator {expr}")
+            };
+            number(&format!("-{num}"))
+        }
+        Expr::Value(value) => Ok(match value {
+            sqlparser::ast::Value::Number(num, _) => number(num)?,
+            sqlparser::ast::Value::SingleQuotedString(s) => ColumnValue::String(s.clone()),
+            sqlparser::ast::Value::Boolean(b) => ColumnValue::Boolean(*b),
+            sqlparser::ast::Value::Null => ColumnValue::Null,
+            _ => bail!("Unsupported syntax for value {value:?}"),
+        }),
+        _ => bail!("Unsupported value {value:?}"),
+    }
+}
+
+/// Value of a number literal: an `Integer` when it fits an `i64`, a `Float` when
+/// an `f64` holds its 15 significant digits or less, else its text as a `Decimal`
+fn number(num: &str) -> Result<ColumnValue> {
+    if !num.contains(['.', 'e', 'E']) {
+        if let Ok(integer) = num.parse() {
+            return Ok(ColumnValue::Integer(integer));
+        }
+    }
+    let digits = num.bytes().filter(u8::is_ascii_digit).count();
+    if digits <= 15 || num.contains(['e', 'E']) {
+        Ok(ColumnValue::Float(num.parse()?))
+    } else if num.parse::<f64>().is_ok() {
+        Ok(ColumnValue::Decimal(num.to_string()))
+    } else {
+        bail!("Invalid number {num}")
+    }
+}
+
+/// Type of a `DECIMAL(precision, scale)` column, MySQL defaulting to `DECIMAL(10, 0)`.
+/// `None` when the precision is too large for a 256 bits decimal.
+fn decimal_type(info: &ExactNumberInfo) -> Option<ColumnType> {
+    let (precision, scale) = match *info {
+        ExactNumberInfo::None => (10, 0),
+        ExactNumberInfo::Precision(precision) => (precision, 0),
+        ExactNumberInfo::PrecisionAndScale(precision, scale) => (precision, scale),
+    };
+    if precision == 0 || precision > 76 || scale > precision {
+        return None;
+    }
+    Some(ColumnType::Decimal(precision as u8, scale as i8))
+}
+
 pub fn parse_line(line: &str) -> Result<Line> {
     let dialect = MySqlDialect {};
     //println!("{line}");
-    let ast = Parser::parse_sql(&dialect, line)
+    let statement: Cow<str> = if line.starts_with("CREATE TABLE") {
+        // one column or key definition per line after the first one
+        let mut lines = line.lines();
+        lines
+            .next()
+            .map(Cow::from)
+            .into_iter()
+            .chain(lines.map(cleanup_key))
+            .collect::<Vec<_>>()
+            .join("\n")
+            .into()
+    } else {
+        line.into()
+    };
+    let ast = Parser::parse_sql(&dialect, &statement)
         .with_context(|| format!("Unable to parse line: {line}"))?;
 
     match ast.len() {
@@ -94,7 +341,7 @@ pub fn parse_line(line: &str) -> Result<Line> {
                     transient: _,
                     name,
                     columns,
-                    constraints: _,
+                    constraints,
                     hive_distribution: _,
                     hive_formats: _,
                     table_properties: _,
@@ -117,15 +364,22 @@ pub fn parse_line(line: &str) -> Result<Line> {
                 } => {
                     let table_name = name.0[0].value.clone();
                     let mut schema = Vec::new();
+                    let mut unsupported = Vec::new();
                     for column in columns {
                         let name = column.name.value.clone();
                         let column_type = match &column.data_type {
                             sqlparser::ast::DataType::Varchar(_) => ColumnType::String,
-                            sqlparser::ast::DataType::Numeric(_) => ColumnType::Integer,
-                            sqlparser::ast::DataType::Decimal(_) => ColumnType::Integer,
-                            sqlparser::ast::DataType::BigNumeric(_) => ColumnType::Integer,
-                            sqlparser::ast::DataType::BigDecimal(_) => ColumnType::Integer,
-                            sqlparser::ast::DataType::Dec(_) => ColumnType::Integer,
+                            sqlparser::ast::DataType::Numeric(info)
+                            | sqlparser::ast::DataType::Decimal(info)
+                            | sqlparser::ast::DataType::BigNumeric(info)
+                            | sqlparser::ast::DataType::BigDecimal(info)
+                            | sqlparser::ast::DataType::Dec(info) => match decimal_type(info) {
+                                Some(column_type) => column_type,
+                                None => {
+                                    unsupported.push(name.clone());
+                                    ColumnType::String
+                                }
+                            },
                             sqlparser::ast::DataType::Float(_) => ColumnType::Float,
                             // should we treat tinyint(1) as boolean?
                             sqlparser::ast::DataType::TinyInt(_) => ColumnType::Integer,
@@ -162,17 +416,24 @@ pub fn parse_line(line: &str) -> Result<Line> {
                             sqlparser::ast::DataType::Text => ColumnType::String,
                             sqlparser::ast::DataType::String(_) => ColumnType::String,
                             sqlparser::ast::DataType::Enum(_) => ColumnType::String,
-                            sqlparser::ast::DataType::Custom(name, _) => {
-                                let type_name = name.0[0].value.as_str();
+                            sqlparser::ast::DataType::Custom(type_name, _) => {
+                                let type_name = type_name.0[0].value.as_str();
                                 match type_name {
                                     "longtext" => ColumnType::String,
                                     "mediumtext" => ColumnType::String,
-                                    _ => bail!("Unsupported data type {:?}", column.data_type),
+                                    _ => {
+                                        unsupported.push(name.clone());
+                                        ColumnType::String
+                                    }
                                 }
                             }
-                            _ => bail!("Unsupported data type {:?}", column.data_type),
+                            _ => {
+                                unsupported.push(name.clone());
+                                ColumnType::String
+                            }
                         };
                         schema.push(ColumnDef {
+                            field_name: name.to_lowercase(),
                             column_name: name,
                             nullable: column
                                 .options
@@ -180,22 +441,131 @@ pub fn parse_line(line: &str) -> Result<Line> {
                                 .map(|column_option| match column_option.option {
                                     sqlparser::ast::ColumnOption::Null => Some(true),
                                     sqlparser::ast::ColumnOption::NotNull => Some(false),
-                                    sqlparser::ast::ColumnOption::Unique { is_primary }
-                                        if is_primary == true =>
-                                    {
+                                    sqlparser::ast::ColumnOption::Unique { is_primary: true } => {
                                         Some(false)
                                     }
                                     _ => None,
                                 })
-                                .filter(Option::is_some)
-                                .next()
+                                .find(Option::is_some)
                                 .flatten()
                                 .unwrap_or(true),
                             column_type,
+                            mysql_type: column.data_type.to_string(),
+                            comment: column.options.iter().find_map(|column_option| {
+                                match &column_option.option {
+                                    sqlparser::ast::ColumnOption::Comment(comment) => {
+                                        Some(comment.clone())
+                                    }
+                                    _ => None,
+                                }
+                            }),
+                            default: column.options.iter().find_map(|column_option| {
+                                match &column_option.option {
+                                    sqlparser::ast::ColumnOption::Default(expr) => {
+                                        Some(expr.to_string())
+                                    }
+                                    _ => None,
+                                }
+                            }),
+                            key: column
+                                .options
+                                .iter()
+                                .find_map(|column_option| match column_option.option {
+                                    sqlparser::ast::ColumnOption::Unique { is_primary } => {
+                                        Some(if is_primary {
+                                            ColumnKey::Primary
+                                        } else {
+                                            ColumnKey::Unique
+                                        })
+                                    }
+                                    _ => None,
+                                })
+                                .unwrap_or_default(),
+                            cast: None,
                         });
                     }
+                    let mut foreign_keys = Vec::new();
+                    // column `UNIQUE` options
+                    let mut indexes: Vec<Index> = columns
+                        .iter()
+                        .filter(|column| {
+                            column.options.iter().any(|column_option| {
+                                matches!(
+                                    column_option.option,
+                                    sqlparser::ast::ColumnOption::Unique { is_primary: false }
+                                )
+                            })
+                        })
+                        .map(|column| Index {
+                            name: None,
+                            columns: vec![column.name.value.clone()],
+                            unique: true,
+                        })
+                        .collect();
+                    for constraint in constraints {
+                        if let sqlparser::ast::TableConstraint::ForeignKey {
+                            columns,
+                            foreign_table,
+                            referred_columns,
+                            ..
+                        } = constraint
+                        {
+                            foreign_keys.push(ForeignKey {
+                                columns: columns.iter().map(|c| c.value.clone()).collect(),
+                                referenced_table: foreign_table.0.last().unwrap().value.clone(),
+                                referenced_columns: referred_columns
+                                    .iter()
+                                    .map(|c| c.value.clone())
+                                    .collect(),
+                            });
+                        } else if let sqlparser::ast::TableConstraint::Unique {
+                            name,
+                            columns,
+                            is_primary,
+                        } = constraint
+                        {
+                            for column in columns {
+                                let Some(column) = schema
+                                    .iter_mut()
+                                    .find(|c: &&mut ColumnDef| c.column_name == column.value)
+                                else {
+                                    continue;
+                                };
+                                if *is_primary {
+                                    column.key = ColumnKey::Primary;
+                                } else if column.key == ColumnKey::None {
+                                    column.key = ColumnKey::Unique;
+                                }
+                            }
+                            if !*is_primary {
+                                indexes.push(Index {
+                                    name: name.as_ref().map(|name| name.value.clone()),
+                                    columns: columns.iter().map(|c| c.value.clone()).collect(),
+                                    unique: true,
+                                });
+                            }
+                        } else if let sqlparser::ast::TableConstraint::Index {
+                            name, columns, ..
+                        } = constraint
+                        {
+                            indexes.push(Index {
+                                name: name.as_ref().map(|name| name.value.clone()),
+                                columns: columns.iter().map(|c| c.value.clone()).collect(),
+                                unique: false,
+                            });
+                        }
+                    }
 
-                    Ok(Line::CreateTable(table_name, Schema(schema)))
+                    Ok(Line::CreateTable(
+                        table_name,
+                        Schema {
+                            columns: schema,
+                            create_table: line.to_string(),
+                            foreign_keys,
+                            indexes,
+                            unsupported,
+                        },
+                    ))
                 }
                 sqlparser::ast::Statement::Insert {
                     or: _,
@@ -223,46 +593,7 @@ pub fn parse_line(line: &str) -> Result<Line> {
                     if let SetExpr::Values(values) = source.body.as_ref() {
                         let mut rows = Vec::new();
                         for values in &values.rows {
-                            let mut row_values = Vec::new();
-                            for value in values {
-                                match value {
-                                    Expr::UnaryOp { op, expr } if *op == UnaryOperator::Minus => {
-                                        // case of negative numbers...
-                                        let Expr::Value(Value::Number(num, _)) = expr.as_ref()
-                                        else {
-                                            bail!("Unknown expr with a minus operator {expr}")
-                                        };
-                                        if num.contains('.') {
-                                            row_values.push(ColumnValue::Float(-num.parse()?));
-                                        } else {
-                                            row_values.push(ColumnValue::Integer(-num.parse()?));
-                                        }
-                                    }
-                                    Expr::Value(value) => {
-                                        let value = match value {
-                                            sqlparser::ast::Value::Number(num, _) => {
-                                                if num.contains('.') {
-                                                    ColumnValue::Float(num.parse()?)
-                                                } else {
-                                                    ColumnValue::Integer(num.parse()?)
-                                                }
-                                            }
-                                            sqlparser::ast::Value::SingleQuotedString(s) => {
-                                                ColumnValue::String(s.clone())
-                                            }
-                                            sqlparser::ast::Value::Boolean(b) => {
-                                                ColumnValue::Boolean(*b)
-                                            }
-                                            sqlparser::ast::Value::Null => ColumnValue::Null,
-                                            _ => bail!("Unsupported syntax for value {value:?}"),
-                                        };
-                                        row_values.push(value);
-                                    }
-                                    _ => {
-                                        bail!("Unsupported value {value:?}");
-                                    }
-                                }
-                            }
+                            let row_values = values.iter().map(literal).collect::<Result<_>>()?;
                             rows.push(row_values);
                         }
                         Ok(Line::InsertInto(table_name, rows))
@@ -281,9 +612,12 @@ pub fn parse_line(line: &str) -> Result<Line> {
 #[cfg(test)]
 mod test {
 
-    use crate::line_parser::{ColumnDef, ColumnType, ColumnValue};
+    use crate::line_parser::{ColumnDef, ColumnKey, ColumnType, ColumnValue};
 
-    use super::{parse_line, Line};
+    use super::{
+        cleanup_key, parse_line, ForeignKey, Index, Line, UnsupportedTypes, COMMENT_KEY,
+        DEFAULT_KEY, KEY_KEY, TYPE_KEY,
+    };
     #[test]
     fn parse_insert_into() {
         let stmt="INSERT INTO `user` VALUES (1, 'foobar', NULL, '2012-01-02 12:55:22', 0),(1, 'foobar', NULL, '2012-01-02 12:55:22', 0),(1, 'foobar', NULL, '2012-01-02 12:55:22', 0),(1, 'foobar', NULL, '2012-01-02 12:55:22', -123);";
@@ -352,42 +686,84 @@ mod test {
         if let Line::CreateTable(name, schema) = line {
             assert_eq!("user", name);
             assert_eq!(
-                schema.0,
+                schema.columns,
                 vec![
                     ColumnDef {
                         column_name: "id".into(),
+                        field_name: "id".into(),
                         nullable: false,
                         column_type: ColumnType::Integer,
+                        key: ColumnKey::None,
+                        mysql_type: "BIGINT".into(),
+                        comment: None,
+                        default: None,
+                        cast: None,
                     },
                     ColumnDef {
                         column_name: "shortName".into(),
+                        field_name: "shortname".into(),
                         nullable: false,
                         column_type: ColumnType::String,
+                        key: ColumnKey::None,
+                        mysql_type: "VARCHAR(255)".into(),
+                        comment: None,
+                        default: None,
+                        cast: None,
                     },
                     ColumnDef {
                         column_name: "avatarUuid".into(),
+                        field_name: "avataruuid".into(),
                         nullable: true,
                         column_type: ColumnType::String,
+                        key: ColumnKey::None,
+                        mysql_type: "VARCHAR(36)".into(),
+                        comment: None,
+                        default: Some("NULL".into()),
+                        cast: None,
                     },
                     ColumnDef {
                         column_name: "registrationDate".into(),
+                        field_name: "registrationdate".into(),
                         nullable: false,
                         column_type: ColumnType::Timestamp,
+                        key: ColumnKey::None,
+                        mysql_type: "TIMESTAMP".into(),
+                        comment: None,
+                        default: Some("CURRENT_TIMESTAMP".into()),
+                        cast: None,
                     },
                     ColumnDef {
                         column_name: "premiumExpirationDate".into(),
+                        field_name: "premiumexpirationdate".into(),
                         nullable: true,
                         column_type: ColumnType::Timestamp,
+                        key: ColumnKey::None,
+                        mysql_type: "TIMESTAMP".into(),
+                        comment: None,
+                        default: Some("NULL".into()),
+                        cast: None,
                     },
                     ColumnDef {
                         column_name: "excluded".into(),
+                        field_name: "excluded".into(),
                         nullable: false,
                         column_type: ColumnType::Integer,
+                        key: ColumnKey::None,
+                        mysql_type: "TINYINT(1)".into(),
+                        comment: None,
+                        default: Some("'0'".into()),
+                        cast: None,
                     },
                     ColumnDef {
                         column_name: "company_lid".into(),
+                        field_name: "company_lid".into(),
                         nullable: true,
                         column_type: ColumnType::Integer,
+                        key: ColumnKey::None,
+                        mysql_type: "BIGINT".into(),
+                        comment: None,
+                        default: Some("NULL".into()),
+                        cast: None,
                     },
                 ]
             )
@@ -395,4 +771,215 @@ mod test {
             panic!("{line:?} is not create table!");
         }
     }
+
+    #[test]
+    fn unsupported_types() {
+        let Line::CreateTable(table_name, schema) = parse_line(
+            "CREATE TABLE `shape` (`id` INT, `area` GEOMETRY, `name` TEXT, `data` JSON)",
+        )
+        .unwrap() else {
+            panic!("not a CREATE TABLE");
+        };
+        assert_eq!(schema.columns.len(), 4);
+        let error = schema.check_types(&table_name).unwrap_err();
+        let unsupported = error.downcast_ref::<UnsupportedTypes>().unwrap();
+        assert_eq!(
+            unsupported.columns,
+            [
+                ("area".to_string(), "GEOMETRY".to_string()),
+                ("data".to_string(), "JSON".to_string())
+            ]
+        );
+        assert_eq!(
+            unsupported.to_string(),
+            "Unsupported data types in table `shape`: `area` GEOMETRY, `data` JSON"
+        );
+    }
+
+    #[test]
+    fn decimal_types() {
+        let Line::CreateTable(_, schema) = parse_line(
+            "CREATE TABLE `price` (`a` DECIMAL, `b` DECIMAL(12), `c` NUMERIC(10,2), `d` DECIMAL(65,30))",
+        )
+        .unwrap() else {
+            panic!("not a CREATE TABLE");
+        };
+        let types: Vec<_> = schema.columns.iter().map(|c| &c.column_type).collect();
+        assert_eq!(
+            types,
+            [
+                &ColumnType::Decimal(10, 0),
+                &ColumnType::Decimal(12, 0),
+                &ColumnType::Decimal(10, 2),
+                &ColumnType::Decimal(65, 30)
+            ]
+        );
+        let Line::CreateTable(_, schema) =
+            parse_line("CREATE TABLE `price` (`a` DECIMAL(80,2))").unwrap()
+        else {
+            panic!("not a CREATE TABLE");
+        };
+        assert_eq!(schema.unsupported, ["a"]);
+
+        // numbers with more digits than a float holds are kept as written
+        let Line::InsertInto(_, rows) = parse_line(
+            "INSERT INTO `price` VALUES (12.5,-12345678901234567890.1234,99999999999999999999)",
+        )
+        .unwrap() else {
+            panic!("not an INSERT INTO");
+        };
+        assert_eq!(
+            rows[0],
+            [
+                ColumnValue::Float(12.5),
+                ColumnValue::Decimal("-12345678901234567890.1234".into()),
+                ColumnValue::Decimal("99999999999999999999".into())
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_keys() {
+        let stmt = r#"CREATE TABLE `user` (
+            `id` bigint NOT NULL,
+            `tenant` bigint NOT NULL,
+            `email` varchar(255) NOT NULL,
+            `tel` varchar(32) UNIQUE,
+            `name` varchar(255),
+            PRIMARY KEY (`tenant`,`id`),
+            UNIQUE KEY `email_index` (`email`),
+            KEY `name_index` (`name`)
+          ) ENGINE=InnoDB;"#;
+        let Line::CreateTable(_, schema) = parse_line(stmt).unwrap() else {
+            panic!("not create table!");
+        };
+        assert_eq!(
+            schema.columns.iter().map(|c| c.key).collect::<Vec<_>>(),
+            vec![
+                ColumnKey::Primary,
+                ColumnKey::Primary,
+                ColumnKey::Unique,
+                ColumnKey::Unique,
+                ColumnKey::None
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_foreign_keys() {
+        let stmt = r#"CREATE TABLE `order_line` (
+            `order_id` bigint NOT NULL,
+            `tenant` bigint NOT NULL,
+            `product_id` bigint,
+            KEY `product_index` (`product_id`),
+            CONSTRAINT `order_fk` FOREIGN KEY (`tenant`, `order_id`) REFERENCES `order` (`tenant`, `id`) ON DELETE CASCADE,
+            CONSTRAINT `product_fk` FOREIGN KEY (`product_id`) REFERENCES `product` (`id`)
+          ) ENGINE=InnoDB;"#;
+        let Line::CreateTable(_, schema) = parse_line(stmt).unwrap() else {
+            panic!("not create table!");
+        };
+        assert_eq!(
+            schema.foreign_keys,
+            [
+                ForeignKey {
+                    columns: vec!["tenant".into(), "order_id".into()],
+                    referenced_table: "order".into(),
+                    referenced_columns: vec!["tenant".into(), "id".into()],
+                },
+                ForeignKey {
+                    columns: vec!["product_id".into()],
+                    referenced_table: "product".into(),
+                    referenced_columns: vec!["id".into()],
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_indexes() {
+        let stmt = r#"CREATE TABLE `user` (
+            `id` bigint NOT NULL,
+            `email` varchar(255) UNIQUE,
+            `tenant` bigint NOT NULL,
+            `name` varchar(255),
+            PRIMARY KEY (`id`),
+            UNIQUE KEY `tenant_name` (`tenant`, `name`),
+            KEY `name_index` (`name`)
+          ) ENGINE=InnoDB;"#;
+        let Line::CreateTable(_, schema) = parse_line(stmt).unwrap() else {
+            panic!("not create table!");
+        };
+        assert_eq!(
+            schema.indexes,
+            [
+                Index {
+                    name: None,
+                    columns: vec!["email".into()],
+                    unique: true,
+                },
+                Index {
+                    name: Some("tenant_name".into()),
+                    columns: vec!["tenant".into(), "name".into()],
+                    unique: true,
+                },
+                Index {
+                    name: Some("name_index".into()),
+                    columns: vec!["name".into()],
+                    unique: false,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn column_metadata() {
+        let stmt = "CREATE TABLE `user` (\n  `id` bigint NOT NULL COMMENT 'user id',\n  `name` varchar(255) DEFAULT 'anonymous',\n  PRIMARY KEY (`id`),\n  KEY `name_index` (`name`(16))\n) ENGINE=InnoDB;";
+        let Line::CreateTable(_, schema) = parse_line(stmt).unwrap() else {
+            panic!("not create table!");
+        };
+        // kept as is, prefix lengths included
+        assert_eq!(schema.create_table, stmt);
+        let arrow_schema = schema.to_arrow_schema();
+        let id = arrow_schema.field(0).metadata();
+        assert_eq!(id[TYPE_KEY], "BIGINT");
+        assert_eq!(id[COMMENT_KEY], "user id");
+        assert_eq!(id[KEY_KEY], "primary");
+        assert!(!id.contains_key(DEFAULT_KEY));
+        let name = arrow_schema.field(1).metadata();
+        assert_eq!(name[TYPE_KEY], "VARCHAR(255)");
+        assert_eq!(name[DEFAULT_KEY], "'anonymous'");
+        assert!(!name.contains_key(KEY_KEY));
+    }
+
+    #[test]
+    fn cleanup() {
+        assert_eq!(
+            cleanup_key("KEY `facebookConnectId_index` (`facebookConnectId`)"),
+            "KEY `facebookConnectId_index` (`facebookConnectId`)"
+        );
+        assert_eq!(
+            cleanup_key("KEY `facebookConnectId_index` (`facebookConnectId`(144))"),
+            "KEY `facebookConnectId_index` (`facebookConnectId`)"
+        );
+        assert_eq!(
+            cleanup_key("KEY `facebookConnectId_index` (`facebookConnectId`(144),`plop`)"),
+            "KEY `facebookConnectId_index` (`facebookConnectId`,`plop`)"
+        );
+        assert_eq!(
+            cleanup_key("KEY `facebookConnectId_index` (`facebookConnectId`(144),`plop`(12))"),
+            "KEY `facebookConnectId_index` (`facebookConnectId`,`plop`)"
+        );
+        assert_eq!(
+            cleanup_key("KEY `facebookConnectId_index` (`facebookConnectId`,`plop`(12))"),
+            "KEY `facebookConnectId_index` (`facebookConnectId`,`plop`)"
+        );
+        assert_eq!(
+            cleanup_key("FOREIGN KEY (`facebookConnectId`)"),
+            "FOREIGN KEY (`facebookConnectId`)"
+        );
+        assert_eq!(
+            cleanup_key("FOREIGN KEY (`facebookConnectId`(144))"),
+            "FOREIGN KEY (`facebookConnectId`)"
+        );
+    }
 }
@@ -1,19 +1,49 @@
 use std::{
-    borrow::Cow,
-    fs::{create_dir_all, File},
-    io::{self, BufRead, BufReader},
-    path::PathBuf,
+    fs::create_dir_all,
+    io::BufRead,
+    path::{Path, PathBuf},
+    sync::Arc,
 };
 
-use clap::Parser;
-use color_eyre::eyre::{Context, Result};
-use flate2::read::GzDecoder;
+use clap::{Parser, Subcommand, ValueEnum};
+use color_eyre::eyre::{bail, eyre, Context, Result};
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 
-use crate::parquet_writer::ParquetWriter;
+use crate::{
+    config::Config,
+    filter::{TableFilter, TablePattern},
+    input::Compression,
+    line_parser::{Line, Schema},
+    naming::ColumnCase,
+    overrides::SchemaOverrides,
+    parquet_writer::{ParquetWriter, WriterSettings},
+    partition::PartitionBy,
+    sample::{RowLimits, SampleSize},
+    sink::{Format, TextOptions},
+};
 
+mod anonymize;
+mod config;
+mod filter;
+mod input;
 mod line_parser;
+mod manifest;
+mod memory;
+mod mydumper;
+mod naming;
+mod overrides;
 mod parquet_writer;
+mod partition;
+mod predicate;
+mod profile;
+mod sample;
+mod schema_export;
+mod sink;
+mod sort;
+mod subset;
+mod tab;
+#[cfg(test)]
+mod testing;
 
 #[cfg(not(target_env = "msvc"))]
 use jemallocator::Jemalloc;
@@ -25,40 +55,245 @@ static GLOBAL: Jemalloc = Jemalloc;
 /// Parse MYSQL dump and write tables to parquet files
 #[derive(Parser)]
 struct Opts {
-    /// Output directory
+    /// Output directory, or database file with `--format sqlite`
     #[clap(short, long, default_value("."))]
     output: String,
-    /// Input statement from this file instead of stdin (.sql or .sql.gz)
-    input: Option<String>,
+    /// Output format [default: parquet]
+    #[clap(long, value_enum)]
+    format: Option<Format>,
+    /// Input statements from these files or glob patterns instead of stdin (.sql, possibly
+    /// compressed).
+    ///
+    /// Files are processed in command line order, files matching a glob pattern in
+    /// lexicographic order. A table may span several files.
+    #[clap(global = true)]
+    inputs: Vec<String>,
+    /// Number of input files processed in parallel.
+    ///
+    /// With more than one job, each file must only contain complete statements.
+    #[clap(short, long, default_value_t = 1)]
+    jobs: usize,
+    /// Layout of the input
+    #[clap(long, value_enum, default_value_t = InputFormat::Sql)]
+    input_format: InputFormat,
+    /// `FIELDS TERMINATED BY` option of the tab input format (escapes such as `\t` allowed)
+    #[clap(long, default_value("\\t"), value_parser = tab::parse_field_option)]
+    fields_terminated_by: String,
+    /// `FIELDS ENCLOSED BY` option of the tab input format
+    #[clap(long)]
+    fields_enclosed_by: Option<char>,
+    /// Compression of the input, detected from its first bytes by default
+    #[clap(global = true, long, value_enum, default_value_t = input::Compression::Auto)]
+    input_compression: input::Compression,
+    /// Approximate memory budget (e.g. 512M, 4G).
+    ///
+    /// A quarter is used for SQL statements waiting to be parsed, a quarter for parsed
+    /// rows waiting to be written and the remaining half for row groups buffered by the
+    /// parquet writer, which are flushed when they come close to this limit. Rows kept
+    /// for a `--sample` of a number of rows and the keys collected for `--subset` are
+    /// not counted.
+    #[clap(long, default_value("4G"), value_parser = memory::parse_byte_size)]
+    max_memory: usize,
+    /// TOML configuration file with per table and per column settings
+    #[clap(global = true, long)]
+    config: Option<PathBuf>,
+    /// TOML or YAML file overriding the output type of some columns, with an optional
+    /// cast such as `epoch_millis` or a date format, e.g. `[user.created_at]`
+    /// `type = "timestamp"` `cast = "epoch_millis"`
+    #[clap(global = true, long)]
+    schema_overrides: Option<PathBuf>,
+    /// Sort a table by some columns (`table:column1,column2`), spilling to disk when it
+    /// does not fit in memory. Can be repeated.
+    #[clap(long, value_parser = config::parse_table_columns)]
+    sort_by: Vec<(String, Vec<String>)>,
+    /// Write a table as a Hive-style partitioned dataset (`table:column` or
+    /// `table:column:year|month|day|hour`), e.g. `orders/created_at_month=2024-05/`.
+    /// Can be repeated.
+    #[clap(long, value_parser = partition::parse_table_partition)]
+    partition_by: Vec<(String, PartitionBy)>,
+    /// Keep partition columns in the parquet files of partitioned tables
+    #[clap(long)]
+    keep_partition_columns: bool,
+    /// Partitions written at the same time per table; the least recently used one is
+    /// closed when a new one is needed and continued in a new file if reopened
+    /// [default: 64]
+    #[clap(long)]
+    max_open_partitions: Option<usize>,
+    /// Index SQLite tables once loaded: their PRIMARY KEY, UNIQUE KEY and KEY indexes
+    #[clap(long)]
+    create_indexes: bool,
+    /// Case of output column and table names, table names being also made safe to use
+    /// as file names. Columns can be renamed in the configuration file [default: lower]
+    #[clap(long, value_enum)]
+    column_case: Option<ColumnCase>,
+    /// Only convert tables matching this glob pattern (`user*`) or, between slashes, this
+    /// regular expression (`/^log_\d+$/`). Can be repeated.
+    #[clap(global = true, long = "tables", value_name = "PATTERN")]
+    tables: Vec<TablePattern>,
+    /// Skip tables matching this glob pattern or regular expression. Can be repeated.
+    #[clap(global = true, long = "exclude-tables", value_name = "PATTERN")]
+    exclude_tables: Vec<TablePattern>,
+    /// Only keep some columns of a table (`table:column1,column2`). Can be repeated.
+    #[clap(global = true, long, value_parser = config::parse_table_columns)]
+    columns: Vec<(String, Vec<String>)>,
+    /// Drop some columns of a table (`table:column1,column2`). Can be repeated.
+    #[clap(global = true, long, value_parser = config::parse_table_columns)]
+    exclude_columns: Vec<(String, Vec<String>)>,
+    /// Only keep the rows of a table matching a SQL condition
+    /// (`"orders: created_at >= '2024-01-01' AND status != 'deleted'"`): comparisons,
+    /// `IN`, `BETWEEN`, `LIKE`, `IS [NOT] NULL`, `AND`, `OR` and `NOT`. Can be repeated.
+    #[clap(global = true, long = "where", value_name = "TABLE: CONDITION", value_parser = predicate::parse_table_condition, conflicts_with = "subset")]
+    where_clauses: Vec<(String, String)>,
+    /// Only keep a deterministic sample of the rows, on a hash of their PRIMARY KEY so
+    /// that tables sharing the key keep the same keys: a percentage of the rows of every
+    /// table (`1%`), or of a table (`users:5%`), or a number of rows of a table
+    /// (`users:10000`). Can be repeated.
+    #[clap(global = true, long, value_name = "[TABLE:]SIZE", value_parser = sample::parse_sample, conflicts_with = "subset")]
+    sample: Vec<(Option<String>, SampleSize)>,
+    /// Write at most this many rows per table, skipping the remaining INSERT statements
+    /// of a table once reached
+    #[clap(global = true, long, conflicts_with = "subset")]
+    limit: Option<usize>,
+    /// Only convert a referentially consistent slice of the dump: the rows of a table
+    /// matching a SQL condition (`"company: id IN (1,2,3)"`), the rows referencing them
+    /// through FOREIGN KEYs, recursively, and the rows these reference. Reads the dump
+    /// twice. Cannot be combined with `--where`, `--sample` nor `--limit`.
+    #[clap(global = true, long, value_name = "TABLE: CONDITION", value_parser = predicate::parse_table_condition)]
+    subset: Option<(String, String)>,
+    /// Only write the schema of every table to `{table}.schema.json`, with its MySQL and
+    /// Arrow types, nullability, keys and comments, skipping INSERT statements. Tables
+    /// with unsupported types are all listed.
+    #[clap(long)]
+    schema_only: bool,
+    /// With --schema-only, also write empty files with the schema of every table
+    #[clap(long, requires = "schema_only")]
+    empty_files: bool,
+    /// Secret mixed into the values of columns anonymized with the `hash` or `fake`
+    /// transforms of the configuration file, so that they cannot be guessed back
+    #[clap(global = true, long)]
+    salt: Option<String>,
+    #[command(flatten)]
+    parquet: WriterSettings,
+    #[command(flatten)]
+    text: TextOptions,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Read the dump without writing any file and report, per table and column, row and
+    /// null counts, min and max values, an approximate distinct count, the max length of
+    /// strings and the values that cannot be converted to the column type. Takes the
+    /// input, table, column, row and type override options of a conversion.
+    Profile {
+        /// Also write the report as JSON to this file
+        #[clap(long)]
+        json: Option<PathBuf>,
+    },
+}
+
+/// Progress bars of the reading, parsing and writing steps
+pub struct ProgressBars {
+    pub read: ProgressBar,
+    pub parse: ProgressBar,
+    pub write: ProgressBar,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum InputFormat {
+    /// mysqldump SQL files
+    Sql,
+    /// mydumper output directory, converted to one dataset per table
+    Mydumper,
+    /// `mysqldump --tab` output directory: `table.sql` schemas and `table.txt` data
+    Tab,
 }
 
 fn main() -> Result<()> {
     color_eyre::install()?;
     let args = Opts::parse();
-    let mut reader: Box<dyn BufRead> = {
-        match &args.input {
-            Some(file) => {
-                if file.ends_with(".gz") {
-                    Box::new(BufReader::with_capacity(
-                        8192 * 1000,
-                        GzDecoder::new(
-                            File::open(file).with_context(|| format!("Cannot open {file}"))?,
-                        ),
-                    ))
-                } else {
-                    Box::new(BufReader::with_capacity(
-                        8192 * 1000,
-                        File::open(file).with_context(|| format!("Cannot open {file}"))?,
-                    ))
-                }
+    let inputs = input::expand(&args.inputs)?;
+    let mut config = match &args.config {
+        Some(file) => Config::load(file)?,
+        None => Config::default(),
+    };
+    if let Some(file) = &args.schema_overrides {
+        config.overrides = SchemaOverrides::load(file)?;
+    }
+    // command line wins over the configuration file
+    if let Some(format) = args.format {
+        config.format = format;
+    }
+    config.parquet = args.parquet.or(&config.parquet);
+    config.text = args.text.or(&config.text);
+    if config.text.gzip() && !config.format.is_text() {
+        bail!("gzip compression is only supported by csv and ndjson formats");
+    }
+    for (table_name, columns) in &args.sort_by {
+        config.tables.entry(table_name.clone()).or_default().sort_by = columns.clone();
+    }
+    for (table_name, columns) in &args.columns {
+        config
+            .tables
+            .entry(table_name.clone())
+            .or_default()
+            .include_columns = columns.clone();
+    }
+    for (table_name, columns) in &args.exclude_columns {
+        config
+            .tables
+            .entry(table_name.clone())
+            .or_default()
+            .exclude_columns = columns.clone();
+    }
+    for (table_name, condition) in &args.where_clauses {
+        config
+            .tables
+            .entry(table_name.clone())
+            .or_default()
+            .where_clause = Some(condition.clone());
+    }
+    for (table_name, partition_by) in &args.partition_by {
+        let table = config.tables.entry(table_name.clone()).or_default();
+        table.partition_by = Some(partition_by.clone());
+        table.keep_partition_column |= args.keep_partition_columns;
+    }
+    if args.max_open_partitions.is_some() {
+        config.max_open_partitions = args.max_open_partitions;
+    }
+    config.create_indexes |= args.create_indexes;
+    if let Some(column_case) = args.column_case {
+        config.column_case = column_case;
+    }
+    for (table_name, sample) in &args.sample {
+        match table_name {
+            Some(table_name) => {
+                config.tables.entry(table_name.clone()).or_default().sample = Some(*sample)
             }
-
-            None => Box::new(io::stdin().lock()),
+            None => config.sample = Some(*sample),
         }
+    }
+    if args.limit.is_some() {
+        config.limit = args.limit;
+    }
+    if args.salt.is_some() {
+        config.salt = args.salt.clone();
+    }
+    let config = Arc::new(config);
+    if args.subset.is_some() && args.input_format != InputFormat::Sql {
+        bail!("--subset only supports mysqldump SQL files");
+    }
+    if args.schema_only && args.input_format != InputFormat::Sql {
+        bail!("--schema-only only supports mysqldump SQL files");
+    }
+    let mut tables = TableFilter {
+        include: args.tables.clone(),
+        exclude: args.exclude_tables.clone(),
+        limits: RowLimits::default(),
+        subset: None,
+        schema_only: args.schema_only,
     };
-    let output_dir = PathBuf::from(&args.output);
-    create_dir_all(&output_dir)
-        .with_context(|| format!("Cannot create output directory {}", args.output))?;
 
     // progress bar handling
 
@@ -85,30 +320,278 @@ fn main() -> Result<()> {
     progress.add(parse_progress_bar.clone());
     progress.add(write_progress_bar.clone());
 
-    let (writer_sender, write_thread_join_handle) =
-        ParquetWriter::start(output_dir, write_progress_bar);
-    let (line_parser_sender, line_parser_receiver) = crossbeam::channel::bounded::<String>(1000);
-
-    let line_parser_handle = std::thread::spawn(move || {
-        while let Ok(line) = line_parser_receiver.recv() {
-            let line = line_parser::parse_line(&line).unwrap();
-            match &line {
-                line_parser::Line::InsertInto(_, rows) => parse_progress_bar.inc(rows.len() as u64),
-                line_parser::Line::CreateTable(table_name, _) => {
-                    parse_progress_bar.set_message(format!("`{table_name}`"))
-                }
-                _ => parse_progress_bar.tick(),
-            }
-            writer_sender
-                .send(line)
-                .expect("Cannot send parsed rows to parquet writer");
+    if let Some(seed) = &args.subset {
+        tables.subset = Some(subset::collect(
+            &inputs,
+            args.input_compression,
+            &tables,
+            seed,
+            &read_progress_bar,
+        )?);
+    }
+
+    if let Some(Command::Profile { json }) = &args.command {
+        let profiles = profile::profile(
+            &inputs,
+            args.input_compression,
+            &tables,
+            &config,
+            &read_progress_bar,
+        )?;
+        read_progress_bar.finish_and_clear();
+        if let Some(json) = json {
+            profile::write_json(json, &profiles)?;
+        }
+        print!("{}", profile::render(&profiles));
+        return Ok(());
+    }
+
+    let output_dir = PathBuf::from(&args.output);
+    if config.format.is_database() {
+        if args.schema_only {
+            bail!("--schema-only writes schema files in a directory, not in a database");
+        }
+        if config.parquet.splits_files()
+            || config.tables.iter().any(|(table_name, table)| {
+                table.partition_by.is_some() || config.writer_settings(table_name).splits_files()
+            })
+        {
+            bail!("sqlite tables cannot be partitioned nor split into several files");
         }
+        if output_dir.is_dir() {
+            bail!("--output must be the database file with the sqlite format");
+        }
+        if let Some(dir) = output_dir
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+        {
+            create_dir_all(dir)
+                .with_context(|| format!("Cannot create output directory {}", dir.display()))?;
+        }
+    } else {
+        create_dir_all(&output_dir)
+            .with_context(|| format!("Cannot create output directory {}", args.output))?;
+    }
+
+    if args.schema_only {
+        let writer = args.empty_files.then(|| {
+            ParquetWriter::start(
+                output_dir.clone(),
+                args.max_memory / 4,
+                args.max_memory / 2,
+                config.clone(),
+                tables.clone(),
+                false,
+                write_progress_bar.clone(),
+            )
+        });
+        let result = schema_export::export(
+            &inputs,
+            args.input_compression,
+            &tables,
+            &config,
+            &output_dir,
+            writer.as_ref().map(|(sender, _)| sender),
+            &read_progress_bar,
+        );
+        read_progress_bar.finish_with_message("done!");
+        if let Some((sender, handle)) = writer {
+            drop(sender);
+            // an error of the writer makes the export fail to send schemas
+            handle.join().expect("Parquet writer thread crashed!")?;
+        }
+        return result;
+    }
+
+    if args.input_format == InputFormat::Mydumper {
+        let [dir] = inputs.as_slice() else {
+            bail!("mydumper input format expects a single directory");
+        };
+        mydumper::convert(
+            dir.as_ref(),
+            &output_dir,
+            args.jobs,
+            args.max_memory / 2,
+            &config,
+            &tables,
+            &ProgressBars {
+                read: read_progress_bar.clone(),
+                parse: parse_progress_bar.clone(),
+                write: write_progress_bar.clone(),
+            },
+        )?;
+        read_progress_bar.finish_with_message("done!");
+        parse_progress_bar.finish_with_message("Done parsing sql");
+        write_progress_bar.finish_with_message("Done writing parquet file(s).");
+        return Ok(());
+    }
+
+    // each worker reads and parses its own files, but the rows of a subset are told apart
+    // by their position in the dump, which must be read in order
+    let parallel = args.input_format == InputFormat::Sql
+        && args.jobs > 1
+        && inputs.len() > 1
+        && tables.subset.is_none();
+    let (writer_sender, write_thread_join_handle) = ParquetWriter::start(
+        output_dir,
+        args.max_memory / 4,
+        args.max_memory / 2,
+        config,
+        tables.clone(),
+        parallel,
+        write_progress_bar,
+    );
+    let read_result = if args.input_format == InputFormat::Tab {
+        let [dir] = inputs.as_slice() else {
+            bail!("tab input format expects a single directory");
+        };
+        let options = tab::TabOptions {
+            fields_terminated_by: args.fields_terminated_by.clone(),
+            fields_enclosed_by: args.fields_enclosed_by,
+        };
+        let result = tab::convert(
+            dir.as_ref(),
+            &options,
+            &tables,
+            args.jobs,
+            writer_sender,
+            &read_progress_bar,
+        );
+        parse_progress_bar.finish_with_message("Done parsing data files");
+        result
+    } else if parallel {
+        let (file_sender, file_receiver) = crossbeam::channel::unbounded();
+        for file in inputs {
+            file_sender.send(file)?;
+        }
+        drop(file_sender);
+        let result = std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..args.jobs)
+                .map(|_| {
+                    scope.spawn(|| -> Result<()> {
+                        while let Ok(file) = file_receiver.recv() {
+                            read_progress_bar.set_message(file.clone());
+                            let mut reader = input::open(Some(&file), args.input_compression)?;
+                            let mut current_statement = String::with_capacity(8192);
+                            read_statements(
+                                &mut reader,
+                                &mut current_statement,
+                                &read_progress_bar,
+                                &tables,
+                                |statement| {
+                                    let line = line_parser::parse_line(&statement)?;
+                                    inc_parse_progress(&parse_progress_bar, &line);
+                                    writer_sender.send(line).map_err(|_| {
+                                        eyre!("Cannot send parsed rows to parquet writer")
+                                    })
+                                },
+                            )
+                            .with_context(|| format!("Unable to convert {file}"))?;
+                        }
+                        Ok(())
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .try_for_each(|worker| worker.join().expect("Reader thread crashed!"))
+        });
+        drop(writer_sender);
         parse_progress_bar.set_message("Done parsing sql");
         parse_progress_bar.finish();
-    });
+        result
+    } else {
+        let (line_parser_sender, line_parser_receiver) =
+            memory::bounded::<String>(args.max_memory / 4);
+
+        let line_parser_handle = std::thread::spawn(move || -> Result<()> {
+            while let Ok(line) = line_parser_receiver.recv() {
+                let line = line_parser::parse_line(&line)?;
+                inc_parse_progress(&parse_progress_bar, &line);
+                if writer_sender.send(line).is_err() {
+                    // the writer stopped on an error, returned when it is joined
+                    break;
+                }
+            }
+            parse_progress_bar.set_message("Done parsing sql");
+            parse_progress_bar.finish();
+            Ok(())
+        });
+
+        // a statement may be split across two files
+        let mut current_statement = String::with_capacity(8192);
+        let send_statement = |statement| {
+            line_parser_sender
+                .send(statement)
+                .map_err(|_| eyre!("Cannot send SQL statement to parser!"))
+        };
+        let mut read = || -> Result<()> {
+            if inputs.is_empty() {
+                let mut reader = input::open(None, args.input_compression)?;
+                read_statements(
+                    &mut reader,
+                    &mut current_statement,
+                    &read_progress_bar,
+                    &tables,
+                    send_statement,
+                )?;
+            }
+            for file in &inputs {
+                read_progress_bar.set_message(file.clone());
+                let mut reader = input::open(Some(file), args.input_compression)?;
+                read_statements(
+                    &mut reader,
+                    &mut current_statement,
+                    &read_progress_bar,
+                    &tables,
+                    send_statement,
+                )?;
+            }
+            Ok(())
+        };
+        let result = read();
+        // nothing to send anymore, drop the sender so the parser thread will end.
+        drop(line_parser_sender);
+        // a parse error makes the reader fail to send statements
+        line_parser_handle
+            .join()
+            .expect("Parser thread crashed!")
+            .and(result)
+    };
+    read_progress_bar.set_message("done!");
+    read_progress_bar.finish();
+    // an error of the writer makes the readers fail to send rows: report it first
+    write_thread_join_handle
+        .join()
+        .expect("Parquet writer thread crashed!")
+        .and(read_result)
+}
+
+fn inc_parse_progress(parse_progress_bar: &ProgressBar, line: &line_parser::Line) {
+    match line {
+        line_parser::Line::InsertInto(_, rows) => parse_progress_bar.inc(rows.len() as u64),
+        line_parser::Line::CreateTable(table_name, _) => {
+            parse_progress_bar.set_message(format!("`{table_name}`"))
+        }
+        _ => parse_progress_bar.tick(),
+    }
+}
 
-    let mut current_statement = String::with_capacity(8192);
+/// Split dump lines into SQL statements and call `on_statement` for each `CREATE TABLE`
+/// and `INSERT INTO` statement of the `tables` to convert.
+///
+/// `current_statement` holds the unfinished statement at the end of the input so it can
+/// be continued by the next file.
+fn read_statements(
+    reader: &mut Box<dyn BufRead>,
+    current_statement: &mut String,
+    read_progress_bar: &ProgressBar,
+    tables: &TableFilter,
+    mut on_statement: impl FnMut(String) -> Result<()>,
+) -> Result<()> {
     let mut line = String::with_capacity(8192);
+    // in a statement of a table not converted
+    let mut skipping = false;
     loop {
         line.clear();
         if reader
@@ -119,6 +602,7 @@ fn main() -> Result<()> {
             break;
         };
         read_progress_bar.inc(1);
+        let raw_line = line.as_str();
         let line = line.trim();
         if line.starts_with("--")
             || line.starts_with("/*") && line.ends_with("*/;")
@@ -126,8 +610,13 @@ fn main() -> Result<()> {
         {
             // ignore comments
             continue;
+        } else if skipping || current_statement.is_empty() && !tables.accepts_statement(line) {
+            skipping = !line.ends_with(';');
+            continue;
         } else if current_statement.starts_with("CREATE TABLE") {
-            current_statement.extend(cleanup_key(line).chars())
+            // keep the original text of the statement
+            current_statement.push('\n');
+            current_statement.push_str(raw_line.trim_end());
         } else {
             current_statement.push_str(line)
         }
@@ -136,83 +625,29 @@ fn main() -> Result<()> {
             if current_statement.starts_with("CREATE TABLE")
                 || current_statement.starts_with("INSERT INTO")
             {
-                line_parser_sender
-                    .send(current_statement.trim().to_string())
-                    .context("Cannot send SQL statement to parser!")?;
+                on_statement(current_statement.trim().to_string())?;
             }
             current_statement.clear();
         }
     }
-    // nothing to send anymore, drop the sender so the parser thread will end.
-    drop(line_parser_sender);
-    read_progress_bar.set_message("done!");
-    read_progress_bar.finish();
-    line_parser_handle.join().expect("Parser thread crashed!");
-    write_thread_join_handle
-        .join()
-        .expect("Parquet writer thread crashed!");
-
     Ok(())
 }
 
-fn cleanup_key(line: &str) -> Cow<str> {
-    if line.contains("KEY ") {
-        let mut ret = String::new();
-        let mut depth = 0;
-        for c in line.chars() {
-            if c == '(' {
-                depth += 1;
-            }
-            if c == ')' {
-                depth -= 1;
-                if depth == 1 {
-                    continue;
-                }
+/// Read the `CREATE TABLE` statement of a schema file, if any.
+fn read_schema(file: &Path) -> Result<Option<(String, Schema)>> {
+    let mut reader = input::open(Some(&file.to_string_lossy()), Compression::Auto)?;
+    let mut create_table = None;
+    read_statements(
+        &mut reader,
+        &mut String::new(),
+        &ProgressBar::hidden(),
+        &TableFilter::default(),
+        |statement| {
+            if let Line::CreateTable(table_name, schema) = line_parser::parse_line(&statement)? {
+                create_table = Some((table_name, schema));
             }
-            if depth >= 2 {
-                continue;
-            }
-            ret.push(c);
-        }
-        ret.into()
-    } else {
-        line.into()
-    }
-}
-
-#[cfg(test)]
-mod test {
-    use crate::cleanup_key;
-
-    #[test]
-    fn cleanup() {
-        assert_eq!(
-            cleanup_key("KEY `facebookConnectId_index` (`facebookConnectId`)"),
-            "KEY `facebookConnectId_index` (`facebookConnectId`)"
-        );
-        assert_eq!(
-            cleanup_key("KEY `facebookConnectId_index` (`facebookConnectId`(144))"),
-            "KEY `facebookConnectId_index` (`facebookConnectId`)"
-        );
-        assert_eq!(
-            cleanup_key("KEY `facebookConnectId_index` (`facebookConnectId`(144),`plop`)"),
-            "KEY `facebookConnectId_index` (`facebookConnectId`,`plop`)"
-        );
-        assert_eq!(
-            cleanup_key("KEY `facebookConnectId_index` (`facebookConnectId`(144),`plop`(12))"),
-            "KEY `facebookConnectId_index` (`facebookConnectId`,`plop`)"
-        );
-        assert_eq!(
-            cleanup_key("KEY `facebookConnectId_index` (`facebookConnectId`,`plop`(12))"),
-            "KEY `facebookConnectId_index` (`facebookConnectId`,`plop`)"
-        );
-        assert_eq!(
-            cleanup_key("FOREIGN KEY (`facebookConnectId`)"),
-            "FOREIGN KEY (`facebookConnectId`)"
-        );
-        assert_eq!(
-            cleanup_key("FOREIGN KEY (`facebookConnectId`(144))"),
-            "FOREIGN KEY (`facebookConnectId`)"
-        );
-    }
+            Ok(())
+        },
+    )?;
+    Ok(create_table)
 }
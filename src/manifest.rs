@@ -0,0 +1,254 @@
+//! Manifest of a dataset written as several files.
+//!
+//! `_manifest.json` lists the parts of a table with their row counts and the min/max
+//! statistics of every column, so readers can prune parts without opening them.
+//! Statistics are read from the footer of parquet files and computed while writing
+//! other formats.
+
+use std::{fs::File, io::BufWriter, path::Path};
+
+use arrow::{
+    array::{ArrayRef, AsArray},
+    compute::{max, max_boolean, max_string, min, min_boolean, min_string},
+    datatypes::{DataType, Float64Type, Int64Type, TimeUnit, TimestampSecondType},
+    record_batch::RecordBatch,
+};
+use color_eyre::eyre::{Context, Result};
+use parquet::{
+    basic::Type,
+    file::statistics::{self, Statistics},
+    format::FileMetaData,
+};
+use serde::Serialize;
+
+pub const MANIFEST_FILE: &str = "_manifest.json";
+
+#[derive(Debug, Serialize)]
+pub struct PartInfo {
+    /// file name, relative to the dataset directory
+    pub path: String,
+    pub row_count: u64,
+    /// size in bytes
+    pub size: u64,
+    pub columns: Vec<ColumnStats>,
+}
+
+/// Statistics of a column, unknown (`null`) when statistics are disabled
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ColumnStats {
+    pub name: String,
+    pub null_count: Option<u64>,
+    pub min: Option<StatValue>,
+    pub max: Option<StatValue>,
+}
+
+#[derive(Clone, Debug, PartialEq, PartialOrd, Serialize)]
+#[serde(untagged)]
+pub enum StatValue {
+    Boolean(bool),
+    Integer(i64),
+    Float(f64),
+    String(String),
+}
+
+impl PartInfo {
+    /// Describe the parquet file `path` from the metadata returned when closing it.
+    pub fn new(path: &Path, metadata: &FileMetaData) -> Result<PartInfo> {
+        let size = file_size(path)?;
+        // flat schema: the root element followed by one element per column
+        let columns = metadata
+            .schema
+            .iter()
+            .skip(1)
+            .enumerate()
+            .map(|(i, element)| {
+                let row_groups = metadata.row_groups.iter().map(|row_group| {
+                    let meta_data = row_group.columns.get(i)?.meta_data.as_ref()?;
+                    statistics::from_thrift(
+                        Type::try_from(meta_data.type_).ok()?,
+                        meta_data.statistics.clone(),
+                    )
+                    .ok()?
+                });
+                ColumnStats::merge(element.name.clone(), row_groups)
+            })
+            .collect();
+        Ok(PartInfo {
+            path: file_name(path),
+            row_count: metadata.num_rows as u64,
+            size,
+            columns,
+        })
+    }
+}
+
+impl ColumnStats {
+    fn new(name: String) -> ColumnStats {
+        ColumnStats {
+            name,
+            null_count: Some(0),
+            min: None,
+            max: None,
+        }
+    }
+
+    /// Merge the statistics of every row group, `None` when a row group has none.
+    fn merge(name: String, row_groups: impl Iterator<Item = Option<Statistics>>) -> ColumnStats {
+        let mut ret = ColumnStats::new(name);
+        for statistics in row_groups {
+            let Some(statistics) = statistics else {
+                ret.null_count = None;
+                ret.min = None;
+                ret.max = None;
+                break;
+            };
+            ret.update(statistics.null_count(), min_max(&statistics));
+        }
+        ret
+    }
+
+    /// Account for more values, `min_max` being `None` when they are all null.
+    fn update(&mut self, null_count: u64, min_max: Option<(StatValue, StatValue)>) {
+        self.null_count = self.null_count.map(|n| n + null_count);
+        let Some((min, max)) = min_max else {
+            return;
+        };
+        if self.min.as_ref().is_none_or(|current| min < *current) {
+            self.min = Some(min);
+        }
+        if self.max.as_ref().is_none_or(|current| max > *current) {
+            self.max = Some(max);
+        }
+    }
+}
+
+/// Statistics computed from the batches written, for formats storing none
+pub struct StatsCollector {
+    row_count: u64,
+    columns: Vec<ColumnStats>,
+}
+
+impl StatsCollector {
+    pub fn new(schema: &arrow::datatypes::Schema) -> StatsCollector {
+        StatsCollector {
+            row_count: 0,
+            columns: schema
+                .fields()
+                .iter()
+                .map(|field| ColumnStats::new(field.name().clone()))
+                .collect(),
+        }
+    }
+
+    pub fn update(&mut self, batch: &RecordBatch) {
+        self.row_count += batch.num_rows() as u64;
+        for (column, array) in self.columns.iter_mut().zip(batch.columns()) {
+            column.update(array.null_count() as u64, array_min_max(array));
+        }
+    }
+
+    /// Describe the file `path` once written
+    pub fn finish(self, path: &Path) -> Result<PartInfo> {
+        Ok(PartInfo {
+            path: file_name(path),
+            row_count: self.row_count,
+            size: file_size(path)?,
+            columns: self.columns,
+        })
+    }
+}
+
+fn array_min_max(array: &ArrayRef) -> Option<(StatValue, StatValue)> {
+    Some(match array.data_type() {
+        DataType::Boolean => {
+            let array = array.as_boolean();
+            (
+                StatValue::Boolean(min_boolean(array)?),
+                StatValue::Boolean(max_boolean(array)?),
+            )
+        }
+        DataType::Int64 => {
+            let array = array.as_primitive::<Int64Type>();
+            (
+                StatValue::Integer(min(array)?),
+                StatValue::Integer(max(array)?),
+            )
+        }
+        DataType::Timestamp(TimeUnit::Second, _) => {
+            let array = array.as_primitive::<TimestampSecondType>();
+            (
+                StatValue::Integer(min(array)?),
+                StatValue::Integer(max(array)?),
+            )
+        }
+        DataType::Float64 => {
+            let array = array.as_primitive::<Float64Type>();
+            (StatValue::Float(min(array)?), StatValue::Float(max(array)?))
+        }
+        DataType::Utf8 => {
+            let array = array.as_string::<i32>();
+            (
+                StatValue::String(min_string(array)?.to_string()),
+                StatValue::String(max_string(array)?.to_string()),
+            )
+        }
+        _ => return None,
+    })
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn file_size(path: &Path) -> Result<u64> {
+    Ok(std::fs::metadata(path)
+        .with_context(|| format!("Cannot read {}", path.display()))?
+        .len())
+}
+
+fn min_max(statistics: &Statistics) -> Option<(StatValue, StatValue)> {
+    if !statistics.has_min_max_set() {
+        return None;
+    }
+    Some(match statistics {
+        Statistics::Boolean(s) => (StatValue::Boolean(*s.min()), StatValue::Boolean(*s.max())),
+        Statistics::Int32(s) => (
+            StatValue::Integer(*s.min() as i64),
+            StatValue::Integer(*s.max() as i64),
+        ),
+        Statistics::Int64(s) => (StatValue::Integer(*s.min()), StatValue::Integer(*s.max())),
+        Statistics::Float(s) => (
+            StatValue::Float(*s.min() as f64),
+            StatValue::Float(*s.max() as f64),
+        ),
+        Statistics::Double(s) => (StatValue::Float(*s.min()), StatValue::Float(*s.max())),
+        Statistics::ByteArray(s) => (
+            StatValue::String(String::from_utf8_lossy(s.min().data()).into_owned()),
+            StatValue::String(String::from_utf8_lossy(s.max().data()).into_owned()),
+        ),
+        Statistics::Int96(_) | Statistics::FixedLenByteArray(_) => return None,
+    })
+}
+
+#[derive(Serialize)]
+struct Manifest<'a> {
+    table: &'a str,
+    row_count: u64,
+    parts: &'a [PartInfo],
+}
+
+/// Write the manifest of `table_name` into its dataset directory `dir`.
+pub fn write(dir: &Path, table_name: &str, parts: &[PartInfo]) -> Result<()> {
+    let path = dir.join(MANIFEST_FILE);
+    let manifest = Manifest {
+        table: table_name,
+        row_count: parts.iter().map(|part| part.row_count).sum(),
+        parts,
+    };
+    let file = File::create(&path).with_context(|| format!("Cannot create {}", path.display()))?;
+    serde_json::to_writer_pretty(BufWriter::new(file), &manifest)
+        .with_context(|| format!("Cannot write {}", path.display()))
+}
@@ -0,0 +1,252 @@
+//! Byte based memory accounting for the conversion pipeline.
+//!
+//! Channels between the reader, the parser and the writer threads are bounded by the
+//! estimated heap size of the messages they hold rather than by a message count: a
+//! single extended INSERT statement can weight several megabytes.
+//!
+//! Rows kept by a row count sample ([`crate::sample::RowSample`]) and the keys of a
+//! `--subset` are not accounted for.
+
+use std::{
+    mem::size_of,
+    sync::{Arc, Condvar, Mutex},
+};
+
+use crossbeam::channel::{RecvError, SendError};
+
+use crate::line_parser::{ColumnDef, ColumnValue, Line};
+
+/// Estimated heap memory owned by a value.
+pub trait HeapSize {
+    fn heap_size(&self) -> usize;
+}
+
+impl HeapSize for String {
+    fn heap_size(&self) -> usize {
+        self.capacity()
+    }
+}
+
+impl HeapSize for ColumnValue {
+    fn heap_size(&self) -> usize {
+        match self {
+            ColumnValue::String(s) | ColumnValue::Decimal(s) => s.capacity(),
+            _ => 0,
+        }
+    }
+}
+
+impl HeapSize for Option<String> {
+    fn heap_size(&self) -> usize {
+        self.as_ref().map_or(0, String::capacity)
+    }
+}
+
+impl HeapSize for ColumnDef {
+    fn heap_size(&self) -> usize {
+        self.column_name.capacity()
+            + self.field_name.capacity()
+            + self.mysql_type.capacity()
+            + self.comment.heap_size()
+            + self.default.heap_size()
+    }
+}
+
+impl HeapSize for Vec<Vec<ColumnValue>> {
+    fn heap_size(&self) -> usize {
+        self.capacity() * size_of::<Vec<ColumnValue>>()
+            + self
+                .iter()
+                .map(|row| {
+                    row.capacity() * size_of::<ColumnValue>()
+                        + row.iter().map(HeapSize::heap_size).sum::<usize>()
+                })
+                .sum::<usize>()
+    }
+}
+
+impl HeapSize for Line {
+    fn heap_size(&self) -> usize {
+        match self {
+            Line::CreateTable(table_name, schema) => {
+                table_name.capacity()
+                    + schema.create_table.capacity()
+                    + schema.columns.capacity() * size_of::<ColumnDef>()
+                    + schema
+                        .columns
+                        .iter()
+                        .map(HeapSize::heap_size)
+                        .sum::<usize>()
+            }
+            Line::InsertInto(table_name, rows) => table_name.capacity() + rows.heap_size(),
+            Line::NOP => 0,
+        }
+    }
+}
+
+/// Parse a human readable byte size such as `512M`, `4G` or `1.5GiB`.
+///
+/// Suffixes are powers of 1024, a bare number is a number of bytes.
+pub fn parse_byte_size(value: &str) -> Result<usize, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("Invalid size `{value}`"))?;
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        _ => return Err(format!("Invalid size unit `{unit}` in `{value}`")),
+    };
+    Ok((number * multiplier as f64) as usize)
+}
+
+struct State {
+    used: usize,
+    closed: bool,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    not_full: Condvar,
+    limit: usize,
+}
+
+/// Create a channel holding at most `limit` bytes of messages.
+///
+/// A message larger than the limit is still accepted when the channel is empty, so the
+/// pipeline never deadlocks on a huge statement.
+pub fn bounded<T: HeapSize>(limit: usize) -> (Sender<T>, Receiver<T>) {
+    let (sender, receiver) = crossbeam::channel::unbounded();
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            used: 0,
+            closed: false,
+        }),
+        not_full: Condvar::new(),
+        limit,
+    });
+    (
+        Sender {
+            inner: sender,
+            shared: shared.clone(),
+        },
+        Receiver {
+            inner: receiver,
+            shared,
+        },
+    )
+}
+
+pub struct Sender<T> {
+    inner: crossbeam::channel::Sender<(T, usize)>,
+    shared: Arc<Shared>,
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        Sender {
+            inner: self.inner.clone(),
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T: HeapSize> Sender<T> {
+    /// Send a message, blocking while the channel is over its byte budget.
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        let size = value.heap_size();
+        {
+            let mut state = self.shared.state.lock().unwrap();
+            while !state.closed && state.used > 0 && state.used + size > self.shared.limit {
+                state = self.shared.not_full.wait(state).unwrap();
+            }
+            if state.closed {
+                return Err(SendError(value));
+            }
+            state.used += size;
+        }
+        self.inner
+            .send((value, size))
+            .map_err(|SendError((value, size))| {
+                self.shared.release(size);
+                SendError(value)
+            })
+    }
+}
+
+pub struct Receiver<T> {
+    inner: crossbeam::channel::Receiver<(T, usize)>,
+    shared: Arc<Shared>,
+}
+
+impl<T> Receiver<T> {
+    pub fn recv(&self) -> Result<T, RecvError> {
+        let (value, size) = self.inner.recv()?;
+        self.shared.release(size);
+        Ok(value)
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        // wake up blocked senders: nobody will ever free memory again.
+        self.shared.state.lock().unwrap().closed = true;
+        self.shared.not_full.notify_all();
+    }
+}
+
+impl Shared {
+    fn release(&self, size: usize) {
+        self.state.lock().unwrap().used -= size;
+        self.not_full.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{thread, time::Duration};
+
+    use super::{bounded, parse_byte_size};
+
+    #[test]
+    fn byte_sizes() {
+        assert_eq!(parse_byte_size("1024"), Ok(1024));
+        assert_eq!(parse_byte_size("512M"), Ok(512 << 20));
+        assert_eq!(parse_byte_size("4G"), Ok(4 << 30));
+        assert_eq!(parse_byte_size("1.5GiB"), Ok(3 << 29));
+        assert_eq!(parse_byte_size("2kb"), Ok(2048));
+        assert!(parse_byte_size("12X").is_err());
+        assert!(parse_byte_size("G").is_err());
+    }
+
+    #[test]
+    fn channel_is_bounded_by_bytes() {
+        let (sender, receiver) = bounded::<String>(10);
+        // bigger than the limit, but the channel is empty
+        sender.send("x".repeat(20)).unwrap();
+        let blocked_sender = sender.clone();
+        let handle = thread::spawn(move || blocked_sender.send("y".repeat(5)).unwrap());
+        thread::sleep(Duration::from_millis(50));
+        assert!(!handle.is_finished());
+        assert_eq!(receiver.recv().unwrap().len(), 20);
+        handle.join().unwrap();
+        assert_eq!(receiver.recv().unwrap(), "yyyyy");
+    }
+
+    #[test]
+    fn dropped_receiver_unblocks_senders() {
+        let (sender, receiver) = bounded::<String>(10);
+        sender.send("x".repeat(10)).unwrap();
+        let handle = thread::spawn(move || sender.send("y".repeat(5)).is_err());
+        thread::sleep(Duration::from_millis(50));
+        drop(receiver);
+        assert!(handle.join().unwrap());
+    }
+}
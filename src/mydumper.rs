@@ -0,0 +1,381 @@
+//! Conversion of mydumper directories.
+//!
+//! mydumper writes one `db.table-schema.sql` file per table holding its `CREATE TABLE`
+//! statement, data chunks named `db.table.00000.sql` (possibly compressed) and a
+//! `metadata` file with the binlog position of the dump.
+//!
+//! Schemas are read first, then data chunks are converted in parallel, each chunk into a
+//! part of the `{table}/` dataset, described by its `_manifest.json` (or committed to the
+//! table when writing Delta or Iceberg tables).
+
+use std::{
+    collections::BTreeMap,
+    fs::{create_dir_all, read_dir, read_to_string},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use color_eyre::eyre::{bail, eyre, Context, Result};
+use parquet::format::KeyValue;
+
+use crate::{
+    config::Config,
+    filter::{TableFilter, TableRows},
+    input::{self, Compression},
+    line_parser::{self, Line, Schema},
+    manifest::{self, PartInfo},
+    parquet_writer::TableWriter,
+    read_schema, read_statements, ProgressBars,
+};
+
+#[derive(Debug, PartialEq, Eq)]
+enum DumpFile {
+    /// `CREATE TABLE` statement of table `db.table`
+    Schema(String),
+    /// data chunk of table `db.table`
+    Data(String),
+}
+
+/// Classify a file of a mydumper directory from its name, ignoring views, triggers,
+/// database creation statements and anything else.
+fn classify(file_name: &str) -> Option<DumpFile> {
+    let name = [".gz", ".zst", ".xz", ".bz2", ".lz4"]
+        .iter()
+        .find_map(|ext| file_name.strip_suffix(ext))
+        .unwrap_or(file_name);
+    let name = name.strip_suffix(".sql")?;
+    if let Some(table) = name.strip_suffix("-schema") {
+        return table.contains('.').then(|| DumpFile::Schema(table.into()));
+    }
+    if name.contains('-') {
+        // -schema-create, -schema-view, -schema-triggers, ...
+        return None;
+    }
+    // strip chunk numbers: db.table.00000 or db.table.00000.00001
+    let mut table = name;
+    while let Some((prefix, chunk)) = table.rsplit_once('.') {
+        if chunk.is_empty() || !chunk.bytes().all(|b| b.is_ascii_digit()) {
+            break;
+        }
+        table = prefix;
+    }
+    table.contains('.').then(|| DumpFile::Data(table.into()))
+}
+
+/// Extract the binlog position from a mydumper `metadata` file, as parquet key-value
+/// metadata.
+///
+/// Handles both the legacy `SHOW MASTER STATUS:` format and the ini format of recent
+/// mydumper versions.
+fn parse_metadata(content: &str) -> Vec<KeyValue> {
+    let mut metadata: Vec<KeyValue> = Vec::new();
+    for line in content.lines() {
+        let Some((key, value)) = line.split_once([':', '=']) else {
+            continue;
+        };
+        let key = match key.trim() {
+            "Log" | "File" => "mydumper.binlog_file",
+            "Pos" | "Position" => "mydumper.binlog_position",
+            "GTID" | "Executed_Gtid_Set" => "mydumper.gtid",
+            _ => continue,
+        };
+        let value = value.trim().trim_matches(['\'', '"']);
+        // only keep the first section: the source server position
+        if value.is_empty() || metadata.iter().any(|kv| kv.key == key) {
+            continue;
+        }
+        metadata.push(KeyValue::new(key.to_string(), value.to_string()));
+    }
+    metadata
+}
+
+struct Chunk {
+    table_name: String,
+    schema: Schema,
+    /// rows and columns kept
+    rows: TableRows,
+    /// key-value metadata of the parquet files
+    metadata: Vec<KeyValue>,
+    file: PathBuf,
+    /// chunk number, in the order of the dump
+    part: usize,
+}
+
+/// Name of the table of a dump file, without its database
+fn table_name(table: &str) -> &str {
+    table.split_once('.').map_or(table, |(_, name)| name)
+}
+
+/// Convert the `tables` of the mydumper directory `dir` into one parquet dataset per
+/// table.
+pub fn convert(
+    dir: &Path,
+    output_dir: &Path,
+    jobs: usize,
+    max_buffered_bytes: usize,
+    config: &Config,
+    tables: &TableFilter,
+    progress_bars: &ProgressBars,
+) -> Result<()> {
+    let ProgressBars {
+        read: read_progress_bar,
+        parse: parse_progress_bar,
+        write: write_progress_bar,
+    } = progress_bars;
+    let mut schema_files = BTreeMap::new();
+    let mut data_files: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    let mut metadata = Vec::new();
+    for entry in read_dir(dir).with_context(|| format!("Cannot read {}", dir.display()))? {
+        let path = entry?.path();
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        if file_name == "metadata" {
+            metadata = parse_metadata(&read_to_string(&path)?);
+            continue;
+        }
+        match classify(&file_name) {
+            Some(DumpFile::Schema(table)) | Some(DumpFile::Data(table))
+                if !tables.accepts(table_name(&table)) => {}
+            Some(DumpFile::Schema(table)) => {
+                schema_files.insert(table, path);
+            }
+            Some(DumpFile::Data(table)) => data_files.entry(table).or_default().push(path),
+            None => {}
+        }
+    }
+
+    // schemas first, so every chunk can be written on its own
+    let mut chunks = Vec::new();
+    let mut table_names = BTreeMap::new();
+    // parts of every chunk, listed in the manifest of each dataset
+    let mut parts: BTreeMap<String, Vec<(usize, Vec<PartInfo>)>> = BTreeMap::new();
+    let mut schemas = BTreeMap::new();
+    // tables with a row count sample, written once every chunk is read
+    let mut samples = Vec::new();
+    for (table, schema_file) in &schema_files {
+        read_progress_bar.set_message(schema_file.display().to_string());
+        let (table_name, mut schema) = read_schema(schema_file)
+            .and_then(|schema| schema.ok_or_else(|| eyre!("No CREATE TABLE statement")))
+            .with_context(|| format!("Unable to read schema {}", schema_file.display()))?;
+        let table_rows = config.table_rows(&table_name, &mut schema, tables)?;
+        let mut table_metadata = metadata.clone();
+        table_metadata.extend(table_rows.key_value_metadata());
+        let output_name = config.output_name(&table_name);
+        if let Some(other) = table_names.insert(output_name.clone(), table) {
+            bail!("Tables {other} and {table} would be written to the same dataset");
+        }
+        schemas.insert(table_name.clone(), schema.clone());
+        if !config.format.is_database() {
+            let table_dir = output_dir.join(&output_name);
+            create_dir_all(&table_dir).with_context(|| {
+                format!("Cannot create output directory {}", table_dir.display())
+            })?;
+        }
+        let mut files = data_files.remove(table).unwrap_or_default();
+        files.sort();
+        if files.is_empty() {
+            // keep the schema of empty tables
+            let writer = TableWriter::create(
+                output_dir,
+                &table_name,
+                schema.clone(),
+                table_metadata.clone(),
+                config,
+                Some(0),
+            )?;
+            parts.insert(table_name.clone(), vec![(0, writer.finish()?)]);
+        } else if table_rows.is_buffered() {
+            samples.push((
+                table_name.clone(),
+                schema.clone(),
+                table_metadata.clone(),
+                table_rows.clone(),
+            ));
+        }
+        for (part, file) in files.into_iter().enumerate() {
+            chunks.push(Chunk {
+                table_name: table_name.clone(),
+                schema: schema.clone(),
+                rows: table_rows.clone(),
+                metadata: table_metadata.clone(),
+                file,
+                part,
+            });
+        }
+    }
+    if let Some(table) = data_files.keys().next() {
+        bail!("No schema file for table {table}");
+    }
+
+    let (chunk_sender, chunk_receiver) = crossbeam::channel::unbounded();
+    for chunk in chunks {
+        chunk_sender.send(chunk)?;
+    }
+    drop(chunk_sender);
+    let jobs = jobs.max(1);
+    let parts = Mutex::new(parts);
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..jobs)
+            .map(|_| {
+                scope.spawn(|| -> Result<()> {
+                    while let Ok(chunk) = chunk_receiver.recv() {
+                        read_progress_bar.set_message(chunk.file.display().to_string());
+                        write_progress_bar.set_message(format!("`{}`", chunk.table_name));
+                        let mut writer = (!chunk.rows.is_buffered())
+                            .then(|| {
+                                TableWriter::create(
+                                    output_dir,
+                                    &chunk.table_name,
+                                    chunk.schema,
+                                    chunk.metadata,
+                                    config,
+                                    Some(chunk.part),
+                                )
+                            })
+                            .transpose()?;
+                        let mut reader =
+                            input::open(Some(&chunk.file.to_string_lossy()), Compression::Auto)?;
+                        read_statements(
+                            &mut reader,
+                            &mut String::new(),
+                            read_progress_bar,
+                            tables,
+                            |statement| {
+                                if let Line::InsertInto(_, mut rows) =
+                                    line_parser::parse_line(&statement)?
+                                {
+                                    chunk.rows.apply(&mut rows);
+                                    let row_count = rows.len() as u64;
+                                    parse_progress_bar.inc(row_count);
+                                    if let Some(writer) = &mut writer {
+                                        writer.write_bounded(rows, max_buffered_bytes / jobs)?;
+                                    }
+                                    write_progress_bar.inc(row_count);
+                                }
+                                Ok(())
+                            },
+                        )
+                        .with_context(|| format!("Unable to convert {}", chunk.file.display()))?;
+                        if let Some(writer) = writer {
+                            parts
+                                .lock()
+                                .unwrap()
+                                .entry(chunk.table_name)
+                                .or_default()
+                                .push((chunk.part, writer.finish()?));
+                        }
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .try_for_each(|worker| worker.join().expect("Chunk converter thread crashed!"))
+    })?;
+    let mut parts = parts.into_inner().unwrap();
+    for (table_name, schema, metadata, table_rows) in samples {
+        let rows = table_rows.sampled_rows();
+        write_progress_bar.inc(rows.len() as u64);
+        let mut writer =
+            TableWriter::create(output_dir, &table_name, schema, metadata, config, Some(0))?;
+        writer.write_rows(rows)?;
+        parts.insert(table_name, vec![(0, writer.finish()?)]);
+    }
+
+    for (table_name, mut chunk_parts) in parts {
+        chunk_parts.sort_by_key(|(part, _)| *part);
+        let table_parts: Vec<PartInfo> = chunk_parts
+            .into_iter()
+            .flat_map(|(_, parts)| parts)
+            .collect();
+        if config.format.commits_tables() {
+            config.format.commit(
+                output_dir,
+                &table_name,
+                &schemas[&table_name],
+                config,
+                &table_parts,
+            )?;
+        } else {
+            let output_name = config.output_name(&table_name);
+            manifest::write(&output_dir.join(&output_name), &output_name, &table_parts)?;
+        }
+    }
+    config.check_projected_tables(tables, |table_name| schemas.contains_key(table_name))
+}
+
+#[cfg(test)]
+mod test {
+    use parquet::format::KeyValue;
+
+    use super::{classify, parse_metadata, DumpFile};
+
+    #[test]
+    fn classify_files() {
+        assert_eq!(
+            classify("db.user-schema.sql"),
+            Some(DumpFile::Schema("db.user".into()))
+        );
+        assert_eq!(
+            classify("db.user.sql.zst"),
+            Some(DumpFile::Data("db.user".into()))
+        );
+        assert_eq!(
+            classify("db.user.00001.sql.gz"),
+            Some(DumpFile::Data("db.user".into()))
+        );
+        assert_eq!(
+            classify("db.user.00000.00002.sql"),
+            Some(DumpFile::Data("db.user".into()))
+        );
+        assert_eq!(classify("db-schema-create.sql"), None);
+        assert_eq!(classify("db.user_view-schema-view.sql"), None);
+        assert_eq!(classify("db.user-schema-triggers.sql"), None);
+        assert_eq!(classify("metadata"), None);
+    }
+
+    #[test]
+    fn legacy_metadata() {
+        let metadata = parse_metadata(
+            "Started dump at: 2024-01-01 00:00:00\n\
+             SHOW MASTER STATUS:\n\
+             \tLog: mysql-bin.000123\n\
+             \tPos: 4567\n\
+             \tGTID:\n\
+             \n\
+             Finished dump at: 2024-01-01 01:00:00\n",
+        );
+        assert_eq!(
+            metadata,
+            vec![
+                KeyValue::new(
+                    "mydumper.binlog_file".into(),
+                    "mysql-bin.000123".to_string()
+                ),
+                KeyValue::new("mydumper.binlog_position".into(), "4567".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn ini_metadata() {
+        let metadata = parse_metadata(
+            "# Started dump at: 2024-01-01 00:00:00\n\
+             [source]\n\
+             File = mysql-bin.000042\n\
+             Position = 157\n\
+             Executed_Gtid_Set = 'uuid:1-42'\n",
+        );
+        assert_eq!(
+            metadata,
+            vec![
+                KeyValue::new(
+                    "mydumper.binlog_file".into(),
+                    "mysql-bin.000042".to_string()
+                ),
+                KeyValue::new("mydumper.binlog_position".into(), "157".to_string()),
+                KeyValue::new("mydumper.gtid".into(), "uuid:1-42".to_string()),
+            ]
+        );
+    }
+}
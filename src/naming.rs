@@ -0,0 +1,116 @@
+//! Names of output columns and tables.
+//!
+//! MySQL identifiers are case insensitive and may hold any character: output names follow
+//! a [`ColumnCase`] policy, unless renamed in the configuration file, and table names are
+//! made safe to use as file names.
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+/// Case of output column and table names
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ColumnCase {
+    /// MySQL names as is
+    Preserve,
+    /// Lowercase MySQL names
+    #[default]
+    Lower,
+    /// snake_case, e.g. `shortName` becomes `short_name`
+    Snake,
+}
+
+impl ColumnCase {
+    pub fn apply(self, name: &str) -> String {
+        match self {
+            ColumnCase::Preserve => name.to_string(),
+            ColumnCase::Lower => name.to_lowercase(),
+            ColumnCase::Snake => snake_case(name),
+        }
+    }
+}
+
+/// `shortName`, `HTTPServer` or `Postal Code` to `short_name`, `http_server` or
+/// `postal_code`
+fn snake_case(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut snake = String::with_capacity(name.len() + 4);
+    for (i, &c) in chars.iter().enumerate() {
+        if !c.is_alphanumeric() {
+            if !snake.is_empty() && !snake.ends_with('_') {
+                snake.push('_');
+            }
+            continue;
+        }
+        if c.is_uppercase() && i > 0 && !snake.is_empty() && !snake.ends_with('_') {
+            let previous = chars[i - 1];
+            let next_is_lower = chars.get(i + 1).is_some_and(|next| next.is_lowercase());
+            if previous.is_lowercase()
+                || previous.is_numeric()
+                || (previous.is_uppercase() && next_is_lower)
+            {
+                snake.push('_');
+            }
+        }
+        snake.extend(c.to_lowercase());
+    }
+    if snake.ends_with('_') {
+        snake.pop();
+    }
+    if snake.is_empty() {
+        name.to_string()
+    } else {
+        snake
+    }
+}
+
+/// Replace the characters of `name` that are unsafe in a file name
+pub fn file_name(name: &str) -> String {
+    let safe: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    // hidden files, `.` and `..`
+    if safe.is_empty() || safe.starts_with('.') {
+        format!("_{safe}")
+    } else {
+        safe
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{file_name, ColumnCase};
+
+    #[test]
+    fn column_case() {
+        assert_eq!(ColumnCase::Preserve.apply("shortName"), "shortName");
+        assert_eq!(ColumnCase::Lower.apply("shortName"), "shortname");
+        for (name, snake) in [
+            ("shortName", "short_name"),
+            ("short_name", "short_name"),
+            ("HTTPServer", "http_server"),
+            ("userID", "user_id"),
+            ("address2Line", "address2_line"),
+            ("Postal Code", "postal_code"),
+            ("user-id", "user_id"),
+            ("_private", "private"),
+            ("ID", "id"),
+        ] {
+            assert_eq!(ColumnCase::Snake.apply(name), snake, "{name}");
+        }
+    }
+
+    #[test]
+    fn file_names() {
+        assert_eq!(file_name("user"), "user");
+        assert_eq!(file_name("sales/2024"), "sales_2024");
+        assert_eq!(file_name("a:b*c?"), "a_b_c_");
+        assert_eq!(file_name(".."), "_..");
+        assert_eq!(file_name(""), "_");
+    }
+}
@@ -0,0 +1,389 @@
+//! Schema overrides: output types of columns whose automatic mapping is wrong.
+//!
+//! A TOML or YAML file maps `table.column` to a type and, optionally, a cast:
+//!
+//! ```toml
+//! [user.created_at]
+//! type = "timestamp"
+//! cast = "epoch_millis"
+//!
+//! [user.birth_date]
+//! type = "timestamp"
+//! cast = "%d/%m/%Y"
+//!
+//! [user.active]
+//! type = "boolean"
+//! ```
+//!
+//! Values are converted when written: `WHERE` conditions compare them as parsed. Epoch
+//! milli and microseconds are written as timestamps of that unit.
+
+use std::{collections::HashMap, fs::read_to_string, path::Path};
+
+use arrow::datatypes::TimeUnit;
+use chrono::{NaiveDate, NaiveDateTime};
+use color_eyre::eyre::{bail, Context, Result};
+use serde::Deserialize;
+
+use crate::line_parser::{ColumnType, ColumnValue, Schema};
+
+/// Overrides by table and column, looked up by MySQL name
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct SchemaOverrides(HashMap<String, HashMap<String, ColumnOverride>>);
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ColumnOverride {
+    #[serde(rename = "type")]
+    column_type: TargetType,
+    #[serde(default)]
+    cast: Cast,
+}
+
+/// Arrow type of a column
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum TargetType {
+    #[serde(alias = "string")]
+    Utf8,
+    #[serde(alias = "integer")]
+    Int64,
+    #[serde(alias = "float", alias = "double")]
+    Float64,
+    Timestamp,
+    #[serde(alias = "bool")]
+    Boolean,
+}
+
+/// Conversion of the parsed values of a column to its type
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Cast {
+    /// numbers and booleans (`0`, `1`, `true`, `false`) are parsed from strings, other
+    /// values written as strings, and timestamps parsed from `YYYY-MM-DD[ hh:mm:ss]`
+    #[default]
+    Default,
+    /// timestamps from numbers of seconds, milliseconds or microseconds since 1970
+    EpochSeconds,
+    EpochMillis,
+    EpochMicros,
+    /// timestamps parsed with a strftime format, such as `%d/%m/%Y` or `%Y%m%dT%H%M%S`
+    Format(String),
+}
+
+impl TryFrom<String> for Cast {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "default" => Ok(Cast::Default),
+            "epoch_seconds" => Ok(Cast::EpochSeconds),
+            "epoch_millis" => Ok(Cast::EpochMillis),
+            "epoch_micros" => Ok(Cast::EpochMicros),
+            format if format.contains('%') => Ok(Cast::Format(value)),
+            _ => Err(format!(
+                "Unknown cast `{value}`: expected epoch_seconds, epoch_millis, epoch_micros \
+                 or a date format such as `%d/%m/%Y`"
+            )),
+        }
+    }
+}
+
+impl SchemaOverrides {
+    /// Load a `.toml`, `.yaml` or `.yml` file
+    pub fn load(file: &Path) -> Result<SchemaOverrides> {
+        let content =
+            read_to_string(file).with_context(|| format!("Cannot read {}", file.display()))?;
+        let overrides: SchemaOverrides = match file.extension().and_then(|e| e.to_str()) {
+            Some("yaml" | "yml") => serde_yaml::from_str(&content)
+                .with_context(|| format!("Invalid {}", file.display()))?,
+            _ => toml::from_str(&content).with_context(|| format!("Invalid {}", file.display()))?,
+        };
+        for (table_name, columns) in &overrides.0 {
+            for (column_name, column) in columns {
+                if column.cast != Cast::Default && column.column_type != TargetType::Timestamp {
+                    bail!("Only timestamps can be cast, not {table_name}.{column_name}");
+                }
+            }
+        }
+        Ok(overrides)
+    }
+
+    /// Set the type and cast of the overridden columns of `schema`
+    pub fn apply(&self, table_name: &str, schema: &mut Schema) -> Result<()> {
+        let Some(columns) = self.0.get(table_name) else {
+            return Ok(());
+        };
+        for (column_name, column) in columns {
+            let Some(def) = schema
+                .columns
+                .iter_mut()
+                .find(|c| c.column_name.eq_ignore_ascii_case(column_name))
+            else {
+                bail!(
+                    "Cannot override the type of unknown column `{column_name}` of `{table_name}`"
+                );
+            };
+            def.column_type = match column.column_type {
+                TargetType::Utf8 => ColumnType::String,
+                TargetType::Int64 => ColumnType::Integer,
+                TargetType::Float64 => ColumnType::Float,
+                TargetType::Timestamp => ColumnType::Timestamp,
+                TargetType::Boolean => ColumnType::Boolean,
+            };
+            def.cast = Some(column.cast.clone());
+        }
+        Ok(())
+    }
+
+    /// Whether a column of `table_name` is overridden
+    pub fn contains(&self, table_name: &str, column_name: &str) -> bool {
+        self.0.get(table_name).is_some_and(|columns| {
+            columns
+                .keys()
+                .any(|name| name.eq_ignore_ascii_case(column_name))
+        })
+    }
+}
+
+impl Cast {
+    /// Convert `value` to a value of `column_type`, timestamps being numbers of
+    /// [`Cast::time_unit`] since 1970
+    pub fn convert(&self, value: ColumnValue, column_type: &ColumnType) -> Result<ColumnValue> {
+        Ok(match (column_type, value) {
+            (_, ColumnValue::Null) => ColumnValue::Null,
+            (ColumnType::String, ColumnValue::String(s)) => ColumnValue::String(s),
+            (ColumnType::String, ColumnValue::Integer(i)) => ColumnValue::String(i.to_string()),
+            (ColumnType::String, ColumnValue::Float(f)) => ColumnValue::String(f.to_string()),
+            (ColumnType::String, ColumnValue::Boolean(b)) => ColumnValue::String(b.to_string()),
+            (ColumnType::Integer, ColumnValue::Integer(i)) => ColumnValue::Integer(i),
+            (ColumnType::Integer, ColumnValue::String(s)) => {
+                ColumnValue::Integer(s.trim().parse().with_context(|| format!("`{s}`"))?)
+            }
+            (ColumnType::Integer, ColumnValue::Float(f)) if f.fract() == 0.0 => {
+                ColumnValue::Integer(f as i64)
+            }
+            (ColumnType::Integer, ColumnValue::Boolean(b)) => ColumnValue::Integer(b.into()),
+            (ColumnType::Float, ColumnValue::Float(f)) => ColumnValue::Float(f),
+            (ColumnType::Float, ColumnValue::Integer(i)) => ColumnValue::Float(i as f64),
+            (ColumnType::Float, ColumnValue::String(s)) => {
+                ColumnValue::Float(s.trim().parse().with_context(|| format!("`{s}`"))?)
+            }
+            (ColumnType::Boolean, ColumnValue::Boolean(b)) => ColumnValue::Boolean(b),
+            (ColumnType::Boolean, ColumnValue::Integer(i)) => ColumnValue::Boolean(i != 0),
+            (ColumnType::Boolean, ColumnValue::String(s)) => {
+                match s.trim().to_ascii_lowercase().as_str() {
+                    "1" | "true" => ColumnValue::Boolean(true),
+                    "0" | "false" => ColumnValue::Boolean(false),
+                    _ => bail!("`{s}` is not a boolean"),
+                }
+            }
+            (ColumnType::Timestamp, value) => ColumnValue::Integer(self.timestamp(value)?),
+            (_, value) => bail!("{value:?} cannot be converted to {column_type:?}"),
+        })
+    }
+
+    /// Unit of the timestamps given by the cast: epoch milli and microseconds are kept
+    /// rather than truncated to seconds
+    pub fn time_unit(&self) -> TimeUnit {
+        match self {
+            Cast::EpochMillis => TimeUnit::Millisecond,
+            Cast::EpochMicros => TimeUnit::Microsecond,
+            Cast::Default | Cast::EpochSeconds | Cast::Format(_) => TimeUnit::Second,
+        }
+    }
+
+    /// Timestamp in the [`Cast::time_unit`] of the cast
+    fn timestamp(&self, value: ColumnValue) -> Result<i64> {
+        let epoch = |value: ColumnValue| -> Result<i64> {
+            match value {
+                ColumnValue::Integer(i) => Ok(i),
+                ColumnValue::String(s) => s.trim().parse().with_context(|| format!("`{s}`")),
+                value => bail!("{value:?} is not a number"),
+            }
+        };
+        match self {
+            Cast::EpochSeconds | Cast::EpochMillis | Cast::EpochMicros => epoch(value),
+            Cast::Format(format) => match value {
+                ColumnValue::String(s) => parse_timestamp(&s, &[format]),
+                value => bail!("{value:?} is not a string"),
+            },
+            Cast::Default => match value {
+                ColumnValue::String(s) => {
+                    parse_timestamp(&s, &["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d"])
+                }
+                ColumnValue::Integer(i) => Ok(i),
+                value => bail!("{value:?} is not a timestamp"),
+            },
+        }
+    }
+}
+
+/// Seconds since 1970 of a date time or a date, in UTC
+fn parse_timestamp(value: &str, formats: &[&str]) -> Result<i64> {
+    for format in formats {
+        if let Ok(datetime) = NaiveDateTime::parse_from_str(value, format) {
+            return Ok(datetime.and_utc().timestamp());
+        }
+        if let Ok(date) = NaiveDate::parse_from_str(value, format) {
+            return Ok(date.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp());
+        }
+    }
+    bail!("`{value}` does not match {}", formats.join(" nor "))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::testing::TempDir;
+    use arrow::datatypes::{DataType, TimeUnit};
+
+    use super::{Cast, SchemaOverrides};
+    use crate::line_parser::{parse_line, ColumnType, ColumnValue, Line};
+
+    #[test]
+    fn override_types() {
+        let overrides: SchemaOverrides = serde_yaml::from_str(
+            r#"
+            user:
+              created_at: {type: timestamp, cast: epoch_millis}
+              birth_date: {type: timestamp, cast: "%d/%m/%Y"}
+              Active: {type: bool}
+            "#,
+        )
+        .unwrap();
+        let Line::CreateTable(_, mut schema) = parse_line(
+            "CREATE TABLE user (id INT, created_at BIGINT, birth_date VARCHAR(10), active TINYINT(1))",
+        )
+        .unwrap() else {
+            panic!("Not a CREATE TABLE statement");
+        };
+        overrides.apply("user", &mut schema).unwrap();
+        overrides.apply("other", &mut schema).unwrap();
+        let types: Vec<_> = schema.columns.iter().map(|c| &c.column_type).collect();
+        assert_eq!(
+            types,
+            [
+                &ColumnType::Integer,
+                &ColumnType::Timestamp,
+                &ColumnType::Timestamp,
+                &ColumnType::Boolean
+            ]
+        );
+        assert_eq!(schema.columns[0].cast, None);
+        assert_eq!(schema.columns[1].cast, Some(Cast::EpochMillis));
+        assert_eq!(
+            schema.to_arrow_schema().field(1).data_type(),
+            &DataType::Timestamp(TimeUnit::Millisecond, None)
+        );
+        assert!(overrides.contains("user", "ACTIVE"));
+
+        let toml: SchemaOverrides = toml::from_str("[user.name]\ntype = \"utf8\"").unwrap();
+        assert_eq!(
+            toml.apply("user", &mut schema).unwrap_err().to_string(),
+            "Cannot override the type of unknown column `name` of `user`"
+        );
+        assert!(toml::from_str::<SchemaOverrides>("[user.id]\ntype = \"date\"").is_err());
+        assert!(
+            toml::from_str::<SchemaOverrides>("[user.id]\ntype = \"utf8\"\ncast = \"x\"").is_err()
+        );
+    }
+
+    #[test]
+    fn load_overrides() {
+        let dir = TempDir::new("overrides");
+        let path = dir.join("overrides.toml");
+        std::fs::write(&path, "[user.id]\ntype = \"utf8\"\ncast = \"epoch_millis\"").unwrap();
+        assert_eq!(
+            SchemaOverrides::load(&path).unwrap_err().to_string(),
+            "Only timestamps can be cast, not user.id"
+        );
+        std::fs::write(
+            &path,
+            "[user.id]\ntype = \"timestamp\"\ncast = \"epoch_millis\"",
+        )
+        .unwrap();
+        let overrides = SchemaOverrides::load(&path).unwrap();
+        let Line::CreateTable(_, mut schema) = parse_line("CREATE TABLE user (id BIGINT)").unwrap()
+        else {
+            panic!("Not a CREATE TABLE statement");
+        };
+        overrides.apply("user", &mut schema).unwrap();
+        assert_eq!(schema.columns[0].cast, Some(Cast::EpochMillis));
+    }
+
+    #[test]
+    fn cast_values() {
+        let convert = |cast: Cast, value: ColumnValue, column_type: ColumnType| {
+            cast.convert(value, &column_type).ok()
+        };
+        assert_eq!(
+            convert(
+                Cast::EpochMillis,
+                ColumnValue::Integer(1_700_000_000_999),
+                ColumnType::Timestamp
+            ),
+            Some(ColumnValue::Integer(1_700_000_000_999))
+        );
+        assert_eq!(Cast::EpochMicros.time_unit(), TimeUnit::Microsecond);
+        assert_eq!(
+            convert(
+                Cast::Format("%d/%m/%Y".into()),
+                ColumnValue::String("02/01/2024".into()),
+                ColumnType::Timestamp
+            ),
+            Some(ColumnValue::Integer(1_704_153_600))
+        );
+        assert_eq!(
+            convert(
+                Cast::Default,
+                ColumnValue::String("2024-01-02".into()),
+                ColumnType::Timestamp
+            ),
+            Some(ColumnValue::Integer(1_704_153_600))
+        );
+        assert_eq!(
+            convert(
+                Cast::Default,
+                ColumnValue::String("2024-01-02T00:00:01".into()),
+                ColumnType::Timestamp
+            ),
+            Some(ColumnValue::Integer(1_704_153_601))
+        );
+        assert_eq!(
+            convert(Cast::Default, ColumnValue::Integer(1), ColumnType::Boolean),
+            Some(ColumnValue::Boolean(true))
+        );
+        assert_eq!(
+            convert(
+                Cast::Default,
+                ColumnValue::String(" 42 ".into()),
+                ColumnType::Integer
+            ),
+            Some(ColumnValue::Integer(42))
+        );
+        assert_eq!(
+            convert(Cast::Default, ColumnValue::Float(1.5), ColumnType::String),
+            Some(ColumnValue::String("1.5".into()))
+        );
+        assert_eq!(
+            convert(Cast::Default, ColumnValue::Null, ColumnType::Timestamp),
+            Some(ColumnValue::Null)
+        );
+        assert_eq!(
+            convert(
+                Cast::Default,
+                ColumnValue::String("yes".into()),
+                ColumnType::Boolean
+            ),
+            None
+        );
+        assert_eq!(
+            convert(
+                Cast::Default,
+                ColumnValue::String("01/02/2024".into()),
+                ColumnType::Timestamp
+            ),
+            None
+        );
+    }
+}
@@ -1,114 +1,881 @@
 use std::{
-    fs::File,
-    path::PathBuf,
+    collections::HashMap,
+    fs::create_dir_all,
+    path::{Path, PathBuf},
+    sync::Arc,
     thread::{self, JoinHandle},
 };
 
 use arrow::{
     array::{
-        make_builder, ArrayBuilder, ArrayRef, BooleanBuilder, Float64Builder, Int64Builder,
-        StringBuilder, TimestampSecondBuilder,
+        make_builder, ArrayBuilder, ArrayRef, BooleanBuilder, Decimal128Builder, Decimal256Builder,
+        Float64Builder, Int64Builder, StringBuilder, TimestampMicrosecondBuilder,
+        TimestampMillisecondBuilder, TimestampSecondBuilder,
     },
-    datatypes::SchemaRef,
+    compute::kernels::cast_utils::parse_decimal,
+    datatypes::{Decimal128Type, Decimal256Type, SchemaRef, TimeUnit},
     record_batch::RecordBatch,
 };
-use chrono::{NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use clap::Args;
+use color_eyre::eyre::{bail, eyre, Context, Result};
 use indicatif::ProgressBar;
-use parquet::{arrow::ArrowWriter, basic::Compression, file::properties::WriterProperties};
+use parquet::{
+    basic::{Compression, Encoding},
+    file::properties::{EnabledStatistics, WriterProperties, WriterVersion},
+    format::{KeyValue, SortingColumn},
+    schema::types::ColumnPath,
+};
+use serde::Deserialize;
+
+use crate::{
+    config::Config,
+    filter::{TableFilter, TableRows},
+    line_parser::{ColumnDef, ColumnKey, ColumnType, ColumnValue, Line, Schema, CREATE_TABLE_KEY},
+    manifest::{self, PartInfo},
+    memory::{self, HeapSize},
+    partition::PartitionedWriter,
+    sink::{self, Format, Sink, SinkOptions},
+    sort::ExternalSorter,
+};
+
+/// Parquet writer settings.
+///
+/// Every setting is optional so they can be layered: column, table, command line and
+/// finally the global section of the configuration file.
+#[derive(Clone, Debug, Default, Args, Deserialize)]
+#[serde(try_from = "RawWriterSettings")]
+pub struct WriterSettings {
+    /// Compression codec: uncompressed, snappy, lz4, lz4_raw, zstd(LEVEL), gzip(LEVEL) or
+    /// brotli(LEVEL) [default: snappy]
+    #[arg(long, value_parser = parse_compression)]
+    pub compression: Option<Compression>,
+    /// Fallback encoding of columns: plain, delta_binary_packed, delta_length_byte_array,
+    /// delta_byte_array or byte_stream_split
+    #[arg(long, value_parser = parse_encoding)]
+    pub encoding: Option<Encoding>,
+    /// Enable dictionary encoding [default: true]
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    pub dictionary: Option<bool>,
+    /// Write bloom filters [default: false]
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    pub bloom_filter: Option<bool>,
+    /// Write bloom filters for PRIMARY KEY and UNIQUE KEY columns [default: false]
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    pub bloom_filter_keys: Option<bool>,
+    /// False positive probability of bloom filters [default: 0.05]
+    #[arg(long)]
+    pub bloom_filter_fpp: Option<f64>,
+    /// Expected number of distinct values in a row group, used to size bloom filters
+    /// [default: 1000000]
+    #[arg(long)]
+    pub bloom_filter_ndv: Option<u64>,
+    /// Statistics level: none, chunk or page [default: page]
+    #[arg(long, value_parser = parse_statistics)]
+    pub statistics: Option<EnabledStatistics>,
+    /// Data page size limit (e.g. 1M) [default: 1M]
+    #[arg(long, value_parser = memory::parse_byte_size)]
+    pub data_page_size: Option<usize>,
+    /// Dictionary page size limit (e.g. 1M) [default: 1M]
+    #[arg(long, value_parser = memory::parse_byte_size)]
+    pub dictionary_page_size: Option<usize>,
+    /// Parquet format version: 1 or 2 [default: 1]
+    #[arg(long, value_parser = parse_writer_version)]
+    pub writer_version: Option<WriterVersion>,
+    /// Maximum number of rows per row group [default: 1048576]
+    #[arg(long)]
+    pub max_row_group_size: Option<usize>,
+    /// Split tables into `table/part-NNNNN.parquet` files of about this size (e.g. 512M)
+    #[arg(long, value_parser = parse_file_size)]
+    pub max_file_size: Option<usize>,
+    /// Split tables into `table/part-NNNNN.parquet` files of at most this many rows
+    #[arg(long, value_parser = parse_rows_per_file)]
+    pub max_rows_per_file: Option<usize>,
+}
+
+impl WriterSettings {
+    /// Fill unset settings from `fallback`
+    pub fn or(&self, fallback: &WriterSettings) -> WriterSettings {
+        WriterSettings {
+            compression: self.compression.or(fallback.compression),
+            encoding: self.encoding.or(fallback.encoding),
+            dictionary: self.dictionary.or(fallback.dictionary),
+            bloom_filter: self.bloom_filter.or(fallback.bloom_filter),
+            bloom_filter_keys: self.bloom_filter_keys.or(fallback.bloom_filter_keys),
+            bloom_filter_fpp: self.bloom_filter_fpp.or(fallback.bloom_filter_fpp),
+            bloom_filter_ndv: self.bloom_filter_ndv.or(fallback.bloom_filter_ndv),
+            statistics: self.statistics.or(fallback.statistics),
+            data_page_size: self.data_page_size.or(fallback.data_page_size),
+            dictionary_page_size: self.dictionary_page_size.or(fallback.dictionary_page_size),
+            writer_version: self.writer_version.or(fallback.writer_version),
+            max_row_group_size: self.max_row_group_size.or(fallback.max_row_group_size),
+            max_file_size: self.max_file_size.or(fallback.max_file_size),
+            max_rows_per_file: self.max_rows_per_file.or(fallback.max_rows_per_file),
+        }
+    }
+
+    /// Whether only settings that parquet supports per column are set
+    pub fn is_column_setting(&self) -> bool {
+        self.data_page_size.is_none()
+            && self.dictionary_page_size.is_none()
+            && self.writer_version.is_none()
+            && self.max_row_group_size.is_none()
+            && self.bloom_filter_keys.is_none()
+            && !self.splits_files()
+    }
+
+    /// Whether tables are split into several files
+    pub fn splits_files(&self) -> bool {
+        self.max_file_size.is_some() || self.max_rows_per_file.is_some()
+    }
+}
+
+pub fn parse_compression(value: &str) -> Result<Compression, String> {
+    // levels are optional
+    match value.to_ascii_lowercase().as_str() {
+        "zstd" => Ok(Compression::ZSTD(Default::default())),
+        "gzip" => Ok(Compression::GZIP(Default::default())),
+        "brotli" => Ok(Compression::BROTLI(Default::default())),
+        "none" => Ok(Compression::UNCOMPRESSED),
+        value => value.parse().map_err(|e| format!("{e}")),
+    }
+}
+
+pub fn parse_encoding(value: &str) -> Result<Encoding, String> {
+    match value.to_ascii_lowercase().parse() {
+        Ok(Encoding::PLAIN_DICTIONARY | Encoding::RLE_DICTIONARY) => {
+            Err("dictionary encoding is enabled with the dictionary setting".into())
+        }
+        Ok(encoding) => Ok(encoding),
+        Err(e) => Err(format!("{e}")),
+    }
+}
+
+pub fn parse_statistics(value: &str) -> Result<EnabledStatistics, String> {
+    value.to_ascii_lowercase().parse()
+}
+
+pub fn parse_writer_version(value: &str) -> Result<WriterVersion, String> {
+    match value {
+        "1" | "1.0" => Ok(WriterVersion::PARQUET_1_0),
+        "2" | "2.0" => Ok(WriterVersion::PARQUET_2_0),
+        value => value.parse(),
+    }
+}
+
+/// Size of the parts of a table, which cannot be empty
+pub fn parse_file_size(value: &str) -> Result<usize, String> {
+    match memory::parse_byte_size(value)? {
+        0 => Err(format!("Parts must be larger than `{value}`")),
+        size => Ok(size),
+    }
+}
+
+/// Rows of the parts of a table, which cannot be empty
+pub fn parse_rows_per_file(value: &str) -> Result<usize, String> {
+    match value.trim().parse() {
+        Ok(0) => Err("Parts must hold at least one row".to_string()),
+        Ok(rows) => Ok(rows),
+        Err(_) => Err(format!("Invalid number of rows `{value}`")),
+    }
+}
+
+/// Settings as written in the configuration file, numbers may be strings (`"1M"`)
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawWriterSettings {
+    compression: Option<String>,
+    encoding: Option<String>,
+    dictionary: Option<bool>,
+    bloom_filter: Option<bool>,
+    bloom_filter_keys: Option<bool>,
+    bloom_filter_fpp: Option<f64>,
+    bloom_filter_ndv: Option<u64>,
+    statistics: Option<String>,
+    data_page_size: Option<Scalar>,
+    dictionary_page_size: Option<Scalar>,
+    writer_version: Option<Scalar>,
+    max_row_group_size: Option<usize>,
+    max_file_size: Option<Scalar>,
+    max_rows_per_file: Option<Scalar>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Scalar {
+    Number(usize),
+    String(String),
+}
+
+impl Scalar {
+    fn parse<T>(self, parser: fn(&str) -> Result<T, String>) -> Result<T, String> {
+        match self {
+            Scalar::Number(n) => parser(&n.to_string()),
+            Scalar::String(s) => parser(&s),
+        }
+    }
+}
+
+impl TryFrom<RawWriterSettings> for WriterSettings {
+    type Error = String;
+
+    fn try_from(raw: RawWriterSettings) -> Result<Self, Self::Error> {
+        Ok(WriterSettings {
+            compression: raw
+                .compression
+                .as_deref()
+                .map(parse_compression)
+                .transpose()?,
+            encoding: raw.encoding.as_deref().map(parse_encoding).transpose()?,
+            dictionary: raw.dictionary,
+            bloom_filter: raw.bloom_filter,
+            bloom_filter_keys: raw.bloom_filter_keys,
+            bloom_filter_fpp: raw.bloom_filter_fpp,
+            bloom_filter_ndv: raw.bloom_filter_ndv,
+            statistics: raw
+                .statistics
+                .as_deref()
+                .map(parse_statistics)
+                .transpose()?,
+            data_page_size: raw
+                .data_page_size
+                .map(|s| s.parse(memory::parse_byte_size))
+                .transpose()?,
+            dictionary_page_size: raw
+                .dictionary_page_size
+                .map(|s| s.parse(memory::parse_byte_size))
+                .transpose()?,
+            writer_version: raw
+                .writer_version
+                .map(|s| s.parse(parse_writer_version))
+                .transpose()?,
+            max_row_group_size: raw.max_row_group_size,
+            max_file_size: raw
+                .max_file_size
+                .map(|s| s.parse(parse_file_size))
+                .transpose()?,
+            max_rows_per_file: raw
+                .max_rows_per_file
+                .map(|s| s.parse(parse_rows_per_file))
+                .transpose()?,
+        })
+    }
+}
 
-use crate::line_parser::{ColumnDef, ColumnValue, Line, Schema};
+/// Build the writer properties of `table_name` from the configuration.
+fn writer_properties(
+    config: &Config,
+    table_name: &str,
+    schema: &Schema,
+    arrow_schema: &arrow::datatypes::Schema,
+    sort_columns: &[usize],
+    key_value_metadata: Vec<KeyValue>,
+) -> WriterProperties {
+    let table = config.table(table_name);
+    let settings = config.writer_settings(table_name);
+    let mut builder = WriterProperties::builder()
+        .set_compression(settings.compression.unwrap_or(Compression::SNAPPY))
+        .set_key_value_metadata(Some(key_value_metadata).filter(|kv| !kv.is_empty()));
+    if let Some(encoding) = settings.encoding {
+        builder = builder.set_encoding(encoding);
+    }
+    if let Some(dictionary) = settings.dictionary {
+        builder = builder.set_dictionary_enabled(dictionary);
+    }
+    if let Some(statistics) = settings.statistics {
+        builder = builder.set_statistics_enabled(statistics);
+    }
+    if let Some(data_page_size) = settings.data_page_size {
+        builder = builder.set_data_page_size_limit(data_page_size);
+    }
+    if let Some(dictionary_page_size) = settings.dictionary_page_size {
+        builder = builder.set_dictionary_page_size_limit(dictionary_page_size);
+    }
+    if let Some(writer_version) = settings.writer_version {
+        builder = builder.set_writer_version(writer_version);
+    }
+    if let Some(max_row_group_size) = settings.max_row_group_size {
+        builder = builder.set_max_row_group_size(max_row_group_size);
+    }
+    if !sort_columns.is_empty() {
+        builder = builder.set_sorting_columns(Some(
+            sort_columns
+                .iter()
+                .map(|i| SortingColumn::new(*i as i32, false, true))
+                .collect(),
+        ));
+    }
+    for (column, field) in schema.columns.iter().zip(arrow_schema.fields()) {
+        let path = ColumnPath::from(field.name().as_str());
+        let column_settings = table.and_then(|table| table.column(&column.column_name));
+        let bloom_settings = column_settings
+            .map(|column| column.parquet.or(&settings))
+            .unwrap_or_else(|| settings.clone());
+        let key_bloom_filter = settings.bloom_filter_keys == Some(true)
+            && column.key != ColumnKey::None
+            && column_settings
+                .and_then(|c| c.parquet.bloom_filter)
+                .is_none();
+        if key_bloom_filter || bloom_settings.bloom_filter == Some(true) {
+            builder = builder.set_column_bloom_filter_enabled(path.clone(), true);
+            if let Some(fpp) = bloom_settings.bloom_filter_fpp {
+                builder = builder.set_column_bloom_filter_fpp(path.clone(), fpp);
+            }
+            if let Some(ndv) = bloom_settings.bloom_filter_ndv {
+                builder = builder.set_column_bloom_filter_ndv(path.clone(), ndv);
+            }
+        }
+        let Some(column) = column_settings else {
+            continue;
+        };
+        let settings = &column.parquet;
+        if let Some(compression) = settings.compression {
+            builder = builder.set_column_compression(path.clone(), compression);
+        }
+        if let Some(encoding) = settings.encoding {
+            builder = builder.set_column_encoding(path.clone(), encoding);
+        }
+        if let Some(dictionary) = settings.dictionary {
+            builder = builder.set_column_dictionary_enabled(path.clone(), dictionary);
+        }
+        if let Some(statistics) = settings.statistics {
+            builder = builder.set_column_statistics_enabled(path, statistics);
+        }
+    }
+    builder.build()
+}
 
 pub struct ParquetWriter {
     output_dir: PathBuf,
-    current_writer: Option<CurrentParquetWriter>,
+    /// Open writers, kept until the end so a table can span several input files
+    writers: HashMap<String, TableWriter>,
+    /// rows received before the CREATE TABLE statement of their table, when input files
+    /// are read in parallel
+    pending_rows: HashMap<String, Vec<Vec<ColumnValue>>>,
+    /// estimated size of `pending_rows`, counted in `max_buffered_bytes`
+    pending_bytes: usize,
+    /// whether CREATE TABLE statements may come after rows of their table
+    unordered: bool,
+    /// rows and columns kept, by table
+    table_rows: HashMap<String, TableRows>,
+    /// limits and subset, shared with the readers
+    tables: TableFilter,
+    /// flush the biggest row groups when open writers buffer more than this
+    max_buffered_bytes: usize,
+    config: Arc<Config>,
     progress_bar: ProgressBar,
 }
 
+/// Writer of the rows of a table: a single file, parts, or one writer per partition
+// one per table, the size of variants does not matter
+#[allow(clippy::large_enum_variant)]
+pub enum TableWriter {
+    Single(CurrentParquetWriter),
+    Partitioned(PartitionedWriter),
+}
+
+/// Where the rows of a table are written
+pub enum OutputFiles {
+    /// a single file
+    File(PathBuf),
+    /// `{dir}/{prefix}00000.parquet`, `{dir}/{prefix}00001.parquet`... (or the extension
+    /// of the output format) rolling over to the next part when `max_file_size` or
+    /// `max_rows_per_file` is reached
+    Parts { dir: PathBuf, prefix: String },
+}
+
 pub struct CurrentParquetWriter {
     row_count: usize,
-    table_name: String,
     schema: Schema,
     arrow_schema: SchemaRef,
-    arrow_writer: ArrowWriter<File>,
+    options: SinkOptions,
+    output: OutputFiles,
+    max_file_size: Option<usize>,
+    max_rows_per_file: Option<usize>,
+    /// part being written, opened when rows come after a roll over
+    part: Option<Part>,
+    /// closed parts
+    parts: Vec<PartInfo>,
+    /// rows are sorted before being written when a sort key is configured
+    sorter: Option<ExternalSorter>,
 }
 
-impl Drop for ParquetWriter {
-    fn drop(&mut self) {
-        let current_writer = self.current_writer.take();
-        if let Some(current_writer) = current_writer {
-            current_writer.finish();
-        }
-        self.progress_bar
-            .set_message("Done writing parquet file(s).");
-        self.progress_bar.finish();
-    }
+struct Part {
+    sink: Box<dyn Sink>,
+    path: PathBuf,
+    row_count: usize,
 }
 
 impl ParquetWriter {
+    /// Start the writer thread.
+    ///
+    /// `max_queued_bytes` bounds the parsed lines waiting to be written and
+    /// `max_buffered_bytes` the data buffered in open row groups. Written rows are
+    /// counted in the limits of `tables`. Rows of a table can only come before its
+    /// `CREATE TABLE` statement when `unordered`, files being read in parallel: they are
+    /// then kept until it comes. The thread stops on the first error, such as
+    /// an invalid table configuration, returned when joined.
     pub fn start(
         output_dir: PathBuf,
+        max_queued_bytes: usize,
+        max_buffered_bytes: usize,
+        config: Arc<Config>,
+        tables: TableFilter,
+        unordered: bool,
         progress_bar: ProgressBar,
-    ) -> (crossbeam::channel::Sender<Line>, JoinHandle<()>) {
-        let (sender, receiver) = crossbeam::channel::bounded(100);
+    ) -> (memory::Sender<Line>, JoinHandle<Result<()>>) {
+        let (sender, receiver) = memory::bounded(max_queued_bytes);
 
         let writer_thread_join_handle = thread::spawn(move || {
             let mut w = ParquetWriter {
                 output_dir,
                 progress_bar,
-                current_writer: None,
+                writers: HashMap::new(),
+                pending_rows: HashMap::new(),
+                pending_bytes: 0,
+                unordered,
+                table_rows: HashMap::new(),
+                tables,
+                max_buffered_bytes,
+                config,
             };
             while let Ok(line) = receiver.recv() {
-                w.new_line(line);
+                w.new_line(line)?;
             }
+            w.finish()
         });
         (sender, writer_thread_join_handle)
     }
 
-    fn new_line(&mut self, line: Line) {
+    fn new_line(&mut self, line: Line) -> Result<()> {
         match line {
-            Line::CreateTable(table_name, schema) => {
+            Line::CreateTable(table_name, mut schema) => {
                 self.progress_bar.set_message(format!("`{table_name}`"));
-                // build Arrow schema
-                let arrow_schema = SchemaRef::from(schema.to_arrow_schema());
-                // build ArrowWriter
-                let props = WriterProperties::builder()
-                    .set_compression(Compression::SNAPPY)
-                    .build();
-                let file_name = format!("{table_name}.parquet");
-                let file_path = self.output_dir.join(file_name);
-                let file = File::create(file_path).unwrap();
-                let arrow_writer =
-                    ArrowWriter::try_new(file, arrow_schema.clone(), Some(props)).unwrap();
-                let previous_writer = self.current_writer.replace(CurrentParquetWriter {
-                    row_count: 0,
-                    table_name,
-                    arrow_schema,
-                    arrow_writer,
-                    schema,
-                });
-                if let Some(preview_writer) = previous_writer {
-                    preview_writer.finish();
+                let table_rows = self
+                    .config
+                    .table_rows(&table_name, &mut schema, &self.tables)?;
+                if let Some(writer) = self.writers.get(&table_name) {
+                    // same table found in another input file: its rows, such as those
+                    // of a sample, are kept
+                    if writer.schema().columns != schema.columns {
+                        bail!("Table `{table_name}` is created twice with different schemas");
+                    }
+                } else {
+                    let key_value_metadata = table_rows.key_value_metadata();
+                    self.table_rows.insert(table_name.clone(), table_rows);
+                    let output_name = self.config.output_name(&table_name);
+                    if let Some(other) = self
+                        .writers
+                        .keys()
+                        .find(|other| self.config.output_name(other) == output_name)
+                    {
+                        bail!("Tables `{other}` and `{table_name}` would both be written to `{output_name}`");
+                    }
+                    let writer = TableWriter::create(
+                        &self.output_dir,
+                        &table_name,
+                        schema,
+                        key_value_metadata,
+                        &self.config,
+                        None,
+                    )?;
+                    self.writers.insert(table_name.clone(), writer);
+                }
+                if let Some(rows) = self.pending_rows.remove(&table_name) {
+                    self.pending_bytes -= rows.heap_size();
+                    self.write_rows(&table_name, rows)?;
                 }
             }
             Line::InsertInto(table_name, rows) => {
-                if Some(&table_name) != self.current_writer.as_ref().map(|w| &w.table_name) {
-                    eprintln!("Received a line from an unknown table: CREATE TABLE statement must precede any INSERT INTO.");
+                if self.writers.contains_key(&table_name) {
+                    self.write_rows(&table_name, rows)?;
+                } else if self.unordered {
+                    // CREATE TABLE statement may come from a file read in parallel
+                    self.pending_bytes += rows.heap_size();
+                    if self.pending_bytes >= self.max_buffered_bytes {
+                        bail!(
+                            "Rows of table `{table_name}` waiting for its CREATE TABLE \
+                             statement exceed the memory budget: is it in the input files?"
+                        );
+                    }
+                    self.pending_rows
+                        .entry(table_name)
+                        .or_default()
+                        .extend(rows);
                 } else {
-                    // INSERT DATA, by construction there is a current writer ;)
-                    let current_writer = self.current_writer.as_mut().unwrap();
-                    let row_count = rows.len();
-
-                    current_writer.write_rows(rows);
-                    self.progress_bar.inc(row_count as u64);
-                    current_writer.row_count += row_count;
+                    bail!("No CREATE TABLE statement for table `{table_name}` before its rows");
                 }
             }
             Line::NOP => {}
         }
+        Ok(())
+    }
+
+    /// Write the rows of samples and close every table, committing or listing their
+    /// files
+    fn finish(mut self) -> Result<()> {
+        if let Some((table_name, rows)) = self.pending_rows.iter().next() {
+            bail!(
+                "No CREATE TABLE statement for table `{table_name}` and its {} rows",
+                rows.len()
+            );
+        }
+        for (table_name, mut writer) in self.writers.drain() {
+            let rows = self.table_rows[&table_name].sampled_rows();
+            if !rows.is_empty() {
+                self.progress_bar.inc(rows.len() as u64);
+                writer.write_rows(rows)?;
+            }
+            let dataset = matches!(writer, TableWriter::Partitioned(_))
+                || self.config.writer_settings(&table_name).splits_files();
+            let schema = writer.schema().clone();
+            let parts = writer.finish()?;
+            if self.config.format.commits_tables() {
+                self.config.format.commit(
+                    &self.output_dir,
+                    &table_name,
+                    &schema,
+                    &self.config,
+                    &parts,
+                )?;
+            } else if dataset {
+                let output_name = self.config.output_name(&table_name);
+                manifest::write(&self.output_dir.join(&output_name), &output_name, &parts)?;
+            }
+        }
+        self.config
+            .check_projected_tables(&self.tables, |table_name| {
+                self.table_rows.contains_key(table_name)
+            })?;
+        self.progress_bar
+            .set_message("Done writing parquet file(s).");
+        self.progress_bar.finish();
+        Ok(())
+    }
+
+    fn write_rows(&mut self, table_name: &str, mut rows: Vec<Vec<ColumnValue>>) -> Result<()> {
+        self.table_rows[table_name].apply(&mut rows);
+        let writer = self.writers.get_mut(table_name).unwrap();
+        let row_count = rows.len();
+        writer.write_rows(rows)?;
+        self.progress_bar.inc(row_count as u64);
+
+        // close the biggest row groups early rather than exceeding the memory budget
+        while self
+            .writers
+            .values()
+            .map(TableWriter::buffered_size)
+            .sum::<usize>()
+            >= self.max_buffered_bytes
+        {
+            let biggest = self
+                .writers
+                .values_mut()
+                .max_by_key(|w| w.buffered_size())
+                .unwrap();
+            if biggest.buffered_size() == 0 {
+                break;
+            }
+            biggest.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl TableWriter {
+    /// Create the writer of `table_name` in `output_dir`.
+    ///
+    /// mydumper `chunk`s of a table are written to the same dataset, their files being
+    /// numbered after the chunk: `part-00003.parquet`, or `part-00003-00000.parquet`,
+    /// `part-00003-00001.parquet`... when split.
+    pub fn create(
+        output_dir: &Path,
+        table_name: &str,
+        schema: Schema,
+        key_value_metadata: Vec<KeyValue>,
+        config: &Config,
+        chunk: Option<usize>,
+    ) -> Result<Self> {
+        if config.format.is_database() {
+            // `output_dir` is the database
+            return Ok(TableWriter::Single(CurrentParquetWriter::create(
+                OutputFiles::File(output_dir.to_path_buf()),
+                table_name,
+                schema,
+                key_value_metadata,
+                config,
+            )?));
+        }
+        let output_name = config.output_name(table_name);
+        let table_dir = config.format.data_dir(&output_dir.join(&output_name));
+        let extension = config.extension();
+        let mut prefix = match chunk {
+            Some(chunk) => format!("part-{chunk:05}"),
+            None => "part".to_string(),
+        };
+        if config.format == Format::Delta {
+            // later conversions add files to the same table
+            prefix = format!("{prefix}-{}", sink::random_uuid());
+        }
+        if config
+            .table(table_name)
+            .is_some_and(|table| table.partition_by.is_some())
+        {
+            return Ok(TableWriter::Partitioned(PartitionedWriter::create(
+                table_dir,
+                format!("{prefix}-"),
+                table_name,
+                schema,
+                key_value_metadata,
+                config,
+            )?));
+        }
+        let output =
+            if config.format.is_table() || config.writer_settings(table_name).splits_files() {
+                create_dir_all(&table_dir).with_context(|| {
+                    format!("Cannot create output directory {}", table_dir.display())
+                })?;
+                OutputFiles::Parts {
+                    dir: table_dir,
+                    prefix: format!("{prefix}-"),
+                }
+            } else if chunk.is_some() {
+                OutputFiles::File(table_dir.join(format!("{prefix}.{extension}")))
+            } else {
+                OutputFiles::File(output_dir.join(format!("{output_name}.{extension}")))
+            };
+        Ok(TableWriter::Single(CurrentParquetWriter::create(
+            output,
+            table_name,
+            schema,
+            key_value_metadata,
+            config,
+        )?))
+    }
+
+    pub fn schema(&self) -> &Schema {
+        match self {
+            TableWriter::Single(writer) => &writer.schema,
+            TableWriter::Partitioned(writer) => writer.schema(),
+        }
+    }
+
+    pub fn write_rows(&mut self, rows: Vec<Vec<ColumnValue>>) -> Result<()> {
+        match self {
+            TableWriter::Single(writer) => writer.write_rows(rows),
+            TableWriter::Partitioned(writer) => writer.write_rows(rows),
+        }
+    }
+
+    /// Write rows, closing row groups early when they buffer more than
+    /// `max_buffered_bytes`.
+    pub fn write_bounded(
+        &mut self,
+        rows: Vec<Vec<ColumnValue>>,
+        max_buffered_bytes: usize,
+    ) -> Result<()> {
+        self.write_rows(rows)?;
+        if self.buffered_size() >= max_buffered_bytes {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Memory used by rows not written to disk yet
+    pub fn buffered_size(&self) -> usize {
+        match self {
+            TableWriter::Single(writer) => writer.buffered_size(),
+            TableWriter::Partitioned(writer) => writer.buffered_size(),
+        }
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        match self {
+            TableWriter::Single(writer) => writer.flush(),
+            TableWriter::Partitioned(writer) => writer.flush(),
+        }
+    }
+
+    /// Close the files of the table, returning the parts written, relative to the
+    /// table directory.
+    pub fn finish(self) -> Result<Vec<PartInfo>> {
+        match self {
+            TableWriter::Single(writer) => writer.finish(),
+            TableWriter::Partitioned(writer) => writer.finish(),
+        }
     }
 }
 
 impl CurrentParquetWriter {
+    /// Create the file(s) of `schema`
+    pub fn create(
+        output: OutputFiles,
+        table_name: &str,
+        schema: Schema,
+        mut key_value_metadata: Vec<KeyValue>,
+        config: &Config,
+    ) -> Result<Self> {
+        if !schema.create_table.is_empty() {
+            key_value_metadata.push(KeyValue::new(
+                CREATE_TABLE_KEY.to_string(),
+                schema.create_table.clone(),
+            ));
+        }
+        // build Arrow schema
+        let arrow_schema = SchemaRef::from(schema.to_arrow_schema());
+        let sort_columns: Vec<usize> = config
+            .table(table_name)
+            .map(|table| table.sort_by.as_slice())
+            .unwrap_or_default()
+            .iter()
+            .map(|sort_column| {
+                schema
+                    .columns
+                    .iter()
+                    .position(|c| c.column_name.eq_ignore_ascii_case(sort_column))
+                    .ok_or_else(|| {
+                        eyre!("Unknown column `{sort_column}` in the sort order of `{table_name}`")
+                    })
+            })
+            .collect::<Result<_>>()?;
+        let options = SinkOptions {
+            format: config.format,
+            parquet: writer_properties(
+                config,
+                table_name,
+                &schema,
+                &arrow_schema,
+                &sort_columns,
+                key_value_metadata.clone(),
+            ),
+            key_value_metadata,
+            text: config.text.clone(),
+            table_name: config.output_name(table_name),
+        };
+        let settings = config.writer_settings(table_name);
+        let (max_file_size, max_rows_per_file) = match output {
+            OutputFiles::File(_) => (None, None),
+            OutputFiles::Parts { .. } => (settings.max_file_size, settings.max_rows_per_file),
+        };
+        let mut writer = CurrentParquetWriter {
+            row_count: 0,
+            arrow_schema,
+            options,
+            output,
+            max_file_size,
+            max_rows_per_file,
+            part: None,
+            parts: Vec::new(),
+            schema,
+            sorter: None,
+        };
+        // create the first part right away, empty tables keep their schema
+        let mut spill_dir = writer.next_part_path().into_os_string();
+        writer.current_part()?;
+        writer.sorter = (!sort_columns.is_empty()).then(|| {
+            spill_dir.push(".sort");
+            ExternalSorter::new(writer.arrow_schema.clone(), sort_columns, spill_dir.into())
+        });
+        Ok(writer)
+    }
+
+    /// Memory used by rows not written to disk yet
+    pub fn buffered_size(&self) -> usize {
+        self.part
+            .as_ref()
+            .map_or(0, |part| part.sink.buffered_size())
+            + self
+                .sorter
+                .as_ref()
+                .map_or(0, ExternalSorter::buffered_size)
+    }
+
+    /// Write buffered rows to disk: close the current row group, or spill rows waiting
+    /// to be sorted.
+    pub fn flush(&mut self) -> Result<()> {
+        match (&mut self.sorter, &mut self.part) {
+            (Some(sorter), _) => sorter.spill().wrap_err("Cannot spill rows to sort"),
+            (None, Some(part)) => part
+                .sink
+                .flush()
+                .with_context(|| format!("Cannot write {}", part.path.display())),
+            (None, None) => Ok(()),
+        }
+    }
+
+    fn next_part_path(&self) -> PathBuf {
+        match &self.output {
+            OutputFiles::File(path) => path.clone(),
+            OutputFiles::Parts { dir, prefix } => dir.join(format!(
+                "{prefix}{:05}.{}",
+                self.parts.len(),
+                self.options.extension()
+            )),
+        }
+    }
+
+    /// Part being written, opening the next one if needed
+    fn current_part(&mut self) -> Result<&mut Part> {
+        if self.part.is_none() {
+            let path = self.next_part_path();
+            let sink = self
+                .options
+                .format
+                .create_table(&path, self.arrow_schema.clone(), &self.options)
+                .with_context(|| format!("Cannot create {}", path.display()))?;
+            self.part = Some(Part {
+                sink,
+                path,
+                row_count: 0,
+            });
+        }
+        Ok(self.part.as_mut().unwrap())
+    }
+
+    fn close_part(&mut self) -> Result<()> {
+        if let Some(part) = self.part.take() {
+            let info = part
+                .sink
+                .finish()
+                .with_context(|| format!("Cannot close {}", part.path.display()))?;
+            self.parts.push(info);
+        }
+        Ok(())
+    }
+
+    /// Whether the current part reached `max_rows_per_file` or `max_file_size`
+    fn part_is_full(&self) -> bool {
+        let Some(part) = &self.part else {
+            return false;
+        };
+        self.max_rows_per_file
+            .is_some_and(|max| part.row_count >= max)
+            || self.max_file_size.is_some_and(|max| {
+                part.sink.bytes_written() as usize + part.sink.buffered_size() >= max
+            })
+    }
+
+    /// Write `batch` to the current part, rolling over to the next parts when full
+    fn write_batch(&mut self, batch: &RecordBatch) -> Result<()> {
+        let mut offset = 0;
+        while offset < batch.num_rows() {
+            let max_rows_per_file = self.max_rows_per_file;
+            let part = self.current_part()?;
+            let len = max_rows_per_file
+                .map_or(usize::MAX, |max| max - part.row_count)
+                .min(batch.num_rows() - offset);
+            part.sink
+                .write(&batch.slice(offset, len))
+                .with_context(|| format!("Cannot write {}", part.path.display()))?;
+            part.row_count += len;
+            offset += len;
+            if self.part_is_full() {
+                self.close_part()?;
+            }
+        }
+        Ok(())
+    }
+
     fn array_builders(&self, capacity: usize) -> Vec<Box<dyn ArrayBuilder>> {
         self.arrow_schema
             .fields()
@@ -117,94 +884,92 @@ impl CurrentParquetWriter {
             .collect()
     }
 
-    fn write_rows(&mut self, rows: Vec<Vec<ColumnValue>>) {
+    /// Write `rows`, failing on a value that does not fit the type of its column
+    pub fn write_rows(&mut self, rows: Vec<Vec<ColumnValue>>) -> Result<()> {
+        self.row_count += rows.len();
         let mut array_builders = self.array_builders(rows.len());
 
         for row in rows {
             for (i, column_value) in row.into_iter().enumerate() {
-                let ColumnDef {
-                    column_name,
-                    nullable: _,
-                    column_type,
-                } = &self.schema.0[i];
-                let array_builder = &mut array_builders[i];
-
-                match column_type {
-                    crate::line_parser::ColumnType::String => {
-                        let builder = array_builder
-                            .as_any_mut()
-                            .downcast_mut::<StringBuilder>()
-                            .unwrap();
-                        match column_value{
-                            ColumnValue::String(value) => builder.append_value(value),
-                            ColumnValue::Null => builder.append_null(),
-                            _ => panic!("Value for column {column_name} should be a string but is {column_value:?}"),
-                        };
-                    }
-                    crate::line_parser::ColumnType::Integer => {
-                        let builder = array_builder
-                            .as_any_mut()
-                            .downcast_mut::<Int64Builder>()
-                            .unwrap();
-                        match column_value{
-                            ColumnValue::Integer(value) => builder.append_value(value),
-                            ColumnValue::Null => builder.append_null(),
-                            _ => panic!("Value for column {column_name} should be an integer but is {column_value:?}"),
-                        };
-                    }
-                    crate::line_parser::ColumnType::Float => {
-                        let builder = array_builder
-                            .as_any_mut()
-                            .downcast_mut::<Float64Builder>()
-                            .unwrap();
-                        match column_value{
-                            ColumnValue::Float(value) => builder.append_value(value),
-                            ColumnValue::Integer(value) => builder.append_value(value as f64),
-                            ColumnValue::Null => builder.append_null(),
-                            _ => panic!("Value for column {column_name} should be a float but is {column_value:?}"),
+                let column = &self.schema.columns[i];
+                let column_value = output_value(column, column_value).map_err(|e| {
+                    eyre!(
+                        "Value of column `{}` of `{}` {e}",
+                        column.column_name,
+                        self.options.table_name
+                    )
+                })?;
+                let array_builder = array_builders[i].as_any_mut();
+                match (&column.column_type, column_value) {
+                    (ColumnType::String, ColumnValue::String(value)) => array_builder
+                        .downcast_mut::<StringBuilder>()
+                        .unwrap()
+                        .append_value(value),
+                    (ColumnType::String, _) => array_builder
+                        .downcast_mut::<StringBuilder>()
+                        .unwrap()
+                        .append_null(),
+                    (ColumnType::Integer, ColumnValue::Integer(value)) => array_builder
+                        .downcast_mut::<Int64Builder>()
+                        .unwrap()
+                        .append_value(value),
+                    (ColumnType::Integer, _) => array_builder
+                        .downcast_mut::<Int64Builder>()
+                        .unwrap()
+                        .append_null(),
+                    (ColumnType::Float, ColumnValue::Float(value)) => array_builder
+                        .downcast_mut::<Float64Builder>()
+                        .unwrap()
+                        .append_value(value),
+                    (ColumnType::Float, _) => array_builder
+                        .downcast_mut::<Float64Builder>()
+                        .unwrap()
+                        .append_null(),
+                    (ColumnType::Timestamp, value) => {
+                        let value = match value {
+                            ColumnValue::Integer(value) => Some(value),
+                            _ => None,
                         };
+                        match column.time_unit() {
+                            TimeUnit::Millisecond => array_builder
+                                .downcast_mut::<TimestampMillisecondBuilder>()
+                                .unwrap()
+                                .append_option(value),
+                            TimeUnit::Microsecond => array_builder
+                                .downcast_mut::<TimestampMicrosecondBuilder>()
+                                .unwrap()
+                                .append_option(value),
+                            _ => array_builder
+                                .downcast_mut::<TimestampSecondBuilder>()
+                                .unwrap()
+                                .append_option(value),
+                        }
                     }
-                    crate::line_parser::ColumnType::Timestamp => {
-                        let builder = array_builder
-                            .as_any_mut()
-                            .downcast_mut::<TimestampSecondBuilder>()
-                            .unwrap();
-                        match column_value{
-                            ColumnValue::String(value) =>{
-                                // Brute force parse date YYYY-mm-DD hh:mm:ss
-                                //                        0123456789
-                                let year = value[0..4].parse().unwrap();
-                                let month = value[5..7].parse().unwrap();
-                                let day = value[8..10].parse().unwrap();
-
-                                let hour = value[11..13].parse().unwrap();
-                                let min = value[14..16].parse().unwrap();
-                                let sec = value[17..19].parse().unwrap();
-
-                                let datetime = NaiveDateTime::new(NaiveDate::from_ymd_opt(year, month, day).expect("Unable to create date"), NaiveTime::from_hms_opt(hour, min, sec).expect("Unable to create time"));
-
-                                let local_tz_datetime = match datetime.and_local_timezone(Utc){
-                                    chrono::LocalResult::None => panic!("{datetime} cannot be converted in local timezone"),
-                                    chrono::LocalResult::Single(dt) => dt,
-                                    // ignore ambigous (not sure how this is handled by mysql)
-                                    chrono::LocalResult::Ambiguous(dt, _) => dt,
-                                };
-                                builder.append_value(local_tz_datetime.timestamp());
-                            },
-                            ColumnValue::Null => builder.append_null(),
-                                _ => panic!("Value for column {column_name} should be a string but is {column_value:?}"),
-                            };
+                    (ColumnType::Boolean, ColumnValue::Boolean(value)) => array_builder
+                        .downcast_mut::<BooleanBuilder>()
+                        .unwrap()
+                        .append_value(value),
+                    (ColumnType::Boolean, _) => array_builder
+                        .downcast_mut::<BooleanBuilder>()
+                        .unwrap()
+                        .append_null(),
+                    (&ColumnType::Decimal(precision, scale), value) if precision <= 38 => {
+                        let builder = array_builder.downcast_mut::<Decimal128Builder>().unwrap();
+                        match decimal_text(&value) {
+                            Some(text) => builder.append_value(
+                                parse_decimal::<Decimal128Type>(&text, precision, scale).unwrap(),
+                            ),
+                            None => builder.append_null(),
+                        }
                     }
-                    crate::line_parser::ColumnType::Boolean => {
-                        let builder = array_builder
-                            .as_any_mut()
-                            .downcast_mut::<BooleanBuilder>()
-                            .unwrap();
-                        match column_value{
-                            ColumnValue::Boolean(value) => builder.append_value(value),
-                            ColumnValue::Null => builder.append_null(),
-                            _ => panic!("Value for column {column_name} should be a string but is {column_value:?}"),
-                        };
+                    (&ColumnType::Decimal(precision, scale), value) => {
+                        let builder = array_builder.downcast_mut::<Decimal256Builder>().unwrap();
+                        match decimal_text(&value) {
+                            Some(text) => builder.append_value(
+                                parse_decimal::<Decimal256Type>(&text, precision, scale).unwrap(),
+                            ),
+                            None => builder.append_null(),
+                        }
                     }
                 }
             }
@@ -214,10 +979,450 @@ impl CurrentParquetWriter {
             .map(|builder| builder.finish())
             .collect();
         let record_batch = RecordBatch::try_new(self.arrow_schema.clone(), array_refs).unwrap();
-        self.arrow_writer.write(&record_batch).unwrap();
+        match &mut self.sorter {
+            Some(sorter) => sorter.push(record_batch),
+            None => self.write_batch(&record_batch)?,
+        }
+        Ok(())
+    }
+
+    /// Write remaining rows and close the last part, returning the parts written.
+    pub fn finish(mut self) -> Result<Vec<PartInfo>> {
+        if let Some(sorter) = self.sorter.take() {
+            sorter
+                .finish(|batch| self.write_batch(batch))
+                .wrap_err("Cannot write sorted rows")?;
+        }
+        if self.parts.is_empty() {
+            self.current_part()?;
+        }
+        self.close_part()?;
+        Ok(self.parts)
+    }
+}
+
+/// Value of `column` as written: cast if its type is overridden, timestamps as seconds
+/// (or the unit of their cast) since 1970, float columns as floats even when their
+/// values are written as integers, and decimals as text. Fails, telling why, when the
+/// value does not fit the column type.
+pub fn output_value(column: &ColumnDef, value: ColumnValue) -> Result<ColumnValue, String> {
+    let value = match &column.cast {
+        Some(cast) => cast
+            .convert(value, &column.column_type)
+            .map_err(|e| format!("cannot be converted to {:?}: {e}", column.column_type))?,
+        None => value,
+    };
+    match (&column.column_type, value) {
+        (_, ColumnValue::Null) => Ok(ColumnValue::Null),
+        (ColumnType::String, value @ ColumnValue::String(_))
+        | (ColumnType::Integer, value @ ColumnValue::Integer(_))
+        | (ColumnType::Float, value @ ColumnValue::Float(_))
+        | (ColumnType::Boolean, value @ ColumnValue::Boolean(_))
+        // converted by a cast
+        | (ColumnType::Timestamp, value @ ColumnValue::Integer(_)) => Ok(value),
+        (ColumnType::Float, ColumnValue::Integer(value)) => Ok(ColumnValue::Float(value as f64)),
+        (ColumnType::Float, ColumnValue::Decimal(value)) => value
+            .parse()
+            .map(ColumnValue::Float)
+            .map_err(|_| format!("should be a float but is {value:?}")),
+        (&ColumnType::Decimal(precision, scale), value) => match decimal_text(&value) {
+            Some(text) if parse_decimal::<Decimal256Type>(&text, precision, scale).is_ok() => {
+                Ok(ColumnValue::Decimal(text))
+            }
+            _ => Err(format!(
+                "should be a DECIMAL({precision}, {scale}) but is {value:?}"
+            )),
+        },
+        (ColumnType::Timestamp, ColumnValue::String(value)) => parse_datetime(&value)
+            .map(ColumnValue::Integer)
+            .ok_or_else(|| format!("should be a YYYY-MM-DD[ hh:mm:ss] date but is {value:?}")),
+        (ColumnType::String, value) => Err(format!("should be a string but is {value:?}")),
+        (ColumnType::Integer, value) => Err(format!("should be an integer but is {value:?}")),
+        (ColumnType::Float, value) => Err(format!("should be a float but is {value:?}")),
+        (ColumnType::Timestamp, value) => Err(format!("should be a string but is {value:?}")),
+        (ColumnType::Boolean, value) => Err(format!("should be a boolean but is {value:?}")),
+    }
+}
+
+/// Text of a number of a DECIMAL column: floats, which only hold numbers of 15
+/// digits or less, are written with the fewest digits reading back the same float,
+/// so `12.34` is stored exactly
+fn decimal_text(value: &ColumnValue) -> Option<String> {
+    match value {
+        ColumnValue::Decimal(value) => Some(value.clone()),
+        ColumnValue::Integer(value) => Some(value.to_string()),
+        ColumnValue::Float(value) if value.is_finite() => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+/// Seconds since 1970 of a `YYYY-MM-DD hh:mm:ss` date, or of the midnight of a
+/// `YYYY-MM-DD` one (`DATE` columns), in UTC
+fn parse_datetime(value: &str) -> Option<i64> {
+    // Brute force parse date YYYY-mm-DD hh:mm:ss
+    //                        0123456789
+    let field = |range: std::ops::Range<usize>| value.get(range)?.parse::<u32>().ok();
+    let date = NaiveDate::from_ymd_opt(field(0..4)? as i32, field(5..7)?, field(8..10)?)?;
+    let time = if value.len() == 10 {
+        NaiveTime::MIN
+    } else {
+        NaiveTime::from_hms_opt(field(11..13)?, field(14..16)?, field(17..19)?)?
+    };
+    Some(NaiveDateTime::new(date, time).and_utc().timestamp())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::testing::TempDir;
+    use std::sync::Arc;
+
+    use arrow::{
+        array::{Array, AsArray},
+        datatypes::{i256, DataType, Decimal128Type, Decimal256Type},
+    };
+    use indicatif::ProgressBar;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    use crate::{
+        config::Config,
+        filter::TableFilter,
+        line_parser::{
+            parse_line, ColumnDef, ColumnKey, ColumnType, ColumnValue, Line, Schema,
+            CREATE_TABLE_KEY, KEY_KEY, TYPE_KEY,
+        },
+        manifest::StatValue,
+    };
+
+    use super::{output_value, CurrentParquetWriter, OutputFiles, ParquetWriter};
+
+    #[test]
+    fn split_parts() {
+        let dir = TempDir::new("parts");
+        let schema = Schema {
+            columns: vec![ColumnDef {
+                column_name: "id".into(),
+                field_name: "id".into(),
+                nullable: true,
+                column_type: ColumnType::Integer,
+                key: ColumnKey::Primary,
+                mysql_type: "BIGINT".into(),
+                comment: None,
+                default: None,
+                cast: None,
+            }],
+            create_table: "CREATE TABLE user (id BIGINT PRIMARY KEY);".into(),
+            foreign_keys: Vec::new(),
+            indexes: Vec::new(),
+            unsupported: Vec::new(),
+        };
+        let mut config = Config::default();
+        config.parquet.max_rows_per_file = Some(10);
+        let mut writer = CurrentParquetWriter::create(
+            OutputFiles::Parts {
+                dir: dir.to_path_buf(),
+                prefix: "part-".into(),
+            },
+            "user",
+            schema,
+            Vec::new(),
+            &config,
+        )
+        .unwrap();
+        for batch in [0..7, 7..25] {
+            writer
+                .write_rows(batch.map(|id| vec![ColumnValue::Integer(id)]).collect())
+                .unwrap();
+        }
+        writer.write_rows(vec![vec![ColumnValue::Null]]).unwrap();
+        let parts = writer.finish().unwrap();
+
+        let paths: Vec<&str> = parts.iter().map(|part| part.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                "part-00000.parquet",
+                "part-00001.parquet",
+                "part-00002.parquet"
+            ]
+        );
+        let row_counts: Vec<u64> = parts.iter().map(|part| part.row_count).collect();
+        assert_eq!(row_counts, [10, 10, 6]);
+        let last = &parts[2].columns[0];
+        assert_eq!(last.name, "id");
+        assert_eq!(last.null_count, Some(1));
+        assert_eq!(last.min, Some(StatValue::Integer(20)));
+        assert_eq!(last.max, Some(StatValue::Integer(24)));
+        assert!(parts.iter().all(|part| dir.join(&part.path).exists()));
+
+        // the DDL and column metadata are kept in every file
+        let reader = ParquetRecordBatchReaderBuilder::try_new(
+            std::fs::File::open(dir.join("part-00002.parquet")).unwrap(),
+        )
+        .unwrap();
+        let key_value_metadata = reader.metadata().file_metadata().key_value_metadata();
+        let create_table = key_value_metadata
+            .unwrap()
+            .iter()
+            .find(|kv| kv.key == CREATE_TABLE_KEY)
+            .and_then(|kv| kv.value.as_deref());
+        assert_eq!(
+            create_table,
+            Some("CREATE TABLE user (id BIGINT PRIMARY KEY);")
+        );
+        let field = reader.schema().field(0).clone();
+        assert_eq!(field.metadata()[TYPE_KEY], "BIGINT");
+        assert_eq!(field.metadata()[KEY_KEY], "primary");
+    }
+
+    #[test]
+    fn reject_empty_parts() {
+        assert!(toml::from_str::<Config>("[parquet]\nmax_rows_per_file = 0").is_err());
+        assert!(toml::from_str::<Config>("[parquet]\nmax_file_size = \"0M\"").is_err());
+        let config: Config = toml::from_str("[parquet]\nmax_file_size = \"1K\"").unwrap();
+        assert_eq!(config.parquet.max_file_size, Some(1024));
+    }
+
+    #[test]
+    fn sample_across_inputs() {
+        let dir = TempDir::new("sample");
+        let config: Config = toml::from_str(
+            r#"
+            format = "ndjson"
+            [tables.t]
+            sample = 100
+            "#,
+        )
+        .unwrap();
+        let (sender, handle) = ParquetWriter::start(
+            dir.to_path_buf(),
+            1 << 20,
+            1 << 20,
+            Arc::new(config),
+            TableFilter::default(),
+            false,
+            ProgressBar::hidden(),
+        );
+        // the same table in two input files
+        for ids in [0..5, 5..10] {
+            let Line::CreateTable(table_name, schema) =
+                parse_line("CREATE TABLE t (id INT NOT NULL, PRIMARY KEY (id));").unwrap()
+            else {
+                panic!("Not a CREATE TABLE statement");
+            };
+            sender.send(Line::CreateTable(table_name, schema)).unwrap();
+            let rows = ids.map(|id| vec![ColumnValue::Integer(id)]).collect();
+            sender.send(Line::InsertInto("t".into(), rows)).unwrap();
+        }
+        drop(sender);
+        handle.join().unwrap().unwrap();
+        let ndjson = std::fs::read_to_string(dir.join("t.ndjson")).unwrap();
+        assert_eq!(ndjson.lines().count(), 10);
+    }
+
+    #[test]
+    fn report_name_collisions() {
+        let dir = TempDir::new("collision");
+        let (sender, handle) = ParquetWriter::start(
+            dir.to_path_buf(),
+            1 << 20,
+            1 << 20,
+            Arc::new(Config::default()),
+            TableFilter::default(),
+            false,
+            ProgressBar::hidden(),
+        );
+        for create_table in ["CREATE TABLE User (id INT);", "CREATE TABLE user (id INT);"] {
+            // the writer stops on the second table, the channel being closed afterwards
+            let _ = sender.send(parse_line(create_table).unwrap());
+        }
+        drop(sender);
+        let error = handle.join().unwrap().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Tables `User` and `user` would both be written to `user`"
+        );
+    }
+
+    #[test]
+    fn partition_by_output_name() {
+        let dir = TempDir::new("partition");
+        let config: Config = toml::from_str(
+            r#"
+            format = "ndjson"
+            column_case = "preserve"
+            [tables.t]
+            partition_by = "createdat:month"
+            "#,
+        )
+        .unwrap();
+        let (sender, handle) = ParquetWriter::start(
+            dir.to_path_buf(),
+            1 << 20,
+            1 << 20,
+            Arc::new(config),
+            TableFilter::default(),
+            false,
+            ProgressBar::hidden(),
+        );
+        for statement in [
+            "CREATE TABLE t (id INT, createdAt DATETIME);",
+            "INSERT INTO t VALUES (1,'2024-05-17 13:45:00');",
+            "INSERT INTO t VALUES (2,'17/05');",
+        ] {
+            let _ = sender.send(parse_line(statement).unwrap());
+        }
+        drop(sender);
+        assert_eq!(
+            handle.join().unwrap().unwrap_err().to_string(),
+            "Cannot truncate `17/05` of column `createdat` to a month"
+        );
+        assert!(dir.join("t/createdAt_month=2024-05").is_dir());
+    }
+
+    #[test]
+    fn rows_before_create_table() {
+        let dir = TempDir::new("pending");
+        let config: Config = toml::from_str("format = \"ndjson\"").unwrap();
+        for unordered in [true, false] {
+            let (sender, handle) = ParquetWriter::start(
+                dir.to_path_buf(),
+                1 << 20,
+                1 << 20,
+                Arc::new(config.clone()),
+                TableFilter::default(),
+                unordered,
+                ProgressBar::hidden(),
+            );
+            for statement in [
+                "INSERT INTO t VALUES (1),(2);",
+                "CREATE TABLE t (id INT);",
+                "INSERT INTO t VALUES (3);",
+            ] {
+                // the ordered writer stops on the first statement
+                let _ = sender.send(parse_line(statement).unwrap());
+            }
+            drop(sender);
+            let result = handle.join().unwrap();
+            if unordered {
+                result.unwrap();
+                let ndjson = std::fs::read_to_string(dir.join("t.ndjson")).unwrap();
+                assert_eq!(ndjson.lines().count(), 3);
+            } else {
+                assert_eq!(
+                    result.unwrap_err().to_string(),
+                    "No CREATE TABLE statement for table `t` before its rows"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn decimal_values() {
+        let dir = TempDir::new("decimal");
+        let Line::CreateTable(_, schema) =
+            parse_line("CREATE TABLE t (price DECIMAL(10,2), total DECIMAL(50,4));").unwrap()
+        else {
+            panic!("not a CREATE TABLE");
+        };
+        let Line::InsertInto(_, rows) = parse_line(
+            "INSERT INTO t VALUES (12.34,-0.5),(-7,NULL),(NULL,12345678901234567890.1234);",
+        )
+        .unwrap() else {
+            panic!("not an INSERT INTO");
+        };
+        let path = dir.join("t.parquet");
+        let mut writer = CurrentParquetWriter::create(
+            OutputFiles::File(path.clone()),
+            "t",
+            schema.clone(),
+            Vec::new(),
+            &Config::default(),
+        )
+        .unwrap();
+        writer.write_rows(rows).unwrap();
+        let error = writer
+            .write_rows(vec![vec![
+                ColumnValue::Float(123456789.5),
+                ColumnValue::Null,
+            ]])
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Value of column `price` of `t` should be a DECIMAL(10, 2) but is Float(123456789.5)"
+        );
+        writer.finish().unwrap();
+
+        let batch = ParquetRecordBatchReaderBuilder::try_new(std::fs::File::open(&path).unwrap())
+            .unwrap()
+            .build()
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+        let prices = batch.column(0).as_primitive::<Decimal128Type>();
+        assert_eq!(prices.data_type(), &DataType::Decimal128(10, 2));
+        assert_eq!(
+            prices.iter().collect::<Vec<_>>(),
+            [Some(1234), Some(-700), None]
+        );
+        let totals = batch.column(1).as_primitive::<Decimal256Type>();
+        assert_eq!(totals.data_type(), &DataType::Decimal256(50, 4));
+        assert_eq!(
+            totals.iter().collect::<Vec<_>>(),
+            [
+                Some(i256::from_i128(-5000)),
+                None,
+                Some(i256::from_i128(123456789012345678901234))
+            ]
+        );
+
+        // too many digits for DECIMAL(10,2)
+        let error = output_value(&schema.columns[0], ColumnValue::Float(123456789.5));
+        assert_eq!(
+            error.unwrap_err(),
+            "should be a DECIMAL(10, 2) but is Float(123456789.5)"
+        );
+    }
+
+    #[test]
+    fn unknown_sort_column() {
+        let config: Config = toml::from_str("[tables.t]\nsort_by = [\"id\", \"nope\"]").unwrap();
+        let Line::CreateTable(_, schema) = parse_line("CREATE TABLE t (id INT);").unwrap() else {
+            panic!("not a CREATE TABLE");
+        };
+        let error = CurrentParquetWriter::create(
+            OutputFiles::File(std::env::temp_dir().join("unknown-sort-column.parquet")),
+            "t",
+            schema,
+            Vec::new(),
+            &config,
+        )
+        .err()
+        .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "Unknown column `nope` in the sort order of `t`"
+        );
     }
 
-    fn finish(self) {
-        self.arrow_writer.close().unwrap();
+    #[test]
+    fn date_values() {
+        let Line::CreateTable(_, schema) =
+            parse_line("CREATE TABLE t (day DATE, at DATETIME);").unwrap()
+        else {
+            panic!("not a CREATE TABLE");
+        };
+        let value = |i: usize, value: &str| {
+            output_value(&schema.columns[i], ColumnValue::String(value.into()))
+        };
+        assert_eq!(value(0, "2024-01-01"), Ok(ColumnValue::Integer(1704067200)));
+        assert_eq!(
+            value(1, "2024-01-01 00:00:01"),
+            Ok(ColumnValue::Integer(1704067201))
+        );
+        assert_eq!(
+            value(0, "0000-00-00").unwrap_err(),
+            "should be a YYYY-MM-DD[ hh:mm:ss] date but is \"0000-00-00\""
+        );
     }
 }
@@ -0,0 +1,506 @@
+//! Hive-style partitioned output.
+//!
+//! Rows of a partitioned table are routed by the value of their partition column,
+//! possibly truncated to a year, month, day or hour, into
+//! `{table}/{column}={value}/part-00000.parquet` directories.
+
+use std::{collections::HashMap, fs::create_dir_all, path::PathBuf, str::FromStr};
+
+use color_eyre::eyre::{bail, eyre, Context, Result};
+use parquet::format::KeyValue;
+use serde::Deserialize;
+
+use crate::{
+    config::Config,
+    line_parser::{ColumnType, ColumnValue, Schema},
+    manifest::PartInfo,
+    parquet_writer::{CurrentParquetWriter, OutputFiles},
+    sink::Format,
+};
+
+/// Partitions written at the same time per table when not configured
+pub const DEFAULT_MAX_OPEN_PARTITIONS: usize = 64;
+
+/// Directory of rows whose partition value is NULL or empty, as named by Hive
+pub const DEFAULT_PARTITION: &str = "__HIVE_DEFAULT_PARTITION__";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Granularity {
+    Year,
+    Month,
+    Day,
+    Hour,
+}
+
+impl Granularity {
+    pub fn name(self) -> &'static str {
+        match self {
+            Granularity::Year => "year",
+            Granularity::Month => "month",
+            Granularity::Day => "day",
+            Granularity::Hour => "hour",
+        }
+    }
+
+    /// Truncate a `YYYY-MM-DD[ hh:mm:ss]` value: `2024`, `2024-05`, `2024-05-01` or
+    /// `2024-05-01T13`.
+    fn truncate(self, value: &str) -> Option<String> {
+        match self {
+            Granularity::Year => value.get(..4).map(str::to_string),
+            Granularity::Month => value.get(..7).map(str::to_string),
+            Granularity::Day => value.get(..10).map(str::to_string),
+            Granularity::Hour => {
+                let day = value.get(..10)?;
+                // dates have no time
+                let hour = value.get(11..13).unwrap_or("00");
+                Some(format!("{day}T{hour}"))
+            }
+        }
+    }
+}
+
+/// Partition column of a table, with an optional date truncation
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct PartitionBy {
+    pub column: String,
+    pub granularity: Option<Granularity>,
+}
+
+impl FromStr for PartitionBy {
+    type Err = String;
+
+    /// Parse `column[:year|month|day|hour]`
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (column, granularity) = match value.split_once(':') {
+            Some((column, granularity)) => (column, Some(granularity)),
+            None => (value, None),
+        };
+        let granularity = granularity
+            .map(
+                |granularity| match granularity.to_ascii_lowercase().as_str() {
+                    "year" => Ok(Granularity::Year),
+                    "month" => Ok(Granularity::Month),
+                    "day" => Ok(Granularity::Day),
+                    "hour" => Ok(Granularity::Hour),
+                    _ => Err(format!(
+                        "Invalid granularity `{granularity}`, expected year, month, day or hour"
+                    )),
+                },
+            )
+            .transpose()?;
+        if column.is_empty() {
+            return Err(format!("Expected COLUMN[:GRANULARITY], got `{value}`"));
+        }
+        Ok(PartitionBy {
+            column: column.to_string(),
+            granularity,
+        })
+    }
+}
+
+impl TryFrom<String> for PartitionBy {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+/// Parse a `table:column[:granularity]` command line argument
+pub fn parse_table_partition(value: &str) -> Result<(String, PartitionBy), String> {
+    match value.split_once(':') {
+        Some((table, partition_by)) if !table.is_empty() => {
+            Ok((table.to_string(), partition_by.parse()?))
+        }
+        _ => Err(format!(
+            "Expected TABLE:COLUMN[:GRANULARITY], got `{value}`"
+        )),
+    }
+}
+
+impl PartitionBy {
+    /// Position of the partition column in `schema`, looked up by MySQL name
+    pub fn position(&self, schema: &Schema) -> Option<usize> {
+        schema
+            .columns
+            .iter()
+            .position(|c| c.column_name.eq_ignore_ascii_case(&self.column))
+    }
+
+    /// Name of the partition key in directory names, after the output name of the
+    /// partition column, such as `created_at_month`
+    pub fn key(&self, field_name: &str) -> String {
+        match self.granularity {
+            Some(granularity) => format!("{field_name}_{}", granularity.name()),
+            None => field_name.to_string(),
+        }
+    }
+
+    /// Directory of the partition holding `value`, such as `created_at_month=2024-05`
+    fn directory(&self, key: &str, value: &ColumnValue) -> Result<String> {
+        let value = match (value, self.granularity) {
+            (ColumnValue::Null, _) => None,
+            (ColumnValue::String(value), Some(granularity)) => {
+                Some(granularity.truncate(value).ok_or_else(|| {
+                    eyre!(
+                        "Cannot truncate `{value}` of column `{}` to a {}",
+                        self.column,
+                        granularity.name()
+                    )
+                })?)
+            }
+            (value, Some(_)) => bail!(
+                "Value of column `{}` should be a date but is {value:?}",
+                self.column
+            ),
+            (ColumnValue::String(value) | ColumnValue::Decimal(value), None) => Some(value.clone()),
+            (ColumnValue::Integer(value), None) => Some(value.to_string()),
+            (ColumnValue::Float(value), None) => Some(value.to_string()),
+            (ColumnValue::Boolean(value), None) => Some(value.to_string()),
+        };
+        Ok(match value.filter(|value| !value.is_empty()) {
+            Some(value) => format!("{key}={}", escape(&value)),
+            None => format!("{key}={DEFAULT_PARTITION}"),
+        })
+    }
+}
+
+/// Percent-encode the characters Hive escapes in partition values
+fn escape(value: &str) -> String {
+    let mut ret = String::with_capacity(value.len());
+    for c in value.chars() {
+        if c.is_control() || "\"#%'*/:=?\\{[]^".contains(c) {
+            let mut buf = [0; 4];
+            for byte in c.encode_utf8(&mut buf).bytes() {
+                ret.push_str(&format!("%{byte:02X}"));
+            }
+        } else {
+            ret.push(c);
+        }
+    }
+    ret
+}
+
+/// Partition value of an escaped directory name
+pub fn unescape(value: &str) -> String {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let decoded = tail
+            .get(..2)
+            .filter(|_| byte == b'%')
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match decoded {
+            Some(decoded) => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            None => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Writer of a partitioned table, keeping at most `max_open` partitions open.
+///
+/// When a partition is reopened after being closed, its rows go to the next file of
+/// its directory.
+pub struct PartitionedWriter {
+    table_dir: PathBuf,
+    /// prefix of file names, numbered by the number of times a partition was opened
+    prefix: String,
+    table_name: String,
+    /// schema of the table
+    schema: Schema,
+    /// schema of the files, without the partition column unless it is kept
+    file_schema: Schema,
+    partition_by: PartitionBy,
+    partition_column: usize,
+    /// partition key in directory names
+    key: String,
+    keep_column: bool,
+    max_open: usize,
+    key_value_metadata: Vec<KeyValue>,
+    config: Config,
+    open: HashMap<String, OpenPartition>,
+    /// number of times each partition was opened
+    opened: HashMap<String, usize>,
+    /// parts of closed partitions, paths relative to `table_dir`
+    parts: Vec<PartInfo>,
+    /// incremented on every write, to close the least recently used partition
+    clock: u64,
+}
+
+struct OpenPartition {
+    writer: CurrentParquetWriter,
+    last_used: u64,
+}
+
+impl PartitionedWriter {
+    /// Create the writer of `table_name`, partitioned as set in its configuration
+    pub fn create(
+        table_dir: PathBuf,
+        prefix: String,
+        table_name: &str,
+        schema: Schema,
+        key_value_metadata: Vec<KeyValue>,
+        config: &Config,
+    ) -> Result<Self> {
+        let Some(table) = config.table(table_name) else {
+            bail!("Table `{table_name}` is not partitioned");
+        };
+        let Some(partition_by) = table.partition_by.clone() else {
+            bail!("Table `{table_name}` is not partitioned");
+        };
+        let Some(partition_column) = partition_by.position(&schema) else {
+            bail!(
+                "Cannot partition `{table_name}` by unknown column `{}`",
+                partition_by.column
+            );
+        };
+        if partition_by.granularity.is_some()
+            && schema.columns[partition_column].column_type != ColumnType::Timestamp
+        {
+            bail!(
+                "Cannot truncate column `{}` of `{table_name}`: it is not a date",
+                partition_by.column
+            );
+        }
+        if partition_by.granularity.is_some() && schema.columns[partition_column].cast.is_some() {
+            bail!(
+                "Cannot truncate column `{}` of `{table_name}`: its type is overridden",
+                partition_by.column
+            );
+        }
+        let key = partition_by.key(&schema.columns[partition_column].field_name);
+        // Iceberg derives partitions from columns of data files
+        let keep_column = table.keep_partition_column || config.format == Format::Iceberg;
+        let mut file_schema = schema.clone();
+        if !keep_column {
+            file_schema.columns.remove(partition_column);
+        }
+        Ok(PartitionedWriter {
+            table_dir,
+            prefix,
+            table_name: table_name.to_string(),
+            schema,
+            file_schema,
+            partition_by,
+            partition_column,
+            key,
+            keep_column,
+            max_open: config
+                .max_open_partitions
+                .unwrap_or(DEFAULT_MAX_OPEN_PARTITIONS)
+                .max(1),
+            key_value_metadata,
+            config: config.clone(),
+            open: HashMap::new(),
+            opened: HashMap::new(),
+            parts: Vec::new(),
+            clock: 0,
+        })
+    }
+
+    pub fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    /// Route rows to the writers of their partitions, failing on a value that cannot be
+    /// truncated
+    pub fn write_rows(&mut self, rows: Vec<Vec<ColumnValue>>) -> Result<()> {
+        let mut partitions: Vec<(String, Vec<Vec<ColumnValue>>)> = Vec::new();
+        let mut indices: HashMap<String, usize> = HashMap::new();
+        for mut row in rows {
+            let directory = self
+                .partition_by
+                .directory(&self.key, &row[self.partition_column])?;
+            if !self.keep_column {
+                row.remove(self.partition_column);
+            }
+            let i = *indices.entry(directory.clone()).or_insert_with(|| {
+                partitions.push((directory, Vec::new()));
+                partitions.len() - 1
+            });
+            partitions[i].1.push(row);
+        }
+        for (directory, rows) in partitions {
+            self.writer(&directory)?.write_rows(rows)?;
+        }
+        Ok(())
+    }
+
+    /// Memory used by rows not written to disk yet
+    pub fn buffered_size(&self) -> usize {
+        self.open
+            .values()
+            .map(|partition| partition.writer.buffered_size())
+            .sum()
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        for partition in self.open.values_mut() {
+            partition.writer.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Writer of the partition stored in `directory`, opening it if needed
+    fn writer(&mut self, directory: &str) -> Result<&mut CurrentParquetWriter> {
+        self.clock += 1;
+        if !self.open.contains_key(directory) {
+            if self.open.len() >= self.max_open {
+                let oldest = self
+                    .open
+                    .iter()
+                    .min_by_key(|(_, partition)| partition.last_used)
+                    .map(|(directory, _)| directory.clone())
+                    .unwrap();
+                self.close(&oldest)?;
+            }
+            let dir = self.table_dir.join(directory);
+            create_dir_all(&dir)
+                .with_context(|| format!("Cannot create output directory {}", dir.display()))?;
+            let opened = self.opened.entry(directory.to_string()).or_default();
+            let prefix = format!("{}{:05}", self.prefix, *opened);
+            *opened += 1;
+            let output = if self.config.writer_settings(&self.table_name).splits_files() {
+                OutputFiles::Parts {
+                    dir,
+                    prefix: format!("{prefix}-"),
+                }
+            } else {
+                OutputFiles::File(dir.join(format!("{prefix}.{}", self.config.extension())))
+            };
+            let writer = CurrentParquetWriter::create(
+                output,
+                &self.table_name,
+                self.file_schema.clone(),
+                self.key_value_metadata.clone(),
+                &self.config,
+            )?;
+            self.open.insert(
+                directory.to_string(),
+                OpenPartition {
+                    writer,
+                    last_used: 0,
+                },
+            );
+        }
+        let partition = self.open.get_mut(directory).unwrap();
+        partition.last_used = self.clock;
+        Ok(&mut partition.writer)
+    }
+
+    fn close(&mut self, directory: &str) -> Result<()> {
+        if let Some(partition) = self.open.remove(directory) {
+            for mut part in partition.writer.finish()? {
+                part.path = format!("{directory}/{}", part.path);
+                self.parts.push(part);
+            }
+        }
+        Ok(())
+    }
+
+    /// Close every partition, returning the parts written, relative to the table
+    /// directory.
+    pub fn finish(mut self) -> Result<Vec<PartInfo>> {
+        let directories: Vec<String> = self.open.keys().cloned().collect();
+        for directory in directories {
+            self.close(&directory)?;
+        }
+        self.parts.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(self.parts)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::line_parser::ColumnValue;
+
+    use super::{parse_table_partition, unescape, Granularity, PartitionBy};
+
+    #[test]
+    fn parse_partition_by() {
+        assert_eq!(
+            parse_table_partition("orders:created_at:month"),
+            Ok((
+                "orders".into(),
+                PartitionBy {
+                    column: "created_at".into(),
+                    granularity: Some(Granularity::Month)
+                }
+            ))
+        );
+        assert_eq!(
+            parse_table_partition("events:tenant_id"),
+            Ok((
+                "events".into(),
+                PartitionBy {
+                    column: "tenant_id".into(),
+                    granularity: None
+                }
+            ))
+        );
+        assert!(parse_table_partition("events").is_err());
+        assert!(parse_table_partition("events:").is_err());
+        assert!(parse_table_partition("orders:created_at:week").is_err());
+    }
+
+    #[test]
+    fn partition_directories() {
+        let month: PartitionBy = "Created_At:month".parse().unwrap();
+        let key = month.key("createdAt");
+        assert_eq!(key, "createdAt_month");
+        let directory = |partition_by: &PartitionBy, key: &str, value: ColumnValue| {
+            partition_by.directory(key, &value).unwrap()
+        };
+        assert_eq!(
+            directory(
+                &month,
+                &key,
+                ColumnValue::String("2024-05-17 13:45:00".into())
+            ),
+            "createdAt_month=2024-05"
+        );
+        assert_eq!(
+            directory(&month, &key, ColumnValue::Null),
+            "createdAt_month=__HIVE_DEFAULT_PARTITION__"
+        );
+        assert_eq!(
+            month
+                .directory(&key, &ColumnValue::String("24".into()))
+                .unwrap_err()
+                .to_string(),
+            "Cannot truncate `24` of column `Created_At` to a month"
+        );
+        assert!(month.directory(&key, &ColumnValue::Integer(1)).is_err());
+        let hour: PartitionBy = "created_at:hour".parse().unwrap();
+        assert_eq!(
+            directory(
+                &hour,
+                "created_at_hour",
+                ColumnValue::String("2024-05-17 13:45:00".into())
+            ),
+            "created_at_hour=2024-05-17T13"
+        );
+        let value: PartitionBy = "tenant".parse().unwrap();
+        assert_eq!(
+            directory(&value, "tenant", ColumnValue::Integer(42)),
+            "tenant=42"
+        );
+        assert_eq!(
+            directory(&value, "tenant", ColumnValue::String("a/b=c".into())),
+            "tenant=a%2Fb%3Dc"
+        );
+        assert_eq!(unescape("a%2Fb%3Dc%"), "a/b=c%");
+        assert_eq!(
+            directory(&value, "tenant", ColumnValue::String("".into())),
+            "tenant=__HIVE_DEFAULT_PARTITION__"
+        );
+    }
+}
@@ -0,0 +1,482 @@
+//! Row filters given as a SQL `WHERE` condition.
+//!
+//! The condition is parsed by sqlparser, then compiled against the schema of its table:
+//! column names are resolved to positions and literals converted to the type of the
+//! columns they are compared to. Rows are filtered on their parsed [`ColumnValue`]s,
+//! with SQL semantics: a comparison with `NULL` is unknown, and so is the row.
+
+use std::{borrow::Cow, cmp::Ordering};
+
+use color_eyre::eyre::{bail, eyre, Context, Result};
+use regex::Regex;
+use sqlparser::{
+    ast::{BinaryOperator, Expr, UnaryOperator},
+    dialect::MySqlDialect,
+    parser::Parser,
+    tokenizer::Token,
+};
+
+use crate::line_parser::{literal, ColumnType, ColumnValue, Schema};
+
+/// Compiled condition on the rows of a table
+#[derive(Clone, Debug)]
+pub struct Predicate(Condition);
+
+#[derive(Clone, Debug)]
+enum Condition {
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+    Not(Box<Condition>),
+    Compare(Operand, BinaryOperator, Operand),
+    In {
+        operand: Operand,
+        list: Vec<Operand>,
+        negated: bool,
+    },
+    IsNull(Operand),
+    Like {
+        operand: Operand,
+        pattern: Regex,
+        negated: bool,
+    },
+}
+
+#[derive(Clone, Debug)]
+enum Operand {
+    /// position of the column in the row
+    Column(usize),
+    /// position of a date and time column in the row, its `YYYY-MM-DD` dates being
+    /// compared as `YYYY-MM-DD 00:00:00` like literals
+    Timestamp(usize),
+    Value(ColumnValue),
+}
+
+/// Parse a `WHERE` condition, without its `WHERE` keyword
+pub fn parse(condition: &str) -> Result<Expr> {
+    let dialect = MySqlDialect {};
+    let mut parser = Parser::new(&dialect)
+        .try_with_sql(condition)
+        .map_err(|e| eyre!("Invalid condition `{condition}`: {e}"))?;
+    let expr = parser
+        .parse_expr()
+        .map_err(|e| eyre!("Invalid condition `{condition}`: {e}"))?;
+    let next = parser.next_token();
+    if next.token != Token::EOF {
+        bail!("Invalid condition `{condition}`: unexpected {next}");
+    }
+    Ok(expr)
+}
+
+/// Parse a `table: condition` command line argument
+pub fn parse_table_condition(value: &str) -> Result<(String, String), String> {
+    let Some((table, condition)) = value.split_once(':') else {
+        return Err(format!("Expected TABLE: CONDITION, got `{value}`"));
+    };
+    let (table, condition) = (table.trim(), condition.trim());
+    if table.is_empty() {
+        return Err(format!("Expected TABLE: CONDITION, got `{value}`"));
+    }
+    parse(condition).map_err(|e| e.to_string())?;
+    Ok((table.to_string(), condition.to_string()))
+}
+
+impl Predicate {
+    /// Compile `condition` against the `schema` of its table
+    pub fn new(condition: &str, schema: &Schema) -> Result<Predicate> {
+        compile(&parse(condition)?, schema)
+            .map(Predicate)
+            .with_context(|| format!("Invalid condition `{condition}`"))
+    }
+
+    /// Whether `row` satisfies the condition, unknown being false
+    pub fn matches(&self, row: &[ColumnValue]) -> bool {
+        self.0.eval(row) == Some(true)
+    }
+}
+
+fn compile(expr: &Expr, schema: &Schema) -> Result<Condition> {
+    Ok(match expr {
+        Expr::Nested(expr) => compile(expr, schema)?,
+        Expr::BinaryOp { left, op, right } => match op {
+            BinaryOperator::And => Condition::And(
+                Box::new(compile(left, schema)?),
+                Box::new(compile(right, schema)?),
+            ),
+            BinaryOperator::Or => Condition::Or(
+                Box::new(compile(left, schema)?),
+                Box::new(compile(right, schema)?),
+            ),
+            BinaryOperator::Eq
+            | BinaryOperator::NotEq
+            | BinaryOperator::Lt
+            | BinaryOperator::LtEq
+            | BinaryOperator::Gt
+            | BinaryOperator::GtEq => {
+                let (left, right) = operands(left, right, schema)?;
+                Condition::Compare(left, op.clone(), right)
+            }
+            _ => bail!("Unsupported operator {op}"),
+        },
+        Expr::UnaryOp {
+            op: UnaryOperator::Not,
+            expr,
+        } => Condition::Not(Box::new(compile(expr, schema)?)),
+        Expr::IsNull(expr) => Condition::IsNull(operand(expr, schema, None)?),
+        Expr::IsNotNull(expr) => {
+            Condition::Not(Box::new(Condition::IsNull(operand(expr, schema, None)?)))
+        }
+        Expr::InList {
+            expr,
+            list,
+            negated,
+        } => {
+            let operand = operand(expr, schema, None)?;
+            let column_type = operand.column_type(schema);
+            Condition::In {
+                list: list
+                    .iter()
+                    .map(|item| self::operand(item, schema, column_type))
+                    .collect::<Result<_>>()?,
+                operand,
+                negated: *negated,
+            }
+        }
+        Expr::Between {
+            expr,
+            negated,
+            low,
+            high,
+        } => {
+            let (operand, low) = operands(expr, low, schema)?;
+            let (_, high) = operands(expr, high, schema)?;
+            let between = Condition::And(
+                Box::new(Condition::Compare(
+                    operand.clone(),
+                    BinaryOperator::GtEq,
+                    low,
+                )),
+                Box::new(Condition::Compare(operand, BinaryOperator::LtEq, high)),
+            );
+            if *negated {
+                Condition::Not(Box::new(between))
+            } else {
+                between
+            }
+        }
+        Expr::Like {
+            negated,
+            expr,
+            pattern,
+            escape_char,
+        } => {
+            let ColumnValue::String(pattern) = literal(pattern)? else {
+                bail!("LIKE expects a string pattern, got {pattern}");
+            };
+            Condition::Like {
+                operand: operand(expr, schema, None)?,
+                pattern: like_regex(&pattern, escape_char.unwrap_or('\\'))?,
+                negated: *negated,
+            }
+        }
+        _ => bail!("Unsupported condition {expr}"),
+    })
+}
+
+/// Operands of a comparison, a literal taking the type of the column it is compared to
+fn operands(left: &Expr, right: &Expr, schema: &Schema) -> Result<(Operand, Operand)> {
+    let left_type = operand(left, schema, None)?.column_type(schema);
+    let right = operand(right, schema, left_type)?;
+    let left = operand(left, schema, right.column_type(schema))?;
+    Ok((left, right))
+}
+
+fn operand(expr: &Expr, schema: &Schema, column_type: Option<&ColumnType>) -> Result<Operand> {
+    let name = match expr {
+        Expr::Nested(expr) => return operand(expr, schema, column_type),
+        Expr::Identifier(ident) => &ident.value,
+        Expr::CompoundIdentifier(idents) if !idents.is_empty() => &idents[idents.len() - 1].value,
+        _ => {
+            let value = literal(expr)?;
+            return Ok(Operand::Value(match column_type {
+                Some(column_type) => convert(value, column_type)?,
+                None => value,
+            }));
+        }
+    };
+    let column = schema
+        .columns
+        .iter()
+        .position(|c| c.column_name.eq_ignore_ascii_case(name))
+        .ok_or_else(|| eyre!("Unknown column `{name}`"))?;
+    Ok(match schema.columns[column].column_type {
+        ColumnType::Timestamp => Operand::Timestamp(column),
+        _ => Operand::Column(column),
+    })
+}
+
+impl Operand {
+    fn column_type<'a>(&self, schema: &'a Schema) -> Option<&'a ColumnType> {
+        match self {
+            Operand::Column(column) | Operand::Timestamp(column) => {
+                Some(&schema.columns[*column].column_type)
+            }
+            Operand::Value(_) => None,
+        }
+    }
+
+    fn value<'a>(&'a self, row: &'a [ColumnValue]) -> Cow<'a, ColumnValue> {
+        match self {
+            Operand::Column(column) => Cow::Borrowed(&row[*column]),
+            Operand::Timestamp(column) => match &row[*column] {
+                ColumnValue::String(date) if date.len() == 10 => {
+                    Cow::Owned(ColumnValue::String(format!("{date} 00:00:00")))
+                }
+                value => Cow::Borrowed(value),
+            },
+            Operand::Value(value) => Cow::Borrowed(value),
+        }
+    }
+}
+
+/// Convert a literal to the type of values of a column, as parsed from `INSERT INTO`
+/// statements
+fn convert(value: ColumnValue, column_type: &ColumnType) -> Result<ColumnValue> {
+    Ok(match (column_type, value) {
+        (_, ColumnValue::Null) => ColumnValue::Null,
+        (
+            ColumnType::Integer | ColumnType::Float | ColumnType::Decimal(_, _),
+            ColumnValue::String(s),
+        ) => match s.trim().parse::<i64>() {
+            Ok(integer) => ColumnValue::Integer(integer),
+            Err(_) => ColumnValue::Float(
+                s.trim()
+                    .parse()
+                    .map_err(|_| eyre!("`{s}` is not a number"))?,
+            ),
+        },
+        (ColumnType::String, ColumnValue::Integer(integer)) => {
+            ColumnValue::String(integer.to_string())
+        }
+        (ColumnType::String, ColumnValue::Float(float)) => ColumnValue::String(float.to_string()),
+        (ColumnType::String, ColumnValue::Decimal(decimal)) => ColumnValue::String(decimal),
+        (ColumnType::Timestamp, ColumnValue::String(s)) => {
+            // timestamps are compared as `YYYY-MM-DD hh:mm:ss` strings
+            let timestamp = if s.len() == 10 {
+                format!("{s} 00:00:00")
+            } else {
+                s
+            };
+            chrono::NaiveDateTime::parse_from_str(&timestamp, "%Y-%m-%d %H:%M:%S")
+                .map_err(|_| eyre!("`{timestamp}` is not a `YYYY-MM-DD[ hh:mm:ss]` date"))?;
+            ColumnValue::String(timestamp)
+        }
+        (ColumnType::Boolean, ColumnValue::Integer(integer)) => ColumnValue::Boolean(integer != 0),
+        (_, value) => value,
+    })
+}
+
+/// Regular expression matching the same strings as the LIKE `pattern`
+fn like_regex(pattern: &str, escape_char: char) -> Result<Regex> {
+    let mut regex = String::from("(?s)^");
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '%' => regex.push_str(".*"),
+            '_' => regex.push('.'),
+            c if c == escape_char => {
+                if let Some(c) = chars.next() {
+                    regex.push_str(&regex::escape(&c.to_string()));
+                }
+            }
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    Ok(Regex::new(&regex)?)
+}
+
+/// Order of two values, `None` when they cannot be compared
+pub fn compare(left: &ColumnValue, right: &ColumnValue) -> Option<Ordering> {
+    match (left, right) {
+        (ColumnValue::Integer(left), ColumnValue::Integer(right)) => Some(left.cmp(right)),
+        (ColumnValue::String(left), ColumnValue::String(right)) => Some(left.cmp(right)),
+        (ColumnValue::Boolean(left), ColumnValue::Boolean(right)) => Some(left.cmp(right)),
+        (left, right) => number(left)?.partial_cmp(&number(right)?),
+    }
+}
+
+/// Numeric value of an `Integer`, `Float` or `Decimal`
+fn number(value: &ColumnValue) -> Option<f64> {
+    match value {
+        ColumnValue::Integer(integer) => Some(*integer as f64),
+        ColumnValue::Float(float) => Some(*float),
+        ColumnValue::Decimal(decimal) => decimal.parse().ok(),
+        _ => None,
+    }
+}
+
+impl Condition {
+    /// Value of the condition for `row`, `None` when unknown
+    fn eval(&self, row: &[ColumnValue]) -> Option<bool> {
+        match self {
+            Condition::And(left, right) => match (left.eval(row), right.eval(row)) {
+                (Some(false), _) | (_, Some(false)) => Some(false),
+                (Some(true), Some(true)) => Some(true),
+                _ => None,
+            },
+            Condition::Or(left, right) => match (left.eval(row), right.eval(row)) {
+                (Some(true), _) | (_, Some(true)) => Some(true),
+                (Some(false), Some(false)) => Some(false),
+                _ => None,
+            },
+            Condition::Not(condition) => condition.eval(row).map(|value| !value),
+            Condition::Compare(left, op, right) => {
+                let ordering = compare(&left.value(row), &right.value(row))?;
+                Some(match op {
+                    BinaryOperator::Eq => ordering == Ordering::Equal,
+                    BinaryOperator::NotEq => ordering != Ordering::Equal,
+                    BinaryOperator::Lt => ordering == Ordering::Less,
+                    BinaryOperator::LtEq => ordering != Ordering::Greater,
+                    BinaryOperator::Gt => ordering == Ordering::Greater,
+                    BinaryOperator::GtEq => ordering != Ordering::Less,
+                    _ => unreachable!("{op} is not a comparison"),
+                })
+            }
+            Condition::In {
+                operand,
+                list,
+                negated,
+            } => {
+                let value = operand.value(row);
+                let mut unknown = false;
+                for item in list {
+                    match compare(&value, &item.value(row)) {
+                        Some(Ordering::Equal) => return Some(!negated),
+                        Some(_) => {}
+                        None => unknown = true,
+                    }
+                }
+                (!unknown).then_some(*negated)
+            }
+            Condition::IsNull(operand) => Some(matches!(*operand.value(row), ColumnValue::Null)),
+            Condition::Like {
+                operand,
+                pattern,
+                negated,
+            } => match operand.value(row).as_ref() {
+                ColumnValue::String(value) => Some(pattern.is_match(value) != *negated),
+                _ => None,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_table_condition, Predicate};
+    use crate::line_parser::{parse_line, ColumnValue, Line};
+
+    #[test]
+    fn filter_rows() {
+        let Line::CreateTable(_, schema) = parse_line(
+            "CREATE TABLE orders (id INT, tenant_id INT, status TEXT, price FLOAT, created_at DATETIME)",
+        )
+        .unwrap() else {
+            panic!("Not a CREATE TABLE statement");
+        };
+        let row = |id: i64, tenant_id: Option<i64>, status: &str, price: f64, created_at: &str| {
+            vec![
+                ColumnValue::Integer(id),
+                tenant_id.map_or(ColumnValue::Null, ColumnValue::Integer),
+                ColumnValue::String(status.into()),
+                ColumnValue::Float(price),
+                ColumnValue::String(created_at.into()),
+            ]
+        };
+        let rows = [
+            row(1, Some(42), "paid", 10.0, "2023-12-31 23:59:59"),
+            row(2, Some(42), "deleted", 5.5, "2024-01-01 00:00:00"),
+            row(3, None, "paid", 7.0, "2024-03-01 12:00:00"),
+            row(4, Some(7), "pending_review", 12.0, "2024-06-01 08:00:00"),
+        ];
+        let ids = |condition: &str| -> Vec<i64> {
+            let predicate = Predicate::new(condition, &schema).unwrap();
+            rows.iter()
+                .filter(|row| predicate.matches(row))
+                .map(|row| match row[0] {
+                    ColumnValue::Integer(id) => id,
+                    _ => unreachable!(),
+                })
+                .collect()
+        };
+        assert_eq!(
+            ids("created_at >= '2024-01-01' AND status != 'deleted'"),
+            [3, 4]
+        );
+        assert_eq!(ids("tenant_id = 42"), [1, 2]);
+        assert_eq!(ids("tenant_id = '42'"), [1, 2]);
+        // unknown for NULL values
+        assert_eq!(ids("tenant_id <> 42"), [4]);
+        assert_eq!(ids("NOT tenant_id = 42"), [4]);
+        assert_eq!(ids("tenant_id IS NULL OR price > 11"), [3, 4]);
+        assert_eq!(ids("tenant_id IS NOT NULL"), [1, 2, 4]);
+        assert_eq!(ids("id IN (1, 3, 5)"), [1, 3]);
+        assert_eq!(ids("tenant_id NOT IN (42)"), [4]);
+        assert_eq!(ids("status LIKE 'pa%'"), [1, 3]);
+        assert_eq!(ids("status LIKE 'pending\\_%'"), [4]);
+        assert_eq!(ids("status NOT LIKE '%d'"), [4]);
+        assert_eq!(ids("price BETWEEN 5 AND 10"), [1, 2, 3]);
+        assert_eq!(ids("(id = 1 OR id = 2) AND NOT (status = 'deleted')"), [1]);
+        assert_eq!(ids("42 = tenant_id"), [1, 2]);
+
+        assert!(Predicate::new("missing = 1", &schema).is_err());
+        assert!(Predicate::new("created_at > 'yesterday'", &schema).is_err());
+        assert!(Predicate::new("id + 1 = 2", &schema).is_err());
+        assert!(Predicate::new("id = 1 id", &schema).is_err());
+    }
+
+    #[test]
+    fn filter_dates() {
+        let Line::CreateTable(_, schema) = parse_line("CREATE TABLE event (day DATE)").unwrap()
+        else {
+            panic!("Not a CREATE TABLE statement");
+        };
+        let rows = ["2023-12-31", "2024-01-01", "2024-01-02"]
+            .map(|day| vec![ColumnValue::String(day.into())]);
+        let days = |condition: &str| -> Vec<&str> {
+            let predicate = Predicate::new(condition, &schema).unwrap();
+            rows.iter()
+                .filter(|row| predicate.matches(row))
+                .map(|row| match &row[0] {
+                    ColumnValue::String(day) => day.as_str(),
+                    _ => unreachable!(),
+                })
+                .collect()
+        };
+        assert_eq!(days("day >= '2024-01-01'"), ["2024-01-01", "2024-01-02"]);
+        assert_eq!(days("day = '2024-01-01 00:00:00'"), ["2024-01-01"]);
+        assert_eq!(
+            days("day < '2024-01-01 12:00:00'"),
+            ["2023-12-31", "2024-01-01"]
+        );
+        assert_eq!(days("day IN ('2024-01-02')"), ["2024-01-02"]);
+        assert_eq!(
+            days("day BETWEEN '2023-12-31' AND '2024-01-01'"),
+            ["2023-12-31", "2024-01-01"]
+        );
+    }
+
+    #[test]
+    fn table_condition() {
+        assert_eq!(
+            parse_table_condition("orders: created_at >= '2024-01-01 10:00:00'"),
+            Ok((
+                "orders".into(),
+                "created_at >= '2024-01-01 10:00:00'".into()
+            ))
+        );
+        assert!(parse_table_condition("orders").is_err());
+        assert!(parse_table_condition(": id = 1").is_err());
+        assert!(parse_table_condition("orders: id = ").is_err());
+    }
+}
@@ -0,0 +1,472 @@
+//! Profile of a dump: what each column holds and which values would not convert.
+//!
+//! Rows go through the same pipeline as a conversion (filters, samples, transforms and
+//! type overrides) and are checked with [`output_value`], but nothing is written.
+
+use std::{
+    cmp::Ordering,
+    collections::{hash_map::DefaultHasher, HashMap},
+    fmt::Write as _,
+    fs::File,
+    hash::Hasher,
+    io::BufWriter,
+    path::Path,
+};
+
+use arrow::datatypes::TimeUnit;
+use chrono::NaiveDateTime;
+use color_eyre::eyre::{Context, Result};
+use indicatif::ProgressBar;
+use serde::{Serialize, Serializer};
+
+use crate::{
+    config::Config,
+    filter::{TableFilter, TableRows},
+    input::{self, Compression},
+    line_parser::{self, ColumnType, ColumnValue, Line, Schema, UnsupportedTypes},
+    parquet_writer::output_value,
+    predicate, read_statements,
+};
+
+/// Values that cannot be converted listed per column, the others are only counted
+const MAX_FAILED_VALUES: usize = 5;
+
+#[derive(Debug, Serialize)]
+pub struct TableProfile {
+    /// MySQL name
+    table: String,
+    rows: u64,
+    columns: Vec<ColumnProfile>,
+    /// columns whose type is not supported, the table cannot be converted
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    unsupported_columns: Vec<(String, String)>,
+}
+
+#[derive(Debug, Serialize)]
+struct ColumnProfile {
+    /// MySQL name
+    name: String,
+    arrow_type: String,
+    #[serde(skip)]
+    column_type: ColumnType,
+    #[serde(skip)]
+    time_unit: TimeUnit,
+    nulls: u64,
+    #[serde(serialize_with = "serialize_value")]
+    min: Option<ColumnValue>,
+    #[serde(serialize_with = "serialize_value")]
+    max: Option<ColumnValue>,
+    /// approximate number of distinct values, nulls excluded
+    #[serde(rename = "distinct", serialize_with = "serialize_estimate")]
+    distinct: HyperLogLog,
+    /// in characters, for strings
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_length: Option<usize>,
+    /// values that cannot be converted to the column type
+    failures: u64,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    failed_values: Vec<String>,
+}
+
+/// Profile the rows of the `tables` of the `inputs`, in `CREATE TABLE` order
+pub fn profile(
+    inputs: &[String],
+    compression: Compression,
+    tables: &TableFilter,
+    config: &Config,
+    progress_bar: &ProgressBar,
+) -> Result<Vec<TableProfile>> {
+    let mut profiles: Vec<TableProfile> = Vec::new();
+    // position in `profiles`, rows and output schema of every table
+    let mut profiled: HashMap<String, (usize, TableRows, Schema)> = HashMap::new();
+    let mut current_statement = String::with_capacity(8192);
+    let inputs: Vec<Option<&str>> = if inputs.is_empty() {
+        vec![None]
+    } else {
+        inputs.iter().map(|file| Some(file.as_str())).collect()
+    };
+    for file in inputs {
+        if let Some(file) = file {
+            progress_bar.set_message(file.to_string());
+        }
+        let mut reader = input::open(file, compression)?;
+        read_statements(
+            &mut reader,
+            &mut current_statement,
+            progress_bar,
+            tables,
+            |statement| {
+                match line_parser::parse_line(&statement)? {
+                    Line::CreateTable(table_name, mut schema) => {
+                        if profiled.contains_key(&table_name) {
+                            // same table found in another input file
+                            return Ok(());
+                        }
+                        let table_rows = match config.table_rows(&table_name, &mut schema, tables) {
+                            Ok(table_rows) => table_rows,
+                            Err(e) => {
+                                let types = e.downcast::<UnsupportedTypes>()?;
+                                if !profiles.iter().any(|p| p.table == types.table_name) {
+                                    profiles.push(TableProfile {
+                                        table: types.table_name,
+                                        rows: 0,
+                                        columns: Vec::new(),
+                                        unsupported_columns: types.columns,
+                                    });
+                                }
+                                return Ok(());
+                            }
+                        };
+                        profiled.insert(
+                            table_name.clone(),
+                            (profiles.len(), table_rows, schema.clone()),
+                        );
+                        profiles.push(TableProfile::new(table_name, &schema));
+                    }
+                    Line::InsertInto(table_name, mut rows) => {
+                        // rows of tables without a supported `CREATE TABLE` are ignored
+                        if let Some((i, table_rows, schema)) = profiled.get(&table_name) {
+                            table_rows.apply(&mut rows);
+                            profiles[*i].add_rows(schema, rows);
+                        }
+                    }
+                    Line::NOP => {}
+                }
+                Ok(())
+            },
+        )?;
+    }
+    for (i, table_rows, schema) in profiled.values() {
+        profiles[*i].add_rows(schema, table_rows.sampled_rows());
+    }
+    config.check_projected_tables(tables, |table_name| {
+        profiles.iter().any(|profile| profile.table == table_name)
+    })?;
+    Ok(profiles)
+}
+
+impl TableProfile {
+    fn new(table: String, schema: &Schema) -> TableProfile {
+        let arrow_schema = schema.to_arrow_schema();
+        TableProfile {
+            table,
+            rows: 0,
+            columns: schema
+                .columns
+                .iter()
+                .zip(arrow_schema.fields())
+                .map(|(column, field)| ColumnProfile {
+                    name: column.column_name.clone(),
+                    arrow_type: field.data_type().to_string(),
+                    column_type: column.column_type.clone(),
+                    time_unit: column.time_unit(),
+                    nulls: 0,
+                    min: None,
+                    max: None,
+                    distinct: HyperLogLog::default(),
+                    max_length: None,
+                    failures: 0,
+                    failed_values: Vec::new(),
+                })
+                .collect(),
+            unsupported_columns: Vec::new(),
+        }
+    }
+
+    fn add_rows(&mut self, schema: &Schema, rows: Vec<Vec<ColumnValue>>) {
+        self.rows += rows.len() as u64;
+        for row in rows {
+            for ((column, def), value) in self.columns.iter_mut().zip(&schema.columns).zip(row) {
+                match output_value(def, value) {
+                    Ok(value) => column.add(value),
+                    Err(e) => {
+                        column.failures += 1;
+                        if column.failed_values.len() < MAX_FAILED_VALUES {
+                            column.failed_values.push(e);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl ColumnProfile {
+    fn add(&mut self, value: ColumnValue) {
+        if value == ColumnValue::Null {
+            self.nulls += 1;
+            return;
+        }
+        self.distinct.insert(&value);
+        if let ColumnValue::String(s) = &value {
+            let length = s.chars().count();
+            self.max_length = Some(self.max_length.map_or(length, |max| max.max(length)));
+        }
+        if self
+            .min
+            .as_ref()
+            .is_none_or(|min| predicate::compare(&value, min) == Some(Ordering::Less))
+        {
+            self.min = Some(value.clone());
+        }
+        if self
+            .max
+            .as_ref()
+            .is_none_or(|max| predicate::compare(&value, max) == Some(Ordering::Greater))
+        {
+            self.max = Some(value);
+        }
+    }
+
+    /// `value` as written in reports, timestamps as dates
+    fn display(&self, value: &ColumnValue) -> String {
+        match (&self.column_type, value) {
+            (ColumnType::Timestamp, ColumnValue::Integer(timestamp)) => {
+                let datetime = match self.time_unit {
+                    TimeUnit::Millisecond => NaiveDateTime::from_timestamp_millis(*timestamp),
+                    TimeUnit::Microsecond => NaiveDateTime::from_timestamp_micros(*timestamp),
+                    _ => NaiveDateTime::from_timestamp_opt(*timestamp, 0),
+                };
+                match datetime {
+                    Some(datetime) => datetime.to_string(),
+                    None => timestamp.to_string(),
+                }
+            }
+            (_, ColumnValue::String(s) | ColumnValue::Decimal(s)) => s.clone(),
+            (_, ColumnValue::Integer(i)) => i.to_string(),
+            (_, ColumnValue::Float(f)) => f.to_string(),
+            (_, ColumnValue::Boolean(b)) => b.to_string(),
+            (_, ColumnValue::Null) => String::new(),
+        }
+    }
+}
+
+fn serialize_value<S: Serializer>(
+    value: &Option<ColumnValue>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match value {
+        None | Some(ColumnValue::Null) => serializer.serialize_none(),
+        Some(ColumnValue::String(s) | ColumnValue::Decimal(s)) => serializer.serialize_str(s),
+        // timestamps are seconds (or the unit of their cast) since 1970
+        Some(ColumnValue::Integer(i)) => serializer.serialize_i64(*i),
+        Some(ColumnValue::Float(f)) => serializer.serialize_f64(*f),
+        Some(ColumnValue::Boolean(b)) => serializer.serialize_bool(*b),
+    }
+}
+
+fn serialize_estimate<S: Serializer>(hll: &HyperLogLog, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(hll.estimate())
+}
+
+/// Write `profiles` as JSON to `path`
+pub fn write_json(path: &Path, profiles: &[TableProfile]) -> Result<()> {
+    let file = File::create(path).with_context(|| format!("Cannot create {}", path.display()))?;
+    serde_json::to_writer_pretty(BufWriter::new(file), profiles)
+        .with_context(|| format!("Cannot write {}", path.display()))
+}
+
+/// `profiles` as a text table per table, with the values that cannot be converted
+pub fn render(profiles: &[TableProfile]) -> String {
+    const HEADER: [&str; 8] = [
+        "column", "type", "nulls", "distinct", "min", "max", "max len", "failures",
+    ];
+    let mut text = String::new();
+    for table in profiles {
+        writeln!(text, "{} ({} rows)", table.table, table.rows).unwrap();
+        for (name, mysql_type) in &table.unsupported_columns {
+            writeln!(text, "  unsupported type {mysql_type} of column {name}").unwrap();
+        }
+        let rows: Vec<[String; 8]> = table
+            .columns
+            .iter()
+            .map(|column| {
+                [
+                    column.name.clone(),
+                    column.arrow_type.clone(),
+                    column.nulls.to_string(),
+                    column.distinct.estimate().to_string(),
+                    column
+                        .min
+                        .as_ref()
+                        .map_or(String::new(), |v| column.display(v)),
+                    column
+                        .max
+                        .as_ref()
+                        .map_or(String::new(), |v| column.display(v)),
+                    column.max_length.map_or(String::new(), |l| l.to_string()),
+                    column.failures.to_string(),
+                ]
+            })
+            .collect();
+        if !rows.is_empty() {
+            let widths: Vec<usize> = (0..HEADER.len())
+                .map(|i| {
+                    rows.iter()
+                        .map(|row| truncate(&row[i]).chars().count())
+                        .chain([HEADER[i].len()])
+                        .max()
+                        .unwrap()
+                })
+                .collect();
+            let header = HEADER.map(String::from);
+            for row in [&header].into_iter().chain(&rows) {
+                let cells: Vec<String> = row
+                    .iter()
+                    .zip(&widths)
+                    .map(|(cell, width)| format!("{:width$}", truncate(cell)))
+                    .collect();
+                writeln!(text, "  {}", cells.join("  ").trim_end()).unwrap();
+            }
+        }
+        for column in table.columns.iter().filter(|c| c.failures > 0) {
+            writeln!(
+                text,
+                "  {} value(s) of {} cannot be converted, e.g.:",
+                column.failures, column.name
+            )
+            .unwrap();
+            for failure in &column.failed_values {
+                writeln!(text, "    {failure}").unwrap();
+            }
+        }
+        text.push('\n');
+    }
+    text
+}
+
+/// Long values, such as texts, cut to keep the table readable
+fn truncate(value: &str) -> String {
+    const MAX_WIDTH: usize = 30;
+    if value.chars().count() <= MAX_WIDTH {
+        return value.replace('\n', " ");
+    }
+    let cut: String = value.chars().take(MAX_WIDTH - 1).collect();
+    format!("{}…", cut.replace('\n', " "))
+}
+
+/// HyperLogLog distinct count estimate, with 2^12 registers (about 1.6% error)
+#[derive(Debug, Default)]
+struct HyperLogLog {
+    /// allocated on the first value, empty columns being common
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    const BITS: u32 = 12;
+
+    fn insert(&mut self, value: &ColumnValue) {
+        let mut hasher = DefaultHasher::new();
+        match value {
+            ColumnValue::Null => return,
+            ColumnValue::String(s) | ColumnValue::Decimal(s) => hasher.write(s.as_bytes()),
+            ColumnValue::Integer(i) => hasher.write_i64(*i),
+            ColumnValue::Float(f) => hasher.write_u64(f.to_bits()),
+            ColumnValue::Boolean(b) => hasher.write_u8(*b as u8),
+        }
+        let hash = hasher.finish();
+        if self.registers.is_empty() {
+            self.registers = vec![0; 1 << Self::BITS];
+        }
+        let index = (hash >> (64 - Self::BITS)) as usize;
+        // the marker bit bounds the rank when the remaining bits are all zeros
+        let rank = ((hash << Self::BITS) | (1 << (Self::BITS - 1))).leading_zeros() as u8 + 1;
+        self.registers[index] = self.registers[index].max(rank);
+    }
+
+    fn estimate(&self) -> u64 {
+        if self.registers.is_empty() {
+            return 0;
+        }
+        let m = self.registers.len() as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|&rank| 2f64.powi(-i32::from(rank)))
+            .sum();
+        let estimate = alpha * m * m / sum;
+        let zeros = self.registers.iter().filter(|&&rank| rank == 0).count();
+        if estimate <= 2.5 * m && zeros > 0 {
+            // linear counting is more accurate for small cardinalities
+            (m * (m / zeros as f64).ln()).round() as u64
+        } else {
+            estimate.round() as u64
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{HyperLogLog, TableProfile};
+    use crate::{
+        config::Config,
+        filter::TableFilter,
+        line_parser::{parse_line, ColumnValue, Line},
+    };
+
+    #[test]
+    fn estimate_distinct() {
+        let mut hll = HyperLogLog::default();
+        assert_eq!(hll.estimate(), 0);
+        for i in 0..100_000 {
+            hll.insert(&ColumnValue::Integer(i % 50_000));
+        }
+        let estimate = hll.estimate() as f64;
+        assert!((estimate - 50_000.0).abs() < 50_000.0 * 0.05, "{estimate}");
+        let mut small = HyperLogLog::default();
+        for s in ["a", "b", "c", "a"] {
+            small.insert(&ColumnValue::String(s.into()));
+        }
+        assert_eq!(small.estimate(), 3);
+    }
+
+    #[test]
+    fn profile_rows() {
+        let Line::CreateTable(table_name, mut schema) = parse_line(
+            "CREATE TABLE user (id INT, name VARCHAR(10), created_at DATETIME, score DOUBLE)",
+        )
+        .unwrap() else {
+            panic!("Not a CREATE TABLE statement");
+        };
+        Config::default()
+            .table_rows(&table_name, &mut schema, &TableFilter::default())
+            .unwrap();
+        let mut profile = TableProfile::new(table_name, &schema);
+        let Line::InsertInto(_, rows) = parse_line(
+            "INSERT INTO user VALUES (2,'bob','2024-01-02 03:04:05',1),(1,'alice',NULL,2.5),\
+             (3,NULL,'0000-00-00 00:00:00',NULL);",
+        )
+        .unwrap() else {
+            panic!("Not an INSERT statement");
+        };
+        profile.add_rows(&schema, rows);
+        let json = serde_json::to_value(&profile).unwrap();
+        assert_eq!(json["rows"], 3);
+        assert_eq!(
+            json["columns"][0],
+            serde_json::json!({
+                "name": "id",
+                "arrow_type": "Int64",
+                "nulls": 0,
+                "min": 1,
+                "max": 3,
+                "distinct": 3,
+                "failures": 0,
+            })
+        );
+        assert_eq!(json["columns"][1]["min"], "alice");
+        assert_eq!(json["columns"][1]["max_length"], 5);
+        assert_eq!(json["columns"][1]["nulls"], 1);
+        assert_eq!(json["columns"][2]["min"], 1_704_164_645);
+        assert_eq!(json["columns"][2]["failures"], 1);
+        assert_eq!(json["columns"][3]["min"], 1.0);
+        let text = super::render(&[profile]);
+        assert!(text.starts_with("user (3 rows)\n"), "{text}");
+        assert!(text.contains("2024-01-02 03:04:05"), "{text}");
+        assert!(
+            text.contains("1 value(s) of created_at cannot be converted"),
+            "{text}"
+        );
+    }
+}
@@ -0,0 +1,334 @@
+//! Deterministic sampling and row limits.
+//!
+//! Rows are sampled on a hash of their PRIMARY KEY columns (of every column when there is
+//! none), which only depends on the key values: tables sharing a key, such as `user.id`
+//! and `user_settings.user_id`, keep the same keys.
+
+use std::{
+    collections::{BinaryHeap, HashMap},
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::line_parser::{ColumnKey, ColumnValue, Schema};
+
+/// Size of the sample of a table: a percentage (`1%`, `0.5%`) or a number of rows
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "RawSampleSize")]
+pub enum SampleSize {
+    /// share of the rows, between 0 and 1
+    Fraction(f64),
+    Rows(usize),
+}
+
+impl FromStr for SampleSize {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        match value.strip_suffix('%') {
+            Some(percent) => match percent.trim().parse::<f64>() {
+                Ok(percent) if (0.0..=100.0).contains(&percent) => {
+                    Ok(SampleSize::Fraction(percent / 100.0))
+                }
+                _ => Err(format!(
+                    "Expected a percentage between 0% and 100%, got `{value}`"
+                )),
+            },
+            None => value
+                .parse()
+                .map(SampleSize::Rows)
+                .map_err(|_| format!("Expected a percentage or a number of rows, got `{value}`")),
+        }
+    }
+}
+
+/// Sample size as written in the configuration file: `sample = "1%"` or `sample = 10000`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawSampleSize {
+    Rows(usize),
+    String(String),
+}
+
+impl TryFrom<RawSampleSize> for SampleSize {
+    type Error = String;
+
+    fn try_from(value: RawSampleSize) -> Result<Self, Self::Error> {
+        match value {
+            RawSampleSize::Rows(rows) => Ok(SampleSize::Rows(rows)),
+            RawSampleSize::String(value) => value.parse(),
+        }
+    }
+}
+
+/// Parse a `--sample` argument: `1%` for every table, or `table:1%` and `table:10000`
+pub fn parse_sample(value: &str) -> Result<(Option<String>, SampleSize), String> {
+    match value.rsplit_once(':') {
+        Some(("", _)) => Err(format!("Expected [TABLE:]SIZE, got `{value}`")),
+        Some((table_name, size)) => Ok((Some(table_name.to_string()), size.parse()?)),
+        None => Ok((None, value.parse()?)),
+    }
+}
+
+/// Sampling of the rows of a table
+#[derive(Clone, Debug)]
+pub struct RowSample {
+    /// positions of the key columns in `CREATE TABLE` order
+    key: Vec<usize>,
+    size: SampleSize,
+    /// rows with the smallest key hashes of a row count sample, shared by the chunks of
+    /// a table, and the number of rows seen
+    kept: Arc<Mutex<(BinaryHeap<Sampled>, u64)>>,
+}
+
+/// Row of a row count sample, ordered by hash
+#[derive(Debug)]
+struct Sampled {
+    hash: u64,
+    /// arrival order, telling apart rows with the same key
+    order: u64,
+    row: Vec<ColumnValue>,
+}
+
+impl PartialEq for Sampled {
+    fn eq(&self, other: &Self) -> bool {
+        (self.hash, self.order) == (other.hash, other.order)
+    }
+}
+
+impl Eq for Sampled {}
+
+impl PartialOrd for Sampled {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Sampled {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.hash, self.order).cmp(&(other.hash, other.order))
+    }
+}
+
+impl RowSample {
+    pub fn new(schema: &Schema, size: SampleSize) -> RowSample {
+        let mut key: Vec<usize> = schema
+            .columns
+            .iter()
+            .enumerate()
+            .filter(|(_, column)| column.key == ColumnKey::Primary)
+            .map(|(i, _)| i)
+            .collect();
+        if key.is_empty() {
+            key = (0..schema.columns.len()).collect();
+        }
+        RowSample {
+            key,
+            size,
+            kept: Arc::default(),
+        }
+    }
+
+    /// Whether rows are only known once every row has been seen, see [`RowSample::take`]
+    pub fn is_buffered(&self) -> bool {
+        matches!(self.size, SampleSize::Rows(_))
+    }
+
+    /// Drop the rows out of a percentage sample, or move rows to the buffer of a row
+    /// count sample
+    pub fn rows(&self, rows: &mut Vec<Vec<ColumnValue>>) {
+        match self.size {
+            SampleSize::Fraction(fraction) if fraction >= 1.0 => {}
+            SampleSize::Fraction(fraction) => {
+                let threshold = (fraction * 2f64.powi(64)) as u64;
+                rows.retain(|row| self.hash(row) < threshold);
+            }
+            SampleSize::Rows(count) => {
+                let (kept, seen) = &mut *self.kept.lock().unwrap();
+                for row in rows.drain(..) {
+                    let sampled = Sampled {
+                        hash: self.hash(&row),
+                        order: *seen,
+                        row,
+                    };
+                    *seen += 1;
+                    if kept.len() < count {
+                        kept.push(sampled);
+                    } else if kept.peek().is_some_and(|max| sampled < *max) {
+                        kept.pop();
+                        kept.push(sampled);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Rows of a row count sample, by key hash
+    pub fn take(&self) -> Vec<Vec<ColumnValue>> {
+        let kept = std::mem::take(&mut self.kept.lock().unwrap().0);
+        kept.into_sorted_vec()
+            .into_iter()
+            .map(|sampled| sampled.row)
+            .collect()
+    }
+
+    fn hash(&self, row: &[ColumnValue]) -> u64 {
+        let mut hasher = Sha256::new();
+        for &i in &self.key {
+            match &row[i] {
+                ColumnValue::Null => hasher.update(b"\\N"),
+                ColumnValue::String(s) | ColumnValue::Decimal(s) => hasher.update(s.as_bytes()),
+                ColumnValue::Integer(n) => hasher.update(n.to_string()),
+                ColumnValue::Float(f) => hasher.update(f.to_string()),
+                ColumnValue::Boolean(b) => hasher.update(if *b { "1" } else { "0" }),
+            }
+            hasher.update([0x1f]);
+        }
+        let digest = hasher.finalize();
+        u64::from_be_bytes(digest[..8].try_into().unwrap())
+    }
+}
+
+/// Rows left to write of the tables with a `--limit`, shared by readers and writers so
+/// that readers skip the `INSERT` statements of tables whose limit is reached
+#[derive(Clone, Debug, Default)]
+pub struct RowLimits(Arc<Mutex<HashMap<String, usize>>>);
+
+impl RowLimits {
+    /// Limit `table_name` to `limit` rows, unless it is already limited (a table found
+    /// in several files)
+    pub fn start(&self, table_name: &str, limit: usize) {
+        self.0
+            .lock()
+            .unwrap()
+            .entry(table_name.to_string())
+            .or_insert(limit);
+    }
+
+    /// Keep the rows of `table_name` within its limit
+    pub fn rows(&self, table_name: &str, rows: &mut Vec<Vec<ColumnValue>>) {
+        if let Some(left) = self.0.lock().unwrap().get_mut(table_name) {
+            rows.truncate(*left);
+            *left -= rows.len();
+        }
+    }
+
+    pub fn is_reached(&self, table_name: &str) -> bool {
+        self.0.lock().unwrap().get(table_name) == Some(&0)
+    }
+
+    /// Limit `table_name` to `limit` rows, see [`RowLimits::start`]
+    pub fn table(&self, table_name: &str, limit: usize) -> TableLimit {
+        self.start(table_name, limit);
+        TableLimit {
+            table_name: table_name.to_string(),
+            limits: self.clone(),
+        }
+    }
+}
+
+/// Row limit of a table
+#[derive(Clone, Debug)]
+pub struct TableLimit {
+    table_name: String,
+    limits: RowLimits,
+}
+
+impl TableLimit {
+    pub fn rows(&self, rows: &mut Vec<Vec<ColumnValue>>) {
+        self.limits.rows(&self.table_name, rows);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_sample, RowLimits, RowSample, SampleSize};
+    use crate::line_parser::{parse_line, ColumnValue, Line, Schema};
+
+    fn schema(create_table: &str) -> Schema {
+        let Line::CreateTable(_, schema) = parse_line(create_table).unwrap() else {
+            panic!("Not a CREATE TABLE statement");
+        };
+        schema
+    }
+
+    #[test]
+    fn parse_samples() {
+        assert_eq!(parse_sample("1%"), Ok((None, SampleSize::Fraction(0.01))));
+        assert_eq!(
+            parse_sample("users:10000"),
+            Ok((Some("users".into()), SampleSize::Rows(10000)))
+        );
+        assert_eq!(
+            parse_sample("users:50%"),
+            Ok((Some("users".into()), SampleSize::Fraction(0.5)))
+        );
+        assert!(parse_sample("users:").is_err());
+        assert!(parse_sample(":1%").is_err());
+        assert!(parse_sample("101%").is_err());
+        assert!(parse_sample("0.5").is_err());
+    }
+
+    #[test]
+    fn sample_rows() {
+        let user = schema("CREATE TABLE user (id INT NOT NULL, name TEXT, PRIMARY KEY (id))");
+        let settings = schema(
+            "CREATE TABLE user_settings (theme TEXT, user_id INT NOT NULL, PRIMARY KEY (user_id))",
+        );
+        let users: Vec<Vec<ColumnValue>> = (0..1000)
+            .map(|id| vec![ColumnValue::Integer(id), ColumnValue::String("x".into())])
+            .collect();
+        let mut user_sample = users.clone();
+        RowSample::new(&user, SampleSize::Fraction(0.1)).rows(&mut user_sample);
+        assert!((50..150).contains(&user_sample.len()));
+
+        // same keys in another table, same sample
+        let mut settings_sample: Vec<Vec<ColumnValue>> = (0..1000)
+            .map(|id| vec![ColumnValue::Null, ColumnValue::Integer(id)])
+            .collect();
+        RowSample::new(&settings, SampleSize::Fraction(0.1)).rows(&mut settings_sample);
+        let ids = |rows: &[Vec<ColumnValue>], i: usize| -> Vec<ColumnValue> {
+            rows.iter().map(|row| row[i].clone()).collect()
+        };
+        assert_eq!(ids(&user_sample, 0), ids(&settings_sample, 1));
+
+        // rows with the smallest hashes, whatever the batches
+        let sample = RowSample::new(&user, SampleSize::Rows(10));
+        assert!(sample.is_buffered());
+        for batch in users.chunks(64) {
+            let mut batch = batch.to_vec();
+            sample.rows(&mut batch);
+            assert!(batch.is_empty());
+        }
+        let rows = sample.take();
+        assert_eq!(rows.len(), 10);
+        let mut all = users.clone();
+        let sample_all = RowSample::new(&user, SampleSize::Rows(10));
+        sample_all.rows(&mut all);
+        assert_eq!(sample_all.take(), rows);
+        assert!(sample.take().is_empty());
+    }
+
+    #[test]
+    fn limit_rows() {
+        let limits = RowLimits::default();
+        limits.start("user", 3);
+        let mut rows = vec![vec![ColumnValue::Integer(1)]; 2];
+        limits.rows("user", &mut rows);
+        assert_eq!(rows.len(), 2);
+        assert!(!limits.is_reached("user"));
+        let mut rows = vec![vec![ColumnValue::Integer(1)]; 2];
+        limits.rows("user", &mut rows);
+        assert_eq!(rows.len(), 1);
+        assert!(limits.is_reached("user"));
+        limits.start("user", 3);
+        assert!(limits.is_reached("user"));
+        let mut rows = vec![vec![ColumnValue::Integer(1)]; 2];
+        limits.rows("other", &mut rows);
+        assert_eq!(rows.len(), 2);
+    }
+}
@@ -0,0 +1,279 @@
+//! Schema-only mode: the schemas of the tables, for review before a conversion.
+//!
+//! `{table}.schema.json` describes the output of a table, once configured: its MySQL
+//! and Arrow types, nullability, keys and comments. `INSERT` statements are skipped by
+//! the reader from their first line.
+
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::eyre::{bail, eyre, Context, Result};
+use indicatif::ProgressBar;
+use serde::Serialize;
+
+use crate::{
+    config::Config,
+    filter::TableFilter,
+    input::{self, Compression},
+    line_parser::{self, ColumnKey, ForeignKey, Line, Schema, UnsupportedTypes},
+    memory, read_statements,
+};
+
+/// Suffix of the schema files
+const SCHEMA_SUFFIX: &str = ".schema.json";
+
+#[derive(Debug, Serialize)]
+struct TableSchema<'a> {
+    /// MySQL name
+    table: &'a str,
+    /// output name, also the name of its files
+    name: String,
+    columns: Vec<ColumnSchema<'a>>,
+    foreign_keys: Vec<ForeignKeySchema<'a>>,
+    /// columns whose type is not supported, the table cannot be converted
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    unsupported_columns: Vec<UnsupportedColumn<'a>>,
+    create_table: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+struct ColumnSchema<'a> {
+    /// MySQL name
+    name: &'a str,
+    /// output name
+    field: &'a str,
+    mysql_type: &'a str,
+    arrow_type: String,
+    nullable: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    key: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    comment: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    default: Option<&'a str>,
+}
+
+#[derive(Debug, Serialize)]
+struct ForeignKeySchema<'a> {
+    columns: &'a [String],
+    referenced_table: &'a str,
+    referenced_columns: &'a [String],
+}
+
+#[derive(Debug, Serialize)]
+struct UnsupportedColumn<'a> {
+    name: &'a str,
+    mysql_type: &'a str,
+}
+
+/// Write the schema of the `tables` of the `inputs` in `output_dir`. Tables are also
+/// sent to `writer`, if any, to write empty files with their schema. Tables with
+/// unsupported types are listed once every schema is written.
+pub fn export(
+    inputs: &[String],
+    compression: Compression,
+    tables: &TableFilter,
+    config: &Config,
+    output_dir: &Path,
+    writer: Option<&memory::Sender<Line>>,
+    progress_bar: &ProgressBar,
+) -> Result<()> {
+    let mut seen = HashSet::new();
+    let mut unsupported: Vec<UnsupportedTypes> = Vec::new();
+    let mut current_statement = String::with_capacity(8192);
+    let inputs: Vec<Option<&str>> = if inputs.is_empty() {
+        vec![None]
+    } else {
+        inputs.iter().map(|file| Some(file.as_str())).collect()
+    };
+    for file in inputs {
+        if let Some(file) = file {
+            progress_bar.set_message(file.to_string());
+        }
+        let mut reader = input::open(file, compression)?;
+        read_statements(
+            &mut reader,
+            &mut current_statement,
+            progress_bar,
+            tables,
+            |statement| {
+                let Line::CreateTable(table_name, schema) = line_parser::parse_line(&statement)?
+                else {
+                    return Ok(());
+                };
+                if !seen.insert(table_name.clone()) {
+                    // same table found in another input file
+                    return Ok(());
+                }
+                let mut output_schema = schema.clone();
+                if let Err(e) = config.table_rows(&table_name, &mut output_schema, tables) {
+                    let types = e.downcast::<UnsupportedTypes>()?;
+                    write_unsupported(output_dir, config, &statement, &types)?;
+                    unsupported.push(types);
+                    return Ok(());
+                }
+                write(
+                    &schema_path(output_dir, config, &table_name),
+                    &table_schema(&table_name, config, &output_schema),
+                )?;
+                if let Some(writer) = writer {
+                    writer
+                        .send(Line::CreateTable(table_name, schema))
+                        .map_err(|_| eyre!("Cannot send schema to writer"))?;
+                }
+                Ok(())
+            },
+        )?;
+    }
+    if !unsupported.is_empty() {
+        let tables: Vec<String> = unsupported.iter().map(ToString::to_string).collect();
+        bail!(
+            "{} table(s) cannot be converted:\n{}",
+            tables.len(),
+            tables.join("\n")
+        );
+    }
+    config.check_projected_tables(tables, |table_name| seen.contains(table_name))
+}
+
+fn schema_path(output_dir: &Path, config: &Config, table_name: &str) -> PathBuf {
+    output_dir.join(format!("{}{SCHEMA_SUFFIX}", config.output_name(table_name)))
+}
+
+fn table_schema<'a>(table_name: &'a str, config: &Config, schema: &'a Schema) -> TableSchema<'a> {
+    let arrow_schema = schema.to_arrow_schema();
+    TableSchema {
+        table: table_name,
+        name: config.output_name(table_name),
+        columns: schema
+            .columns
+            .iter()
+            .zip(arrow_schema.fields())
+            .map(|(column, field)| ColumnSchema {
+                name: &column.column_name,
+                field: &column.field_name,
+                mysql_type: &column.mysql_type,
+                arrow_type: field.data_type().to_string(),
+                nullable: column.nullable,
+                key: match column.key {
+                    ColumnKey::None => None,
+                    ColumnKey::Primary => Some("primary"),
+                    ColumnKey::Unique => Some("unique"),
+                },
+                comment: column.comment.as_deref(),
+                default: column.default.as_deref(),
+            })
+            .collect(),
+        foreign_keys: schema.foreign_keys.iter().map(foreign_key).collect(),
+        unsupported_columns: Vec::new(),
+        create_table: &schema.create_table,
+    }
+}
+
+fn foreign_key(fk: &ForeignKey) -> ForeignKeySchema<'_> {
+    ForeignKeySchema {
+        columns: &fk.columns,
+        referenced_table: &fk.referenced_table,
+        referenced_columns: &fk.referenced_columns,
+    }
+}
+
+/// Schema of a table with unsupported types, listing them
+fn write_unsupported(
+    output_dir: &Path,
+    config: &Config,
+    create_table: &str,
+    types: &UnsupportedTypes,
+) -> Result<()> {
+    let table_name = &types.table_name;
+    write(
+        &schema_path(output_dir, config, table_name),
+        &TableSchema {
+            table: table_name,
+            name: config.output_name(table_name),
+            columns: Vec::new(),
+            foreign_keys: Vec::new(),
+            unsupported_columns: types
+                .columns
+                .iter()
+                .map(|(name, mysql_type)| UnsupportedColumn { name, mysql_type })
+                .collect(),
+            create_table,
+        },
+    )
+}
+
+fn write(path: &Path, schema: &TableSchema) -> Result<()> {
+    let file = File::create(path).with_context(|| format!("Cannot create {}", path.display()))?;
+    serde_json::to_writer_pretty(BufWriter::new(file), schema)
+        .with_context(|| format!("Cannot write {}", path.display()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::table_schema;
+    use crate::{
+        config::Config,
+        filter::TableFilter,
+        line_parser::{parse_line, Line},
+    };
+
+    #[test]
+    fn describe_table() {
+        let Line::CreateTable(table_name, mut schema) = parse_line(
+            "CREATE TABLE `User` (\n  `id` bigint NOT NULL,\n  `companyId` int COMMENT 'owner',\n  `email` varchar(255) DEFAULT NULL,\n  PRIMARY KEY (`id`),\n  CONSTRAINT `fk` FOREIGN KEY (`companyId`) REFERENCES `company` (`id`)\n);",
+        )
+        .unwrap() else {
+            panic!("Not a CREATE TABLE statement");
+        };
+        let config: Config = toml::from_str(
+            r#"
+            salt = "s"
+            column_case = "snake"
+            [tables.User]
+            exclude_columns = ["email"]
+            [tables.User.columns.id]
+            transform = "hash"
+            "#,
+        )
+        .unwrap();
+        config
+            .table_rows(&table_name, &mut schema, &TableFilter::default())
+            .unwrap();
+        let json = serde_json::to_value(table_schema(&table_name, &config, &schema)).unwrap();
+        assert_eq!(json["table"], "User");
+        assert_eq!(json["name"], "user");
+        assert_eq!(
+            json["columns"],
+            serde_json::json!([
+                {
+                    "name": "id",
+                    "field": "id",
+                    "mysql_type": "BIGINT",
+                    "arrow_type": "Utf8",
+                    "nullable": false,
+                    "key": "primary",
+                },
+                {
+                    "name": "companyId",
+                    "field": "company_id",
+                    "mysql_type": "INT",
+                    "arrow_type": "Int64",
+                    "nullable": true,
+                    "comment": "owner",
+                },
+            ])
+        );
+        assert_eq!(
+            json["foreign_keys"],
+            serde_json::json!([
+                {"columns": ["companyId"], "referenced_table": "company", "referenced_columns": ["id"]}
+            ])
+        );
+        assert!(json.get("unsupported_columns").is_none());
+    }
+}
@@ -0,0 +1,311 @@
+//! Output formats.
+//!
+//! A [`Sink`] writes the record batches of a table, or of one part of it, to a file.
+//! Record batches are built by [`crate::parquet_writer`] whatever the format.
+
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use arrow::{datatypes::SchemaRef, record_batch::RecordBatch};
+use clap::ValueEnum;
+use color_eyre::eyre::{Context, Result};
+use parquet::{file::properties::WriterProperties, format::KeyValue};
+use serde::Deserialize;
+
+use crate::{config::Config, line_parser, manifest::PartInfo};
+
+pub mod delta;
+pub mod iceberg;
+mod ipc;
+mod parquet_file;
+pub mod sqlite;
+mod text;
+
+pub use text::TextOptions;
+
+/// Output format
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Format {
+    #[default]
+    Parquet,
+    /// Arrow IPC file, also known as Feather v2
+    Arrow,
+    /// Arrow IPC stream
+    ArrowStream,
+    Csv,
+    /// Newline delimited JSON
+    Ndjson,
+    /// Delta Lake table: parquet files and a `_delta_log` commit per conversion
+    Delta,
+    /// Apache Iceberg table: parquet data files, Avro manifests and table metadata
+    Iceberg,
+    /// SQLite database file holding every table, `--output` being its path
+    Sqlite,
+    // a DuckDB database (`.duckdb`, loaded with its appender from record batches) is
+    // left to a follow-up, which will add the `duckdb` dependency
+}
+
+impl Format {
+    /// Extension of the files written, e.g. `csv.gz`
+    pub fn extension(self, text: &TextOptions) -> String {
+        let extension = match self {
+            Format::Parquet | Format::Delta | Format::Iceberg => "parquet",
+            Format::Arrow => "arrow",
+            Format::ArrowStream => "arrows",
+            Format::Csv => "csv",
+            Format::Ndjson => "ndjson",
+            Format::Sqlite => "sqlite",
+        };
+        if self.is_text() && text.gzip() {
+            format!("{extension}.gz")
+        } else {
+            extension.to_string()
+        }
+    }
+
+    pub fn is_text(self) -> bool {
+        matches!(self, Format::Csv | Format::Ndjson)
+    }
+
+    /// Whether tables are committed to a table format rather than described by a manifest
+    pub fn is_table(self) -> bool {
+        matches!(self, Format::Delta | Format::Iceberg)
+    }
+
+    /// Whether every table is written to a single database file
+    pub fn is_database(self) -> bool {
+        matches!(self, Format::Sqlite)
+    }
+
+    /// Whether tables are committed once written (table formats and databases) rather
+    /// than described by a manifest
+    pub fn commits_tables(self) -> bool {
+        self.is_table() || self.is_database()
+    }
+
+    /// Directory of the files of a table in its dataset directory
+    pub fn data_dir(self, table_dir: &Path) -> PathBuf {
+        match self {
+            Format::Iceberg => table_dir.join(iceberg::DATA_DIR),
+            _ => table_dir.to_path_buf(),
+        }
+    }
+
+    /// Commit the `parts` of a table written in `output`: record them in a table format,
+    /// or index the table of a database when asked. `table_name` is the MySQL name.
+    pub fn commit(
+        self,
+        output: &Path,
+        table_name: &str,
+        schema: &line_parser::Schema,
+        config: &Config,
+        parts: &[PartInfo],
+    ) -> Result<()> {
+        let table_dir = output.join(config.output_name(table_name));
+        match self {
+            Format::Delta => delta::commit(&table_dir, table_name, schema, config, parts),
+            Format::Iceberg => iceberg::commit(&table_dir, table_name, schema, config, parts),
+            Format::Sqlite if config.create_indexes => {
+                sqlite::create_indexes(output, &config.output_name(table_name), schema)
+            }
+            Format::Sqlite => Ok(()),
+            _ => unreachable!("{self:?} tables are not committed"),
+        }
+    }
+
+    /// Create the file of a table, or of a part of it, at `path`
+    pub fn create_table(
+        self,
+        path: &Path,
+        schema: SchemaRef,
+        options: &SinkOptions,
+    ) -> Result<Box<dyn Sink>> {
+        Ok(match self {
+            Format::Parquet => Box::new(parquet_file::ParquetSink::create(path, schema, options)?),
+            Format::Arrow | Format::ArrowStream => {
+                Box::new(ipc::IpcSink::create(path, schema, options)?)
+            }
+            Format::Csv | Format::Ndjson => {
+                Box::new(text::TextSink::create(path, schema, options)?)
+            }
+            Format::Delta => Box::new(delta::DeltaSink::create(path, schema, options)?),
+            Format::Iceberg => Box::new(iceberg::IcebergSink::create(path, schema, options)?),
+            Format::Sqlite => Box::new(sqlite::SqliteSink::create(path, schema, options)?),
+        })
+    }
+}
+
+/// Settings of the files of a table
+pub struct SinkOptions {
+    pub format: Format,
+    pub parquet: WriterProperties,
+    /// metadata stored in every file
+    pub key_value_metadata: Vec<KeyValue>,
+    pub text: TextOptions,
+    /// name of the table, for databases
+    pub table_name: String,
+}
+
+impl SinkOptions {
+    pub fn extension(&self) -> String {
+        self.format.extension(&self.text)
+    }
+}
+
+/// Writer of the record batches of a file
+pub trait Sink {
+    fn create(path: &Path, schema: SchemaRef, options: &SinkOptions) -> Result<Self>
+    where
+        Self: Sized;
+
+    fn write(&mut self, batch: &RecordBatch) -> Result<()>;
+
+    /// Memory used by rows not written to the file yet
+    fn buffered_size(&self) -> usize;
+
+    /// Bytes written to the file so far
+    fn bytes_written(&self) -> u64;
+
+    /// Write buffered rows to the file
+    fn flush(&mut self) -> Result<()>;
+
+    /// Complete the file, describing it for the manifest
+    fn finish(self: Box<Self>) -> Result<PartInfo>;
+}
+
+/// Random (version 4) UUID
+pub fn random_uuid() -> String {
+    let mut bytes = [0; 16];
+    getrandom::getrandom(&mut bytes).expect("Cannot generate a random UUID");
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// File counting the bytes written to it, the count being shared with its sink
+struct CountingFile {
+    file: File,
+    bytes_written: Arc<AtomicU64>,
+}
+
+impl CountingFile {
+    fn create(path: &Path) -> Result<(CountingFile, Arc<AtomicU64>)> {
+        let file =
+            File::create(path).with_context(|| format!("Cannot create {}", path.display()))?;
+        let bytes_written = Arc::new(AtomicU64::new(0));
+        Ok((
+            CountingFile {
+                file,
+                bytes_written: bytes_written.clone(),
+            },
+            bytes_written,
+        ))
+    }
+}
+
+impl Write for CountingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.file.write(buf)?;
+        self.bytes_written
+            .fetch_add(written as u64, Ordering::Relaxed);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::testing::TempDir;
+    use std::{fs::File, sync::Arc};
+
+    use arrow::{
+        array::{Int64Array, StringArray},
+        datatypes::{DataType, Field, Schema},
+        ipc::reader::{FileReader, StreamReader},
+        record_batch::RecordBatch,
+    };
+    use parquet::{
+        arrow::arrow_reader::ParquetRecordBatchReaderBuilder, file::properties::WriterProperties,
+        format::KeyValue,
+    };
+
+    use super::{Format, SinkOptions};
+    use crate::manifest::StatValue;
+
+    #[test]
+    fn write_formats() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("name", DataType::Utf8, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Int64Array::from(vec![3, 1, 2])),
+                Arc::new(StringArray::from(vec![Some("c"), None, Some("b")])),
+            ],
+        )
+        .unwrap();
+        let dir = TempDir::new("sink");
+        for format in [Format::Parquet, Format::Arrow, Format::ArrowStream] {
+            let options = SinkOptions {
+                format,
+                parquet: WriterProperties::builder().build(),
+                key_value_metadata: vec![KeyValue::new("origin".into(), "test".to_string())],
+                text: Default::default(),
+                table_name: "user".into(),
+            };
+            let path = dir.join(format!("user.{}", options.extension()));
+            let mut sink = format
+                .create_table(&path, schema.clone(), &options)
+                .unwrap();
+            sink.write(&batch).unwrap();
+            sink.write(&batch.slice(0, 1)).unwrap();
+            let part = sink.finish().unwrap();
+            assert_eq!(part.path, format!("user.{}", options.extension()));
+            assert_eq!(part.row_count, 4);
+            assert_eq!(part.columns[1].null_count, Some(1));
+            assert_eq!(part.columns[0].max, Some(StatValue::Integer(3)));
+
+            let file = File::open(&path).unwrap();
+            let batches: Vec<RecordBatch> = match format {
+                Format::Parquet => ParquetRecordBatchReaderBuilder::try_new(file)
+                    .unwrap()
+                    .build()
+                    .unwrap()
+                    .collect::<Result<_, _>>()
+                    .unwrap(),
+                Format::Arrow => {
+                    let reader = FileReader::try_new(file, None).unwrap();
+                    assert_eq!(reader.schema().metadata()["origin"], "test");
+                    reader.collect::<Result<_, _>>().unwrap()
+                }
+                Format::ArrowStream => StreamReader::try_new(file, None)
+                    .unwrap()
+                    .collect::<Result<_, _>>()
+                    .unwrap(),
+                _ => unreachable!(),
+            };
+            assert_eq!(batches.iter().map(RecordBatch::num_rows).sum::<usize>(), 4);
+        }
+    }
+}
@@ -0,0 +1,547 @@
+//! Delta Lake tables.
+//!
+//! Parts are written as parquet files in the table directory, then every conversion of
+//! the table is recorded as a new commit of its `_delta_log`: the first one holds the
+//! protocol and the metadata (schema and partition columns) of the table, every one
+//! adds its files with their statistics.
+//!
+//! Delta has no timestamp without a time unit nor time zone, `DATETIME` and `TIMESTAMP`
+//! columns are stored as microseconds in UTC.
+
+use std::{
+    fs::{create_dir_all, read_dir, read_to_string, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use arrow::{
+    compute::cast,
+    datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit},
+    record_batch::RecordBatch,
+};
+use chrono::NaiveDateTime;
+use color_eyre::eyre::{bail, eyre, Context, Result};
+use serde_json::{json, Map, Value};
+
+use super::{parquet_file::ParquetSink, random_uuid, Sink, SinkOptions};
+use crate::{
+    config::Config,
+    line_parser,
+    manifest::{PartInfo, StatValue},
+    partition::{unescape, DEFAULT_PARTITION},
+};
+
+pub const DELTA_LOG_DIR: &str = "_delta_log";
+
+/// Parquet file of a Delta table, timestamps being converted on the fly
+pub struct DeltaSink {
+    parquet: Box<ParquetSink>,
+    file_schema: SchemaRef,
+}
+
+impl Sink for DeltaSink {
+    fn create(path: &Path, schema: SchemaRef, options: &SinkOptions) -> Result<Self> {
+        let file_schema = file_schema(&schema);
+        Ok(DeltaSink {
+            parquet: Box::new(ParquetSink::create(path, file_schema.clone(), options)?),
+            file_schema,
+        })
+    }
+
+    fn write(&mut self, batch: &RecordBatch) -> Result<()> {
+        let columns = batch
+            .columns()
+            .iter()
+            .zip(self.file_schema.fields())
+            .map(|(column, field)| cast(column, field.data_type()))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.parquet
+            .write(&RecordBatch::try_new(self.file_schema.clone(), columns)?)
+    }
+
+    fn buffered_size(&self) -> usize {
+        self.parquet.buffered_size()
+    }
+
+    fn bytes_written(&self) -> u64 {
+        self.parquet.bytes_written()
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.parquet.flush()
+    }
+
+    fn finish(self: Box<Self>) -> Result<PartInfo> {
+        self.parquet.finish()
+    }
+}
+
+/// Schema of the files of a Delta table: timestamps in microseconds, UTC
+pub fn file_schema(schema: &Schema) -> SchemaRef {
+    let fields: Vec<Field> = schema
+        .fields()
+        .iter()
+        .map(|field| match field.data_type() {
+            DataType::Timestamp(_, _) => field.as_ref().clone().with_data_type(
+                DataType::Timestamp(TimeUnit::Microsecond, Some("+00:00".into())),
+            ),
+            _ => field.as_ref().clone(),
+        })
+        .collect();
+    Arc::new(Schema::new_with_metadata(fields, schema.metadata().clone()))
+}
+
+/// Type of a column in the schema of a Delta table
+fn delta_type(data_type: &DataType) -> Result<String> {
+    Ok(match data_type {
+        DataType::Utf8 | DataType::LargeUtf8 => "string".into(),
+        DataType::Int64 => "long".into(),
+        DataType::Int32 => "integer".into(),
+        DataType::Int16 => "short".into(),
+        DataType::Int8 => "byte".into(),
+        DataType::Float64 => "double".into(),
+        DataType::Float32 => "float".into(),
+        DataType::Boolean => "boolean".into(),
+        DataType::Binary | DataType::LargeBinary => "binary".into(),
+        DataType::Date32 => "date".into(),
+        DataType::Timestamp(TimeUnit::Microsecond, Some(_)) => "timestamp".into(),
+        DataType::Decimal128(precision, scale) => format!("decimal({precision},{scale})"),
+        _ => bail!("Cannot store {data_type} columns in a Delta table"),
+    })
+}
+
+/// `schemaString` of the metadata of a table: the columns of its files, then its
+/// partition column unless the files hold it
+fn schema_string(file_schema: &Schema, partition_field: Option<&Field>) -> Result<String> {
+    let partition_field =
+        partition_field.filter(|field| file_schema.field_with_name(field.name()).is_err());
+    let fields = file_schema
+        .fields()
+        .iter()
+        .map(AsRef::as_ref)
+        .chain(partition_field)
+        .map(|field| {
+            Ok(json!({
+                "name": field.name(),
+                "type": delta_type(field.data_type())?,
+                "nullable": field.is_nullable(),
+                "metadata": {},
+            }))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(json!({ "type": "struct", "fields": fields }).to_string())
+}
+
+fn commit_path(log_dir: &Path, version: u64) -> PathBuf {
+    log_dir.join(format!("{version:020}.json"))
+}
+
+/// Versions of the commits found in `log_dir`, in ascending order
+fn versions(log_dir: &Path) -> Result<Vec<u64>> {
+    if !log_dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut versions = Vec::new();
+    for entry in read_dir(log_dir).with_context(|| format!("Cannot read {}", log_dir.display()))? {
+        let name = entry?.file_name();
+        let Some(version) = name.to_str().and_then(|name| name.strip_suffix(".json")) else {
+            continue;
+        };
+        if version.len() == 20 && version.bytes().all(|b| b.is_ascii_digit()) {
+            versions.push(version.parse()?);
+        }
+    }
+    versions.sort_unstable();
+    Ok(versions)
+}
+
+/// Latest `metaData` action of the table, looking at the most recent commits first
+fn latest_metadata(log_dir: &Path, versions: &[u64]) -> Result<Value> {
+    for version in versions.iter().rev() {
+        let path = commit_path(log_dir, *version);
+        let content =
+            read_to_string(&path).with_context(|| format!("Cannot read {}", path.display()))?;
+        for line in content.lines().filter(|line| !line.trim().is_empty()) {
+            let mut action: Value = serde_json::from_str(line)
+                .with_context(|| format!("Invalid {}", path.display()))?;
+            if let Some(metadata) = action.get_mut("metaData") {
+                return Ok(metadata.take());
+            }
+        }
+    }
+    bail!("No metadata found in {}", log_dir.display())
+}
+
+/// Percent-encode a relative path for the `path` of an add action
+fn encode_path(path: &str) -> String {
+    let mut ret = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~/=".contains(&byte) {
+            ret.push(byte as char);
+        } else {
+            ret.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    ret
+}
+
+/// Partition values of a part, from the `key=value` directories of its path
+fn partition_values(path: &str) -> Map<String, Value> {
+    let mut values = Map::new();
+    let mut directories: Vec<&str> = path.split('/').collect();
+    directories.pop();
+    for directory in directories {
+        if let Some((key, value)) = directory.split_once('=') {
+            let value = match value {
+                DEFAULT_PARTITION => Value::Null,
+                value => Value::String(unescape(value)),
+            };
+            values.insert(key.to_string(), value);
+        }
+    }
+    values
+}
+
+/// Statistics of a part, as JSON stored in its add action
+fn stats(part: &PartInfo, file_schema: &Schema) -> String {
+    let mut min_values = Map::new();
+    let mut max_values = Map::new();
+    let mut null_count = Map::new();
+    for column in &part.columns {
+        let Some(nulls) = column.null_count else {
+            continue;
+        };
+        null_count.insert(column.name.clone(), nulls.into());
+        let timestamp = file_schema
+            .field_with_name(&column.name)
+            .is_ok_and(|field| matches!(field.data_type(), DataType::Timestamp(_, _)));
+        let value = |value: &StatValue| match value {
+            StatValue::Integer(micros) if timestamp => {
+                NaiveDateTime::from_timestamp_micros(*micros)
+                    .map(|t| t.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string())
+                    .into()
+            }
+            value => serde_json::to_value(value).unwrap_or_default(),
+        };
+        if let Some(min) = &column.min {
+            min_values.insert(column.name.clone(), value(min));
+        }
+        if let Some(max) = &column.max {
+            max_values.insert(column.name.clone(), value(max));
+        }
+    }
+    json!({
+        "numRecords": part.row_count,
+        "minValues": min_values,
+        "maxValues": max_values,
+        "nullCount": null_count,
+    })
+    .to_string()
+}
+
+fn millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_millis() as u64)
+}
+
+/// Commit the `parts` written in `table_dir` to the `_delta_log` of the table, creating
+/// it with the schema of `ddl` on the first conversion.
+pub fn commit(
+    table_dir: &Path,
+    table_name: &str,
+    ddl: &line_parser::Schema,
+    config: &Config,
+    parts: &[PartInfo],
+) -> Result<()> {
+    let table = config.table(table_name);
+    let mut schema = file_schema(&ddl.to_arrow_schema());
+    let partition = table
+        .and_then(|table| table.partition_by.as_ref())
+        .map(|partition_by| {
+            let position = partition_by
+                .position(ddl)
+                .ok_or_else(|| eyre!("Unknown partition column {}", partition_by.column))?;
+            let key = partition_by.key(&ddl.columns[position].field_name);
+            // truncated dates are strings, not values of their column
+            let field = match partition_by.granularity {
+                Some(_) => Field::new(key, DataType::Utf8, true),
+                None => schema.field(position).clone(),
+            };
+            Ok::<_, color_eyre::Report>((position, field))
+        })
+        .transpose()?;
+    if let Some((position, _)) = &partition {
+        if !table.is_some_and(|table| table.keep_partition_column) {
+            let mut fields = schema.fields().to_vec();
+            fields.remove(*position);
+            schema = Arc::new(Schema::new_with_metadata(fields, schema.metadata().clone()));
+        }
+    }
+    let schema_string = schema_string(&schema, partition.as_ref().map(|(_, field)| field))?;
+    let partition_columns: Vec<String> = partition
+        .iter()
+        .map(|(_, field)| field.name().clone())
+        .collect();
+
+    let log_dir = table_dir.join(DELTA_LOG_DIR);
+    let versions = versions(&log_dir)?;
+    let now = millis(SystemTime::now());
+    let mut actions = Vec::new();
+    let version = match versions.last() {
+        None => {
+            actions.push(json!({
+                "protocol": { "minReaderVersion": 1, "minWriterVersion": 2 },
+            }));
+            actions.push(json!({
+                "metaData": {
+                    "id": random_uuid(),
+                    "name": table_name,
+                    "format": { "provider": "parquet", "options": {} },
+                    "schemaString": schema_string,
+                    "partitionColumns": partition_columns,
+                    "configuration": {},
+                    "createdTime": now,
+                },
+            }));
+            0
+        }
+        Some(latest) => {
+            let metadata = latest_metadata(&log_dir, &versions)?;
+            if metadata["schemaString"].as_str() != Some(&schema_string) {
+                bail!(
+                    "Table `{table_name}` has another schema in {}",
+                    log_dir.display()
+                );
+            }
+            if metadata["partitionColumns"] != json!(partition_columns) {
+                bail!(
+                    "Table `{table_name}` is partitioned differently in {}",
+                    log_dir.display()
+                );
+            }
+            latest + 1
+        }
+    };
+    actions.push(json!({
+        "commitInfo": {
+            "timestamp": now,
+            "operation": "WRITE",
+            "operationParameters": {
+                "mode": "Append",
+                "partitionBy": json!(partition_columns).to_string(),
+            },
+            "isBlindAppend": true,
+            "engineInfo": concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION")),
+        },
+    }));
+    for part in parts {
+        let path = table_dir.join(&part.path);
+        let modified = path
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .with_context(|| format!("Cannot read {}", path.display()))?;
+        actions.push(json!({
+            "add": {
+                "path": encode_path(&part.path),
+                "partitionValues": partition_values(&part.path),
+                "size": part.size,
+                "modificationTime": millis(modified),
+                "dataChange": true,
+                "stats": stats(part, &schema),
+            },
+        }));
+    }
+
+    create_dir_all(&log_dir)
+        .with_context(|| format!("Cannot create directory {}", log_dir.display()))?;
+    let path = commit_path(&log_dir, version);
+    // never overwrite a commit made in the meantime
+    let file = File::options()
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .with_context(|| format!("Cannot create {}", path.display()))?;
+    let mut writer = BufWriter::new(file);
+    for action in actions {
+        writeln!(writer, "{action}")?;
+    }
+    writer
+        .flush()
+        .map_err(|e| eyre!("Cannot write {}: {e}", path.display()))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::testing::TempDir;
+    use std::{fs::read_to_string, sync::Arc};
+
+    use arrow::{
+        array::{Int64Array, TimestampSecondArray},
+        record_batch::RecordBatch,
+    };
+    use parquet::file::properties::WriterProperties;
+    use serde_json::{json, Value};
+
+    use super::{commit, commit_path, versions, DELTA_LOG_DIR};
+    use crate::{
+        config::Config,
+        line_parser::{parse_line, Line},
+        sink::{Format, SinkOptions},
+    };
+
+    #[test]
+    fn commit_and_append() {
+        let Line::CreateTable(_, ddl) =
+            parse_line("CREATE TABLE user (id BIGINT NOT NULL, created_at DATETIME)").unwrap()
+        else {
+            panic!("Not a CREATE TABLE statement");
+        };
+        let schema = Arc::new(ddl.to_arrow_schema());
+        let config: Config = toml::from_str(
+            r#"
+            format = "delta"
+            [tables.user]
+            partition_by = "created_at:month"
+            keep_partition_column = true
+            "#,
+        )
+        .unwrap();
+        let options = SinkOptions {
+            format: Format::Delta,
+            parquet: WriterProperties::builder().build(),
+            key_value_metadata: Vec::new(),
+            text: Default::default(),
+            table_name: "user".into(),
+        };
+        let table_dir = TempDir::new("delta");
+        let partition = table_dir.join("created_at_month=2024-05");
+        std::fs::create_dir_all(&partition).unwrap();
+        let write = |name: &str, ids: Vec<i64>| {
+            let path = partition.join(name);
+            let mut sink = Format::Delta
+                .create_table(&path, schema.clone(), &options)
+                .unwrap();
+            let created_at: Vec<_> = ids.iter().map(|id| Some(1_714_521_600 + id)).collect();
+            let batch = RecordBatch::try_new(
+                schema.clone(),
+                vec![
+                    Arc::new(Int64Array::from(ids)),
+                    Arc::new(TimestampSecondArray::from(created_at)),
+                ],
+            )
+            .unwrap();
+            sink.write(&batch).unwrap();
+            let mut part = sink.finish().unwrap();
+            part.path = format!("created_at_month=2024-05/{}", part.path);
+            part
+        };
+
+        let part = write("part 1.parquet", vec![1, 2]);
+        commit(&table_dir, "user", &ddl, &config, &[part]).unwrap();
+        let part = write("part-2.parquet", vec![3]);
+        commit(&table_dir, "user", &ddl, &config, &[part]).unwrap();
+
+        let log_dir = table_dir.join(DELTA_LOG_DIR);
+        assert_eq!(versions(&log_dir).unwrap(), vec![0, 1]);
+        let actions = |version| -> Vec<Value> {
+            read_to_string(commit_path(&log_dir, version))
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect()
+        };
+        let first = actions(0);
+        assert_eq!(first.len(), 4);
+        assert_eq!(first[0]["protocol"]["minReaderVersion"], 1);
+        let metadata = &first[1]["metaData"];
+        assert_eq!(metadata["partitionColumns"], json!(["created_at_month"]));
+        let schema_string: Value =
+            serde_json::from_str(metadata["schemaString"].as_str().unwrap()).unwrap();
+        let types: Vec<_> = schema_string["fields"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|field| (field["name"].clone(), field["type"].clone()))
+            .collect();
+        assert_eq!(
+            types,
+            vec![
+                (json!("id"), json!("long")),
+                (json!("created_at"), json!("timestamp")),
+                (json!("created_at_month"), json!("string")),
+            ]
+        );
+        let add = &first[3]["add"];
+        assert_eq!(add["path"], "created_at_month=2024-05/part%201.parquet");
+        assert_eq!(
+            add["partitionValues"],
+            json!({"created_at_month": "2024-05"})
+        );
+        let stats: Value = serde_json::from_str(add["stats"].as_str().unwrap()).unwrap();
+        assert_eq!(stats["numRecords"], 2);
+        assert_eq!(stats["minValues"]["id"], 1);
+        assert_eq!(stats["maxValues"]["created_at"], "2024-05-01T00:00:02.000Z");
+        assert_eq!(stats["nullCount"]["created_at"], 0);
+
+        let second = actions(1);
+        assert_eq!(second.len(), 2);
+        assert!(second[0].get("commitInfo").is_some());
+        assert_eq!(
+            second[1]["add"]["path"],
+            "created_at_month=2024-05/part-2.parquet"
+        );
+
+        // appending requires the same schema
+        let Line::CreateTable(_, other) =
+            parse_line("CREATE TABLE user (id TEXT, created_at DATETIME)").unwrap()
+        else {
+            panic!("Not a CREATE TABLE statement");
+        };
+        assert!(commit(&table_dir, "user", &other, &config, &[]).is_err());
+    }
+
+    #[test]
+    fn partition_schema() {
+        let Line::CreateTable(_, ddl) =
+            parse_line("CREATE TABLE user (id BIGINT NOT NULL, created_at DATETIME)").unwrap()
+        else {
+            panic!("Not a CREATE TABLE statement");
+        };
+        for (partition_by, fields) in [
+            // the truncated column is not in the files, only its partition
+            (
+                "created_at:month",
+                json!([["id", "long"], ["created_at_month", "string"]]),
+            ),
+            (
+                "created_at",
+                json!([["id", "long"], ["created_at", "timestamp"]]),
+            ),
+        ] {
+            let config: Config = toml::from_str(&format!(
+                "format = \"delta\"\n[tables.user]\npartition_by = \"{partition_by}\""
+            ))
+            .unwrap();
+            let table_dir = TempDir::new("delta-partition");
+            commit(&table_dir, "user", &ddl, &config, &[]).unwrap();
+            let metadata: Value = read_to_string(commit_path(&table_dir.join(DELTA_LOG_DIR), 0))
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str::<Value>(line).unwrap())
+                .find_map(|action| action.get("metaData").cloned())
+                .unwrap();
+            let schema_string: Value =
+                serde_json::from_str(metadata["schemaString"].as_str().unwrap()).unwrap();
+            let names: Vec<_> = schema_string["fields"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|field| json!([field["name"], field["type"]]))
+                .collect();
+            assert_eq!(json!(names), fields);
+            assert_eq!(metadata["partitionColumns"], json!([fields[1][0]]));
+        }
+    }
+}
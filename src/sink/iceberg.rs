@@ -0,0 +1,737 @@
+//! Apache Iceberg tables, in the layout of a filesystem (Hadoop) catalog.
+//!
+//! Parquet data files are written in `{table}/data/`, then committed as the single
+//! snapshot of the table: an Avro manifest listing them with their statistics, the
+//! manifest list of the snapshot and `{table}/metadata/v1.metadata.json`.
+//!
+//! Field IDs follow the column order of the `CREATE TABLE` statement, starting at 1, and
+//! are stored in the parquet schema of data files. Partitioning maps to the `identity`
+//! transform, or to `year`, `month`, `day` and `hour` with a granularity.
+
+use std::{
+    fs::{create_dir_all, write},
+    path::Path,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use arrow::{
+    compute::cast,
+    datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit},
+    record_batch::RecordBatch,
+};
+use chrono::{NaiveDate, NaiveDateTime};
+use color_eyre::eyre::{bail, eyre, Context, Result};
+use parquet::arrow::PARQUET_FIELD_ID_META_KEY;
+use serde_json::json;
+
+use super::{parquet_file::ParquetSink, random_uuid, Sink, SinkOptions};
+use crate::{
+    config::Config,
+    line_parser,
+    manifest::{PartInfo, StatValue},
+    partition::{unescape, Granularity, DEFAULT_PARTITION},
+};
+
+use self::avro::Value;
+
+mod avro;
+
+pub const DATA_DIR: &str = "data";
+pub const METADATA_DIR: &str = "metadata";
+
+/// Field ID of the first partition field
+const PARTITION_FIELD_ID: usize = 1000;
+
+/// Parquet data file of an Iceberg table, with field IDs and timestamps in microseconds
+pub struct IcebergSink {
+    parquet: Box<ParquetSink>,
+    file_schema: SchemaRef,
+}
+
+impl Sink for IcebergSink {
+    fn create(path: &Path, schema: SchemaRef, options: &SinkOptions) -> Result<Self> {
+        let file_schema = file_schema(&schema);
+        Ok(IcebergSink {
+            parquet: Box::new(ParquetSink::create(path, file_schema.clone(), options)?),
+            file_schema,
+        })
+    }
+
+    fn write(&mut self, batch: &RecordBatch) -> Result<()> {
+        let columns = batch
+            .columns()
+            .iter()
+            .zip(self.file_schema.fields())
+            .map(|(column, field)| cast(column, field.data_type()))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.parquet
+            .write(&RecordBatch::try_new(self.file_schema.clone(), columns)?)
+    }
+
+    fn buffered_size(&self) -> usize {
+        self.parquet.buffered_size()
+    }
+
+    fn bytes_written(&self) -> u64 {
+        self.parquet.bytes_written()
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.parquet.flush()
+    }
+
+    fn finish(self: Box<Self>) -> Result<PartInfo> {
+        self.parquet.finish()
+    }
+}
+
+/// Schema of the data files of a table, data files holding every column (partition
+/// columns included) so their position gives their field ID.
+pub fn file_schema(schema: &Schema) -> SchemaRef {
+    let fields: Vec<Field> = schema
+        .fields()
+        .iter()
+        .enumerate()
+        .map(|(i, field)| {
+            let mut metadata = field.metadata().clone();
+            metadata.insert(PARQUET_FIELD_ID_META_KEY.to_string(), (i + 1).to_string());
+            let field = field.as_ref().clone().with_metadata(metadata);
+            match field.data_type().clone() {
+                DataType::Timestamp(_, tz) => {
+                    field.with_data_type(DataType::Timestamp(TimeUnit::Microsecond, tz))
+                }
+                _ => field,
+            }
+        })
+        .collect();
+    Arc::new(Schema::new_with_metadata(fields, schema.metadata().clone()))
+}
+
+/// Type of a column in the schema of an Iceberg table
+fn iceberg_type(data_type: &DataType) -> Result<String> {
+    Ok(match data_type {
+        DataType::Utf8 | DataType::LargeUtf8 => "string".into(),
+        DataType::Int64 => "long".into(),
+        DataType::Int32 | DataType::Int16 | DataType::Int8 => "int".into(),
+        DataType::Float64 => "double".into(),
+        DataType::Float32 => "float".into(),
+        DataType::Boolean => "boolean".into(),
+        DataType::Binary | DataType::LargeBinary => "binary".into(),
+        DataType::Date32 => "date".into(),
+        DataType::Timestamp(TimeUnit::Microsecond, None) => "timestamp".into(),
+        DataType::Timestamp(TimeUnit::Microsecond, Some(_)) => "timestamptz".into(),
+        DataType::Decimal128(precision, scale) => format!("decimal({precision}, {scale})"),
+        _ => bail!("Cannot store {data_type} columns in an Iceberg table"),
+    })
+}
+
+/// Avro type of the values of an identity partition
+fn avro_type(data_type: &DataType) -> Result<serde_json::Value> {
+    Ok(match data_type {
+        DataType::Utf8 | DataType::LargeUtf8 => json!("string"),
+        DataType::Int64 => json!("long"),
+        DataType::Int32 | DataType::Int16 | DataType::Int8 => json!("int"),
+        DataType::Float64 => json!("double"),
+        DataType::Float32 => json!("float"),
+        DataType::Boolean => json!("boolean"),
+        DataType::Date32 => json!({ "type": "int", "logicalType": "date" }),
+        DataType::Timestamp(_, tz) => json!({
+            "type": "long",
+            "logicalType": "timestamp-micros",
+            "adjust-to-utc": tz.is_some(),
+        }),
+        _ => bail!("Cannot partition an Iceberg table by a {data_type} column"),
+    })
+}
+
+/// Field of the partition spec of a table
+struct PartitionField {
+    name: String,
+    source_id: usize,
+    source_type: DataType,
+    granularity: Option<Granularity>,
+}
+
+impl PartitionField {
+    fn transform(&self) -> &'static str {
+        self.granularity.map_or("identity", Granularity::name)
+    }
+
+    fn avro_type(&self) -> Result<serde_json::Value> {
+        match self.granularity {
+            None => avro_type(&self.source_type),
+            Some(Granularity::Day) => Ok(json!({ "type": "int", "logicalType": "date" })),
+            Some(_) => Ok(json!("int")),
+        }
+    }
+
+    /// Partition value of the directory value `value`, written by [`crate::partition`]
+    fn value(&self, value: &str) -> Result<Value> {
+        let invalid = || eyre!("Invalid value `{value}` of partition {}", self.name);
+        let epoch = NaiveDate::from_ymd_opt(1970, 1, 1).unwrap();
+        let date =
+            |value: &str| NaiveDate::parse_from_str(value, "%Y-%m-%d").map_err(|_| invalid());
+        let year = |value: &str| value.parse::<i32>().map_err(|_| invalid());
+        Ok(match self.granularity {
+            Some(Granularity::Year) => Value::Int(year(value)? - 1970),
+            Some(Granularity::Month) => {
+                let (y, m) = value.split_once('-').ok_or_else(invalid)?;
+                Value::Int((year(y)? - 1970) * 12 + m.parse::<i32>().map_err(|_| invalid())? - 1)
+            }
+            Some(Granularity::Day) => Value::Int((date(value)? - epoch).num_days() as i32),
+            Some(Granularity::Hour) => {
+                let hour = NaiveDateTime::parse_from_str(&format!("{value}:00"), "%Y-%m-%dT%H:%M")
+                    .map_err(|_| invalid())?;
+                Value::Int((hour.and_utc().timestamp() / 3600) as i32)
+            }
+            None => match &self.source_type {
+                DataType::Utf8 | DataType::LargeUtf8 => Value::String(value.to_string()),
+                DataType::Int64 => Value::Long(value.parse().map_err(|_| invalid())?),
+                DataType::Int32 | DataType::Int16 | DataType::Int8 => {
+                    Value::Int(value.parse().map_err(|_| invalid())?)
+                }
+                DataType::Float64 => Value::Double(value.parse().map_err(|_| invalid())?),
+                DataType::Float32 => Value::Float(value.parse().map_err(|_| invalid())?),
+                DataType::Boolean => Value::Boolean(value == "true" || value == "1"),
+                DataType::Date32 => Value::Int((date(value)? - epoch).num_days() as i32),
+                DataType::Timestamp(_, _) => {
+                    let timestamp = NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S")
+                        .or_else(|_| date(value).map(|date| date.and_time(Default::default())))
+                        .map_err(|_| invalid())?;
+                    Value::Long(timestamp.and_utc().timestamp_micros())
+                }
+                data_type => bail!("Cannot partition an Iceberg table by a {data_type} column"),
+            },
+        })
+    }
+}
+
+/// Partition of a data file, from the `key=value` directory of its path
+fn partition(path: &str, field: &PartitionField) -> Result<Value> {
+    let value = path
+        .split('/')
+        .find_map(|directory| directory.strip_prefix(&format!("{}=", field.name)))
+        .ok_or_else(|| eyre!("No partition {} in the path of {path}", field.name))?;
+    let value = match value {
+        DEFAULT_PARTITION => None,
+        value => Some(field.value(&unescape(value))?),
+    };
+    Ok(Value::Record(vec![Value::optional(value)]))
+}
+
+/// Single-value serialization of a bound, for the types having statistics
+fn bound(value: &StatValue, data_type: &DataType) -> Option<Vec<u8>> {
+    Some(match (value, data_type) {
+        (StatValue::Boolean(value), DataType::Boolean) => vec![u8::from(*value)],
+        (StatValue::Integer(value), DataType::Int64 | DataType::Timestamp(_, _)) => {
+            value.to_le_bytes().to_vec()
+        }
+        (StatValue::Integer(value), DataType::Int32 | DataType::Date32) => {
+            (*value as i32).to_le_bytes().to_vec()
+        }
+        (StatValue::Float(value), DataType::Float64) => value.to_le_bytes().to_vec(),
+        (StatValue::Float(value), DataType::Float32) => (*value as f32).to_le_bytes().to_vec(),
+        (StatValue::String(value), DataType::Utf8) => value.as_bytes().to_vec(),
+        _ => return None,
+    })
+}
+
+/// `map<int, T>` stored as an Avro array of key/value records
+fn map_type(
+    field_id: usize,
+    key_id: usize,
+    value_id: usize,
+    value_type: &str,
+) -> serde_json::Value {
+    json!({
+        "type": [
+            "null",
+            {
+                "type": "array",
+                "logicalType": "map",
+                "items": {
+                    "type": "record",
+                    "name": format!("k{key_id}_v{value_id}"),
+                    "fields": [
+                        { "name": "key", "type": "int", "field-id": key_id },
+                        { "name": "value", "type": value_type, "field-id": value_id },
+                    ],
+                },
+            },
+        ],
+        "default": null,
+        "field-id": field_id,
+    })
+}
+
+fn map_value(entries: Vec<(usize, Value)>) -> Value {
+    Value::optional(Some(Value::Array(
+        entries
+            .into_iter()
+            .map(|(key, value)| Value::Record(vec![Value::Int(key as i32), value]))
+            .collect(),
+    )))
+}
+
+/// Avro schema of manifest entries, `partition_fields` being the Avro fields of the
+/// partition struct
+fn manifest_entry_schema(partition_fields: Vec<serde_json::Value>) -> serde_json::Value {
+    let optional_long = |name: &str, field_id: usize| json!({ "name": name, "type": ["null", "long"], "default": null, "field-id": field_id });
+    let mut value_counts = map_type(109, 119, 120, "long");
+    value_counts["name"] = json!("value_counts");
+    let mut null_value_counts = map_type(110, 121, 122, "long");
+    null_value_counts["name"] = json!("null_value_counts");
+    let mut lower_bounds = map_type(125, 126, 127, "bytes");
+    lower_bounds["name"] = json!("lower_bounds");
+    let mut upper_bounds = map_type(128, 129, 130, "bytes");
+    upper_bounds["name"] = json!("upper_bounds");
+    json!({
+        "type": "record",
+        "name": "manifest_entry",
+        "fields": [
+            { "name": "status", "type": "int", "field-id": 0 },
+            optional_long("snapshot_id", 1),
+            optional_long("sequence_number", 3),
+            optional_long("file_sequence_number", 4),
+            {
+                "name": "data_file",
+                "type": {
+                    "type": "record",
+                    "name": "r2",
+                    "fields": [
+                        { "name": "content", "type": "int", "field-id": 134 },
+                        { "name": "file_path", "type": "string", "field-id": 100 },
+                        { "name": "file_format", "type": "string", "field-id": 101 },
+                        {
+                            "name": "partition",
+                            "type": { "type": "record", "name": "r102", "fields": partition_fields },
+                            "field-id": 102,
+                        },
+                        { "name": "record_count", "type": "long", "field-id": 103 },
+                        { "name": "file_size_in_bytes", "type": "long", "field-id": 104 },
+                        value_counts,
+                        null_value_counts,
+                        lower_bounds,
+                        upper_bounds,
+                    ],
+                },
+                "field-id": 2,
+            },
+        ],
+    })
+}
+
+/// Avro schema of manifest list entries
+fn manifest_file_schema() -> serde_json::Value {
+    let field = |name: &str, avro_type: &str, field_id: usize| json!({ "name": name, "type": avro_type, "field-id": field_id });
+    json!({
+        "type": "record",
+        "name": "manifest_file",
+        "fields": [
+            field("manifest_path", "string", 500),
+            field("manifest_length", "long", 501),
+            field("partition_spec_id", "int", 502),
+            field("content", "int", 517),
+            field("sequence_number", "long", 515),
+            field("min_sequence_number", "long", 516),
+            field("added_snapshot_id", "long", 503),
+            field("added_files_count", "int", 504),
+            field("existing_files_count", "int", 505),
+            field("deleted_files_count", "int", 506),
+            field("added_rows_count", "long", 512),
+            field("existing_rows_count", "long", 513),
+            field("deleted_rows_count", "long", 514),
+        ],
+    })
+}
+
+/// Commit the `parts` written in the data directory of `table_dir` as the first
+/// snapshot of a new table.
+pub fn commit(
+    table_dir: &Path,
+    table_name: &str,
+    ddl: &line_parser::Schema,
+    config: &Config,
+    parts: &[PartInfo],
+) -> Result<()> {
+    let metadata_dir = table_dir.join(METADATA_DIR);
+    let metadata_path = metadata_dir.join("v1.metadata.json");
+    if metadata_path.exists() {
+        bail!(
+            "Iceberg table `{table_name}` already exists in {}",
+            table_dir.display()
+        );
+    }
+    create_dir_all(&metadata_dir)
+        .with_context(|| format!("Cannot create directory {}", metadata_dir.display()))?;
+    let location = table_dir
+        .canonicalize()
+        .with_context(|| format!("Cannot resolve {}", table_dir.display()))?
+        .to_string_lossy()
+        .into_owned();
+
+    let schema = file_schema(&ddl.to_arrow_schema());
+    let fields = schema
+        .fields()
+        .iter()
+        .enumerate()
+        .map(|(i, field)| {
+            Ok(json!({
+                "id": i + 1,
+                "name": field.name(),
+                "required": !field.is_nullable(),
+                "type": iceberg_type(field.data_type())?,
+            }))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let schema_json = json!({ "type": "struct", "schema-id": 0, "fields": fields });
+
+    let partition_field = config
+        .table(table_name)
+        .and_then(|table| table.partition_by.as_ref())
+        .map(|partition_by| {
+            let source = partition_by
+                .position(ddl)
+                .ok_or_else(|| eyre!("Unknown partition column {}", partition_by.column))?;
+            let field = schema.field(source);
+            Ok::<_, color_eyre::Report>(PartitionField {
+                name: partition_by.key(field.name()),
+                source_id: source + 1,
+                source_type: field.data_type().clone(),
+                granularity: partition_by.granularity,
+            })
+        })
+        .transpose()?;
+    let spec_fields: Vec<_> = partition_field
+        .iter()
+        .map(|field| {
+            json!({
+                "name": field.name,
+                "transform": field.transform(),
+                "source-id": field.source_id,
+                "field-id": PARTITION_FIELD_ID,
+            })
+        })
+        .collect();
+    let partition_avro_fields = partition_field
+        .iter()
+        .map(|field| {
+            Ok(json!({
+                "name": field.name,
+                "type": ["null", field.avro_type()?],
+                "default": null,
+                "field-id": PARTITION_FIELD_ID,
+            }))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut snapshot_id = [0; 8];
+    getrandom::getrandom(&mut snapshot_id).expect("Cannot generate a snapshot ID");
+    let snapshot_id = (u64::from_le_bytes(snapshot_id) >> 1) as i64;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_millis() as u64);
+
+    // manifest of the data files
+    let entries = parts
+        .iter()
+        .map(|part| {
+            let partition = match &partition_field {
+                Some(field) => partition(&part.path, field)?,
+                None => Value::Record(Vec::new()),
+            };
+            let mut value_counts = Vec::new();
+            let mut null_value_counts = Vec::new();
+            let mut lower_bounds = Vec::new();
+            let mut upper_bounds = Vec::new();
+            for column in &part.columns {
+                let Some((i, field)) = schema.column_with_name(&column.name) else {
+                    continue;
+                };
+                let field_id = i + 1;
+                if let Some(null_count) = column.null_count {
+                    value_counts.push((field_id, Value::Long(part.row_count as i64)));
+                    null_value_counts.push((field_id, Value::Long(null_count as i64)));
+                }
+                let bound = |value: &Option<StatValue>| {
+                    bound(value.as_ref()?, field.data_type()).map(Value::Bytes)
+                };
+                if let Some(min) = bound(&column.min) {
+                    lower_bounds.push((field_id, min));
+                }
+                if let Some(max) = bound(&column.max) {
+                    upper_bounds.push((field_id, max));
+                }
+            }
+            Ok(Value::Record(vec![
+                // ADDED
+                Value::Int(1),
+                Value::optional(Some(Value::Long(snapshot_id))),
+                // inherited from the manifest list
+                Value::optional(None),
+                Value::optional(None),
+                Value::Record(vec![
+                    // DATA
+                    Value::Int(0),
+                    Value::String(format!("{location}/{DATA_DIR}/{}", part.path)),
+                    Value::String("PARQUET".into()),
+                    partition,
+                    Value::Long(part.row_count as i64),
+                    Value::Long(part.size as i64),
+                    map_value(value_counts),
+                    map_value(null_value_counts),
+                    map_value(lower_bounds),
+                    map_value(upper_bounds),
+                ]),
+            ]))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let manifest_path = format!("{location}/{METADATA_DIR}/{}-m0.avro", random_uuid());
+    let spec_json = json!(spec_fields).to_string();
+    let manifest_length = avro::write_file(
+        Path::new(&manifest_path),
+        &manifest_entry_schema(partition_avro_fields),
+        &[
+            ("schema", schema_json.to_string()),
+            ("schema-id", "0".into()),
+            ("partition-spec", spec_json),
+            ("partition-spec-id", "0".into()),
+            ("format-version", "2".into()),
+            ("content", "data".into()),
+        ],
+        &entries,
+    )?;
+
+    // manifest list of the snapshot
+    let row_count: u64 = parts.iter().map(|part| part.row_count).sum();
+    let size: u64 = parts.iter().map(|part| part.size).sum();
+    let manifest_list_path = format!(
+        "{location}/{METADATA_DIR}/snap-{snapshot_id}-1-{}.avro",
+        random_uuid()
+    );
+    avro::write_file(
+        Path::new(&manifest_list_path),
+        &manifest_file_schema(),
+        &[
+            ("snapshot-id", snapshot_id.to_string()),
+            ("parent-snapshot-id", "null".into()),
+            ("sequence-number", "1".into()),
+            ("format-version", "2".into()),
+        ],
+        &[Value::Record(vec![
+            Value::String(manifest_path),
+            Value::Long(manifest_length as i64),
+            Value::Int(0),
+            Value::Int(0),
+            Value::Long(1),
+            Value::Long(1),
+            Value::Long(snapshot_id),
+            Value::Int(parts.len() as i32),
+            Value::Int(0),
+            Value::Int(0),
+            Value::Long(row_count as i64),
+            Value::Long(0),
+            Value::Long(0),
+        ])],
+    )?;
+
+    let metadata = json!({
+        "format-version": 2,
+        "table-uuid": random_uuid(),
+        "location": location,
+        "last-sequence-number": 1,
+        "last-updated-ms": now,
+        "last-column-id": schema.fields().len(),
+        "current-schema-id": 0,
+        "schemas": [schema_json],
+        "default-spec-id": 0,
+        "partition-specs": [{ "spec-id": 0, "fields": spec_fields }],
+        "last-partition-id": PARTITION_FIELD_ID + spec_fields.len() - 1,
+        "default-sort-order-id": 0,
+        "sort-orders": [{ "order-id": 0, "fields": [] }],
+        "properties": {},
+        "current-snapshot-id": snapshot_id,
+        "refs": { "main": { "snapshot-id": snapshot_id, "type": "branch" } },
+        "snapshots": [{
+            "sequence-number": 1,
+            "snapshot-id": snapshot_id,
+            "timestamp-ms": now,
+            "summary": {
+                "operation": "append",
+                "added-data-files": parts.len().to_string(),
+                "added-records": row_count.to_string(),
+                "added-files-size": size.to_string(),
+                "total-data-files": parts.len().to_string(),
+                "total-records": row_count.to_string(),
+                "total-files-size": size.to_string(),
+                "total-delete-files": "0",
+                "total-position-deletes": "0",
+                "total-equality-deletes": "0",
+            },
+            "manifest-list": manifest_list_path,
+            "schema-id": 0,
+        }],
+        "snapshot-log": [{ "snapshot-id": snapshot_id, "timestamp-ms": now }],
+        "metadata-log": [],
+    });
+    write(&metadata_path, serde_json::to_string_pretty(&metadata)?)
+        .with_context(|| format!("Cannot write {}", metadata_path.display()))?;
+    let version_hint = metadata_dir.join("version-hint.text");
+    write(&version_hint, "1").with_context(|| format!("Cannot write {}", version_hint.display()))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::testing::TempDir;
+    use std::{fs::File, sync::Arc};
+
+    use arrow::{
+        array::{Int64Array, StringArray, TimestampSecondArray},
+        datatypes::{DataType, TimeUnit},
+        record_batch::RecordBatch,
+    };
+    use parquet::file::{
+        properties::WriterProperties, reader::FileReader, serialized_reader::SerializedFileReader,
+    };
+    use serde_json::{json, Value as Json};
+
+    use super::{avro::Value, commit, PartitionField, DATA_DIR, METADATA_DIR};
+    use crate::{
+        config::Config,
+        line_parser::{self, Line},
+        partition::Granularity,
+        sink::{Format, SinkOptions},
+    };
+
+    #[test]
+    fn partition_values() {
+        let field = |source_type, granularity| PartitionField {
+            name: "p".into(),
+            source_id: 1,
+            source_type,
+            granularity,
+        };
+        let timestamp = DataType::Timestamp(TimeUnit::Microsecond, None);
+        let month = field(timestamp.clone(), Some(Granularity::Month));
+        assert_eq!(month.value("2024-05").unwrap(), Value::Int(652));
+        assert!(month.value("2024").is_err());
+        let year = field(timestamp.clone(), Some(Granularity::Year));
+        assert_eq!(year.value("1969").unwrap(), Value::Int(-1));
+        let day = field(timestamp.clone(), Some(Granularity::Day));
+        assert_eq!(day.value("1970-01-02").unwrap(), Value::Int(1));
+        let hour = field(timestamp.clone(), Some(Granularity::Hour));
+        assert_eq!(hour.value("1970-01-02T01").unwrap(), Value::Int(25));
+        let identity = field(timestamp, None);
+        assert_eq!(
+            identity.value("1970-01-01 00:00:01").unwrap(),
+            Value::Long(1_000_000)
+        );
+        assert_eq!(
+            field(DataType::Int64, None).value("-3").unwrap(),
+            Value::Long(-3)
+        );
+    }
+
+    #[test]
+    fn commit_table() {
+        let Line::CreateTable(_, ddl) = line_parser::parse_line(
+            "CREATE TABLE user (id BIGINT NOT NULL, name TEXT, created_at DATETIME)",
+        )
+        .unwrap() else {
+            panic!("Not a CREATE TABLE statement");
+        };
+        let schema = Arc::new(ddl.to_arrow_schema());
+        let config: Config = toml::from_str(
+            r#"
+            format = "iceberg"
+            [tables.user]
+            partition_by = "created_at:month"
+            "#,
+        )
+        .unwrap();
+        let options = SinkOptions {
+            format: Format::Iceberg,
+            parquet: WriterProperties::builder().build(),
+            key_value_metadata: Vec::new(),
+            text: Default::default(),
+            table_name: "user".into(),
+        };
+        let table_dir = TempDir::new("iceberg");
+        let partition = table_dir.join(DATA_DIR).join("created_at_month=2024-05");
+        std::fs::create_dir_all(&partition).unwrap();
+        let path = partition.join("part-00000.parquet");
+        let mut sink = Format::Iceberg
+            .create_table(&path, schema.clone(), &options)
+            .unwrap();
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Int64Array::from(vec![1, 2])),
+                Arc::new(StringArray::from(vec![Some("a"), None])),
+                Arc::new(TimestampSecondArray::from(vec![
+                    1_714_521_600,
+                    1_714_521_601,
+                ])),
+            ],
+        )
+        .unwrap();
+        sink.write(&batch).unwrap();
+        let mut part = sink.finish().unwrap();
+        part.path = format!("created_at_month=2024-05/{}", part.path);
+
+        // field IDs in data files
+        let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+        let ids: Vec<i32> = reader
+            .metadata()
+            .file_metadata()
+            .schema_descr()
+            .columns()
+            .iter()
+            .map(|column| column.self_type().get_basic_info().id())
+            .collect();
+        assert_eq!(ids, vec![1, 2, 3]);
+
+        commit(&table_dir, "user", &ddl, &config, &[part]).unwrap();
+        let metadata_dir = table_dir.join(METADATA_DIR);
+        let metadata: Json = serde_json::from_str(
+            &std::fs::read_to_string(metadata_dir.join("v1.metadata.json")).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            metadata["schemas"][0]["fields"],
+            json!([
+                { "id": 1, "name": "id", "required": true, "type": "long" },
+                { "id": 2, "name": "name", "required": false, "type": "string" },
+                { "id": 3, "name": "created_at", "required": false, "type": "timestamp" },
+            ])
+        );
+        assert_eq!(
+            metadata["partition-specs"][0]["fields"],
+            json!([{ "name": "created_at_month", "transform": "month", "source-id": 3, "field-id": 1000 }])
+        );
+        assert_eq!(metadata["snapshots"][0]["summary"]["added-records"], "2");
+        let manifest_list = metadata["snapshots"][0]["manifest-list"].as_str().unwrap();
+        let manifest_list = std::fs::read(manifest_list).unwrap();
+        assert!(manifest_list.starts_with(b"Obj\x01"));
+        assert_eq!(
+            std::fs::read_to_string(metadata_dir.join("version-hint.text")).unwrap(),
+            "1"
+        );
+        // the data file is referenced by its absolute path
+        let manifest = std::fs::read_dir(&metadata_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|path| path.to_string_lossy().ends_with("-m0.avro"))
+            .unwrap();
+        let manifest = std::fs::read(manifest).unwrap();
+        let data_file = path.canonicalize().unwrap();
+        let data_file = data_file.to_string_lossy();
+        assert!(manifest
+            .windows(data_file.len())
+            .any(|window| window == data_file.as_bytes()));
+
+        // a table is only created once
+        assert!(commit(&table_dir, "user", &ddl, &config, &[]).is_err());
+    }
+}
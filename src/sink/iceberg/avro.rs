@@ -0,0 +1,158 @@
+//! Avro object container files, as much as Iceberg manifests need.
+//!
+//! Records are encoded against a schema given as JSON, without validation: values must
+//! follow the schema field by field.
+
+use std::{fs::File, io::Write, path::Path};
+
+use color_eyre::eyre::{Context, Result};
+
+const MAGIC: &[u8] = b"Obj\x01";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Boolean(bool),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    String(String),
+    Bytes(Vec<u8>),
+    /// index of the branch in the union, and its value
+    Union(usize, Box<Value>),
+    Array(Vec<Value>),
+    /// values of the fields, in schema order
+    Record(Vec<Value>),
+}
+
+impl Value {
+    /// Value of a `["null", type]` union
+    pub fn optional(value: Option<Value>) -> Value {
+        match value {
+            None => Value::Union(0, Box::new(Value::Null)),
+            Some(value) => Value::Union(1, Box::new(value)),
+        }
+    }
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Value::Null => {}
+            Value::Boolean(value) => buf.push(u8::from(*value)),
+            Value::Int(value) => encode_long(i64::from(*value), buf),
+            Value::Long(value) => encode_long(*value, buf),
+            Value::Float(value) => buf.extend_from_slice(&value.to_le_bytes()),
+            Value::Double(value) => buf.extend_from_slice(&value.to_le_bytes()),
+            Value::String(value) => encode_bytes(value.as_bytes(), buf),
+            Value::Bytes(value) => encode_bytes(value, buf),
+            Value::Union(index, value) => {
+                encode_long(*index as i64, buf);
+                value.encode(buf);
+            }
+            Value::Array(items) => {
+                // a single block, followed by the empty one ending the array
+                if !items.is_empty() {
+                    encode_long(items.len() as i64, buf);
+                    for item in items {
+                        item.encode(buf);
+                    }
+                }
+                encode_long(0, buf);
+            }
+            Value::Record(fields) => {
+                for field in fields {
+                    field.encode(buf);
+                }
+            }
+        }
+    }
+}
+
+/// Zigzag variable-length encoding of `int` and `long`
+fn encode_long(value: i64, buf: &mut Vec<u8>) {
+    let mut value = ((value << 1) ^ (value >> 63)) as u64;
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn encode_bytes(bytes: &[u8], buf: &mut Vec<u8>) {
+    encode_long(bytes.len() as i64, buf);
+    buf.extend_from_slice(bytes);
+}
+
+/// Write `records` to the uncompressed container file `path`, returning its size.
+pub fn write_file(
+    path: &Path,
+    schema: &serde_json::Value,
+    metadata: &[(&str, String)],
+    records: &[Value],
+) -> Result<u64> {
+    let mut sync = [0; 16];
+    getrandom::getrandom(&mut sync).expect("Cannot generate a sync marker");
+
+    let mut buf = MAGIC.to_vec();
+    let schema = schema.to_string();
+    let header = [("avro.schema", schema.as_str()), ("avro.codec", "null")]
+        .into_iter()
+        .chain(metadata.iter().map(|(key, value)| (*key, value.as_str())));
+    let header: Vec<_> = header.collect();
+    encode_long(header.len() as i64, &mut buf);
+    for (key, value) in header {
+        encode_bytes(key.as_bytes(), &mut buf);
+        encode_bytes(value.as_bytes(), &mut buf);
+    }
+    encode_long(0, &mut buf);
+    buf.extend_from_slice(&sync);
+
+    if !records.is_empty() {
+        let mut block = Vec::new();
+        for record in records {
+            record.encode(&mut block);
+        }
+        encode_long(records.len() as i64, &mut buf);
+        encode_bytes(&block, &mut buf);
+        buf.extend_from_slice(&sync);
+    }
+
+    File::create(path)
+        .and_then(|mut file| file.write_all(&buf))
+        .with_context(|| format!("Cannot write {}", path.display()))?;
+    Ok(buf.len() as u64)
+}
+
+#[cfg(test)]
+mod test {
+    use super::Value;
+
+    fn encode(value: Value) -> Vec<u8> {
+        let mut buf = Vec::new();
+        value.encode(&mut buf);
+        buf
+    }
+
+    #[test]
+    fn encode_values() {
+        assert_eq!(encode(Value::Long(0)), [0x00]);
+        assert_eq!(encode(Value::Long(-1)), [0x01]);
+        assert_eq!(encode(Value::Int(1)), [0x02]);
+        assert_eq!(encode(Value::Long(64)), [0x80, 0x01]);
+        assert_eq!(encode(Value::Long(-65)), [0x81, 0x01]);
+        assert_eq!(encode(Value::String("ab".into())), [0x04, b'a', b'b']);
+        assert_eq!(encode(Value::optional(None)), [0x00]);
+        assert_eq!(
+            encode(Value::optional(Some(Value::Boolean(true)))),
+            [0x02, 0x01]
+        );
+        assert_eq!(encode(Value::Array(Vec::new())), [0x00]);
+        assert_eq!(
+            encode(Value::Array(vec![Value::Record(vec![
+                Value::Int(1),
+                Value::Long(2)
+            ])])),
+            [0x02, 0x02, 0x04, 0x00]
+        );
+    }
+}
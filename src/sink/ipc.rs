@@ -0,0 +1,88 @@
+//! Arrow IPC files and streams, for Arrow-native consumers that do not need parquet
+//! encodings.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use arrow::{
+    datatypes::{Schema, SchemaRef},
+    ipc::writer::{FileWriter, StreamWriter},
+    record_batch::RecordBatch,
+};
+use color_eyre::eyre::Result;
+
+use super::{CountingFile, Format, Sink, SinkOptions};
+use crate::manifest::{PartInfo, StatsCollector};
+
+enum IpcWriter {
+    File(FileWriter<CountingFile>),
+    Stream(StreamWriter<CountingFile>),
+}
+
+pub struct IpcSink {
+    path: PathBuf,
+    writer: IpcWriter,
+    bytes_written: Arc<AtomicU64>,
+    stats: StatsCollector,
+}
+
+impl Sink for IpcSink {
+    fn create(path: &Path, schema: SchemaRef, options: &SinkOptions) -> Result<Self> {
+        let (file, bytes_written) = CountingFile::create(path)?;
+        // key-value metadata goes to the schema, IPC files have no other place for it
+        let schema = Schema::new_with_metadata(
+            schema.fields().clone(),
+            options
+                .key_value_metadata
+                .iter()
+                .map(|kv| (kv.key.clone(), kv.value.clone().unwrap_or_default()))
+                .collect(),
+        );
+        let writer = match options.format {
+            Format::ArrowStream => IpcWriter::Stream(StreamWriter::try_new(file, &schema)?),
+            _ => IpcWriter::File(FileWriter::try_new(file, &schema)?),
+        };
+        Ok(IpcSink {
+            path: path.to_path_buf(),
+            writer,
+            bytes_written,
+            stats: StatsCollector::new(&schema),
+        })
+    }
+
+    fn write(&mut self, batch: &RecordBatch) -> Result<()> {
+        self.stats.update(batch);
+        match &mut self.writer {
+            IpcWriter::File(writer) => writer.write(batch)?,
+            IpcWriter::Stream(writer) => writer.write(batch)?,
+        }
+        Ok(())
+    }
+
+    /// Batches are written as they come
+    fn buffered_size(&self) -> usize {
+        0
+    }
+
+    fn bytes_written(&self) -> u64 {
+        self.bytes_written.load(Ordering::Relaxed)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<PartInfo> {
+        // finish the file and flush it
+        match self.writer {
+            IpcWriter::File(writer) => writer.into_inner()?,
+            IpcWriter::Stream(writer) => writer.into_inner()?,
+        };
+        self.stats.finish(&self.path)
+    }
+}
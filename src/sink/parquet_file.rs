@@ -0,0 +1,54 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use arrow::{datatypes::SchemaRef, record_batch::RecordBatch};
+use color_eyre::eyre::Result;
+use parquet::arrow::ArrowWriter;
+
+use super::{CountingFile, Sink, SinkOptions};
+use crate::manifest::PartInfo;
+
+pub struct ParquetSink {
+    path: PathBuf,
+    arrow_writer: ArrowWriter<CountingFile>,
+    bytes_written: Arc<AtomicU64>,
+}
+
+impl Sink for ParquetSink {
+    fn create(path: &Path, schema: SchemaRef, options: &SinkOptions) -> Result<Self> {
+        let (file, bytes_written) = CountingFile::create(path)?;
+        let arrow_writer = ArrowWriter::try_new(file, schema, Some(options.parquet.clone()))?;
+        Ok(ParquetSink {
+            path: path.to_path_buf(),
+            arrow_writer,
+            bytes_written,
+        })
+    }
+
+    fn write(&mut self, batch: &RecordBatch) -> Result<()> {
+        Ok(self.arrow_writer.write(batch)?)
+    }
+
+    fn buffered_size(&self) -> usize {
+        self.arrow_writer.in_progress_size()
+    }
+
+    fn bytes_written(&self) -> u64 {
+        self.bytes_written.load(Ordering::Relaxed)
+    }
+
+    /// Close the current row group
+    fn flush(&mut self) -> Result<()> {
+        Ok(self.arrow_writer.flush()?)
+    }
+
+    fn finish(self: Box<Self>) -> Result<PartInfo> {
+        let metadata = self.arrow_writer.close()?;
+        PartInfo::new(&self.path, &metadata)
+    }
+}
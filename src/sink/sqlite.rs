@@ -0,0 +1,375 @@
+//! SQLite database.
+//!
+//! Every table of the dump is created in the same database file, rows being inserted by
+//! a prepared statement, one transaction per record batch. Timestamps are stored as
+//! `YYYY-MM-DD hh:mm:ss[.fff]` text, which SQLite date functions understand.
+
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use arrow::{
+    array::{Array, ArrayRef, AsArray},
+    datatypes::{
+        DataType, Float32Type, Float64Type, Int16Type, Int32Type, Int64Type, Int8Type, SchemaRef,
+        TimeUnit, TimestampMicrosecondType, TimestampMillisecondType, TimestampSecondType,
+    },
+    record_batch::RecordBatch,
+    util::display::ArrayFormatter,
+};
+use chrono::NaiveDateTime;
+use color_eyre::eyre::{Context, Result};
+use rusqlite::{params_from_iter, types::Value, Connection};
+
+use super::{Sink, SinkOptions};
+use crate::{
+    line_parser::{ColumnKey, Schema},
+    manifest::{PartInfo, StatsCollector},
+};
+
+/// How long a connection waits for another one to release the database, converters of
+/// mydumper chunks writing at the same time
+const BUSY_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Table of a SQLite database
+pub struct SqliteSink {
+    path: PathBuf,
+    connection: Connection,
+    insert: String,
+    stats: StatsCollector,
+}
+
+fn open(path: &Path) -> Result<Connection> {
+    let connection =
+        Connection::open(path).with_context(|| format!("Cannot open {}", path.display()))?;
+    connection.busy_timeout(BUSY_TIMEOUT)?;
+    // the database is rebuilt from the dump when a conversion fails
+    connection.pragma_update(None, "synchronous", "OFF")?;
+    Ok(connection)
+}
+
+/// Quote an identifier
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Type of a column in a `CREATE TABLE` statement
+fn sqlite_type(data_type: &DataType) -> &'static str {
+    match data_type {
+        DataType::Int8 | DataType::Int16 | DataType::Int32 | DataType::Int64 => "INTEGER",
+        DataType::Boolean => "INTEGER",
+        DataType::Float32 | DataType::Float64 => "REAL",
+        DataType::Binary | DataType::LargeBinary => "BLOB",
+        // decimals are kept exact
+        _ => "TEXT",
+    }
+}
+
+/// Value of a cell
+fn sql_value(array: &ArrayRef, formatter: &ArrayFormatter, row: usize) -> Value {
+    if array.is_null(row) {
+        return Value::Null;
+    }
+    match array.data_type() {
+        DataType::Int8 => Value::Integer(array.as_primitive::<Int8Type>().value(row).into()),
+        DataType::Int16 => Value::Integer(array.as_primitive::<Int16Type>().value(row).into()),
+        DataType::Int32 => Value::Integer(array.as_primitive::<Int32Type>().value(row).into()),
+        DataType::Int64 => Value::Integer(array.as_primitive::<Int64Type>().value(row)),
+        DataType::Boolean => Value::Integer(array.as_boolean().value(row).into()),
+        DataType::Float32 => Value::Real(array.as_primitive::<Float32Type>().value(row).into()),
+        DataType::Float64 => Value::Real(array.as_primitive::<Float64Type>().value(row)),
+        DataType::Utf8 => Value::Text(array.as_string::<i32>().value(row).to_string()),
+        DataType::Binary => Value::Blob(array.as_binary::<i32>().value(row).to_vec()),
+        DataType::Timestamp(unit, _) => {
+            let timestamp = match unit {
+                TimeUnit::Second => NaiveDateTime::from_timestamp_opt(
+                    array.as_primitive::<TimestampSecondType>().value(row),
+                    0,
+                ),
+                TimeUnit::Millisecond => NaiveDateTime::from_timestamp_millis(
+                    array.as_primitive::<TimestampMillisecondType>().value(row),
+                ),
+                TimeUnit::Microsecond => NaiveDateTime::from_timestamp_micros(
+                    array.as_primitive::<TimestampMicrosecondType>().value(row),
+                ),
+                TimeUnit::Nanosecond => return Value::Text(formatter.value(row).to_string()),
+            };
+            match timestamp {
+                Some(timestamp) => {
+                    Value::Text(timestamp.format("%Y-%m-%d %H:%M:%S%.f").to_string())
+                }
+                None => Value::Null,
+            }
+        }
+        _ => Value::Text(formatter.value(row).to_string()),
+    }
+}
+
+impl Sink for SqliteSink {
+    fn create(path: &Path, schema: SchemaRef, options: &SinkOptions) -> Result<Self> {
+        let connection = open(path)?;
+        let table = quote(&options.table_name);
+        let columns: Vec<String> = schema
+            .fields()
+            .iter()
+            .map(|field| {
+                let not_null = if field.is_nullable() { "" } else { " NOT NULL" };
+                format!(
+                    "{} {}{not_null}",
+                    quote(field.name()),
+                    sqlite_type(field.data_type())
+                )
+            })
+            .collect();
+        // chunks of a table are created by each of their converters
+        connection
+            .execute(
+                &format!(
+                    "CREATE TABLE IF NOT EXISTS {table} ({})",
+                    columns.join(", ")
+                ),
+                (),
+            )
+            .with_context(|| format!("Cannot create table {table} in {}", path.display()))?;
+        let names: Vec<String> = schema
+            .fields()
+            .iter()
+            .map(|field| quote(field.name()))
+            .collect();
+        let placeholders: Vec<String> = (1..=names.len()).map(|i| format!("?{i}")).collect();
+        Ok(SqliteSink {
+            path: path.to_path_buf(),
+            connection,
+            insert: format!(
+                "INSERT INTO {table} ({}) VALUES ({})",
+                names.join(", "),
+                placeholders.join(", ")
+            ),
+            stats: StatsCollector::new(&schema),
+        })
+    }
+
+    fn write(&mut self, batch: &RecordBatch) -> Result<()> {
+        let formatters = batch
+            .columns()
+            .iter()
+            .map(|column| ArrayFormatter::try_new(column.as_ref(), &Default::default()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let transaction = self.connection.transaction()?;
+        {
+            let mut insert = transaction.prepare_cached(&self.insert)?;
+            for row in 0..batch.num_rows() {
+                let values = batch
+                    .columns()
+                    .iter()
+                    .zip(&formatters)
+                    .map(|(column, formatter)| sql_value(column, formatter, row));
+                insert.execute(params_from_iter(values))?;
+            }
+        }
+        transaction.commit()?;
+        self.stats.update(batch);
+        Ok(())
+    }
+
+    /// Rows are inserted right away
+    fn buffered_size(&self) -> usize {
+        0
+    }
+
+    /// Not tracked, databases are never split
+    fn bytes_written(&self) -> u64 {
+        0
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<PartInfo> {
+        let SqliteSink {
+            path,
+            connection,
+            stats,
+            ..
+        } = *self;
+        connection
+            .close()
+            .map_err(|(_, e)| e)
+            .with_context(|| format!("Cannot close {}", path.display()))?;
+        stats.finish(&path)
+    }
+}
+
+/// Index a table once loaded: its PRIMARY KEY with a unique index, and its `UNIQUE KEY`
+/// and `KEY` indexes whose columns are all written
+pub fn create_indexes(database: &Path, table_name: &str, schema: &Schema) -> Result<()> {
+    let mut indexes: Vec<(String, bool, Vec<String>)> = Vec::new();
+    let primary: Vec<String> = schema
+        .columns
+        .iter()
+        .filter(|column| column.key == ColumnKey::Primary)
+        .map(|column| quote(&column.field_name))
+        .collect();
+    if !primary.is_empty() {
+        indexes.push((format!("{table_name}_primary"), true, primary));
+    }
+    for (i, index) in schema.indexes.iter().enumerate() {
+        let columns: Option<Vec<String>> = index
+            .columns
+            .iter()
+            .map(|name| {
+                schema
+                    .columns
+                    .iter()
+                    .find(|column| column.column_name.eq_ignore_ascii_case(name))
+                    .map(|column| quote(&column.field_name))
+            })
+            .collect();
+        let Some(columns) = columns else {
+            // an excluded column
+            continue;
+        };
+        // index names are shared by the tables of a database
+        let name = match &index.name {
+            Some(name) => format!("{table_name}_{name}"),
+            None => format!("{table_name}_index{i}"),
+        };
+        indexes.push((name, index.unique, columns));
+    }
+    if indexes.is_empty() {
+        return Ok(());
+    }
+    let connection = open(database)?;
+    for (name, unique, columns) in indexes {
+        connection
+            .execute(
+                &format!(
+                    "CREATE {}INDEX IF NOT EXISTS {} ON {} ({})",
+                    if unique { "UNIQUE " } else { "" },
+                    quote(&name),
+                    quote(table_name),
+                    columns.join(", ")
+                ),
+                (),
+            )
+            .with_context(|| format!("Cannot create index `{name}` of `{table_name}`"))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::testing::TempDir;
+    use std::sync::Arc;
+
+    use arrow::{
+        array::{Float64Array, Int64Array, StringArray, TimestampSecondArray},
+        datatypes::{DataType, Field, Schema, TimeUnit},
+        record_batch::RecordBatch,
+    };
+    use parquet::file::properties::WriterProperties;
+    use rusqlite::Connection;
+
+    use super::create_indexes;
+    use crate::{
+        line_parser::{self, Line},
+        sink::{Format, SinkOptions},
+    };
+
+    #[test]
+    fn load_tables() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("name", DataType::Utf8, true),
+            Field::new("price", DataType::Float64, true),
+            Field::new(
+                "created_at",
+                DataType::Timestamp(TimeUnit::Second, None),
+                true,
+            ),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Int64Array::from(vec![1, 2])),
+                Arc::new(StringArray::from(vec![Some("a \"b\""), None])),
+                Arc::new(Float64Array::from(vec![Some(1.5), None])),
+                Arc::new(TimestampSecondArray::from(vec![Some(1_714_557_600), None])),
+            ],
+        )
+        .unwrap();
+        let dir = TempDir::new("sqlite");
+        let path = dir.join("dump.sqlite");
+        let options = SinkOptions {
+            format: Format::Sqlite,
+            parquet: WriterProperties::builder().build(),
+            key_value_metadata: Vec::new(),
+            text: Default::default(),
+            table_name: "user".into(),
+        };
+        // two chunks of the same table
+        for _ in 0..2 {
+            let mut sink = Format::Sqlite
+                .create_table(&path, schema.clone(), &options)
+                .unwrap();
+            sink.write(&batch).unwrap();
+            assert_eq!(sink.finish().unwrap().row_count, 2);
+        }
+
+        let connection = Connection::open(&path).unwrap();
+        type Row = (i64, Option<String>, Option<f64>, Option<String>);
+        let rows: Vec<Row> = connection
+            .prepare("SELECT id, name, price, created_at FROM user ORDER BY rowid")
+            .unwrap()
+            .query_map((), |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(rows.len(), 4);
+        assert_eq!(
+            rows[0],
+            (
+                1,
+                Some("a \"b\"".into()),
+                Some(1.5),
+                Some("2024-05-01 10:00:00".into())
+            )
+        );
+        assert_eq!(rows[1], (2, None, None, None));
+        assert!(connection
+            .execute("INSERT INTO user (id) VALUES (NULL)", ())
+            .is_err());
+
+        // duplicated primary keys cannot be indexed
+        let Line::CreateTable(_, mut ddl) = line_parser::parse_line(
+            "CREATE TABLE user (id INT, name TEXT, price DOUBLE, excluded INT, PRIMARY KEY (id), \
+             KEY name (name), UNIQUE KEY name_price (name, price), KEY other (excluded))",
+        )
+        .unwrap() else {
+            panic!("Not a CREATE TABLE statement");
+        };
+        // as written: without the excluded column, whose index is skipped
+        ddl.columns
+            .retain(|column| column.column_name != "excluded");
+        assert!(create_indexes(&path, "user", &ddl).is_err());
+        connection
+            .execute("DELETE FROM user WHERE rowid > 2", ())
+            .unwrap();
+        create_indexes(&path, "user", &ddl).unwrap();
+        let indexes: Vec<String> = connection
+            .prepare(
+                "SELECT name FROM sqlite_master WHERE type = 'index' AND tbl_name = 'user' \
+                 ORDER BY name",
+            )
+            .unwrap()
+            .query_map((), |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(indexes, ["user_name", "user_name_price", "user_primary"]);
+    }
+}
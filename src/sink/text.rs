@@ -0,0 +1,435 @@
+//! CSV and NDJSON files, for spreadsheets, Elasticsearch bulk loads and legacy loaders.
+//!
+//! Values are rendered with Arrow display formatting, so decimals are written exactly;
+//! binary values would be written as base64, but dumps do not produce binary columns
+//! yet: BLOB and BINARY columns are still reported as unsupported types.
+
+use std::{
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use arrow::{
+    array::{Array, ArrayRef, AsArray, FixedSizeBinaryArray, StringArray},
+    datatypes::{DataType, Schema, SchemaRef},
+    record_batch::RecordBatch,
+    util::display::{ArrayFormatter, FormatOptions},
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use clap::{Args, ValueEnum};
+use color_eyre::eyre::Result;
+use flate2::write::GzEncoder;
+use serde::Deserialize;
+
+use super::{CountingFile, Format, Sink, SinkOptions};
+use crate::{
+    manifest::{PartInfo, StatsCollector},
+    tab,
+};
+
+/// Format of timestamps when not configured, as MySQL prints them
+const DEFAULT_TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum QuoteStyle {
+    /// Quote every field
+    Always,
+    /// Quote fields holding the delimiter, the quote character or a line break
+    Necessary,
+    /// Quote every field which is not a number
+    NonNumeric,
+    /// Never quote fields
+    Never,
+}
+
+/// Settings of CSV and NDJSON output
+#[derive(Clone, Debug, Default, Args, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TextOptions {
+    /// Field delimiter of CSV files (escapes such as `\t` allowed) [default: ,]
+    #[arg(long, value_parser = parse_char)]
+    pub csv_delimiter: Option<char>,
+    /// Quote character of CSV files [default: "]
+    #[arg(long, value_parser = parse_char)]
+    pub csv_quote: Option<char>,
+    /// Which CSV fields are quoted [default: necessary]
+    #[arg(long, value_enum)]
+    pub csv_quote_style: Option<QuoteStyle>,
+    /// Write a header line with column names in CSV files [default: true]
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    pub csv_header: Option<bool>,
+    /// Representation of NULL in CSV files, e.g. `\N` [default: empty field]
+    #[arg(long)]
+    pub csv_null: Option<String>,
+    /// strftime format of timestamps in CSV and NDJSON files [default: %Y-%m-%d %H:%M:%S]
+    #[arg(long)]
+    pub timestamp_format: Option<String>,
+    /// Compress CSV and NDJSON files with gzip (`.csv.gz`, `.ndjson.gz`)
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    pub gzip: Option<bool>,
+}
+
+impl TextOptions {
+    /// Fill unset settings from `fallback`
+    pub fn or(&self, fallback: &TextOptions) -> TextOptions {
+        TextOptions {
+            csv_delimiter: self.csv_delimiter.or(fallback.csv_delimiter),
+            csv_quote: self.csv_quote.or(fallback.csv_quote),
+            csv_quote_style: self.csv_quote_style.or(fallback.csv_quote_style),
+            csv_header: self.csv_header.or(fallback.csv_header),
+            csv_null: self.csv_null.clone().or(fallback.csv_null.clone()),
+            timestamp_format: self
+                .timestamp_format
+                .clone()
+                .or(fallback.timestamp_format.clone()),
+            gzip: self.gzip.or(fallback.gzip),
+        }
+    }
+
+    pub fn gzip(&self) -> bool {
+        self.gzip == Some(true)
+    }
+}
+
+/// Parse a single byte character option, accepting MySQL escapes such as `\t`
+fn parse_char(value: &str) -> Result<char, String> {
+    let value = tab::parse_field_option(value)?;
+    let mut chars = value.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if c.is_ascii() => Ok(c),
+        _ => Err(format!("Expected a single ASCII character, got `{value}`")),
+    }
+}
+
+/// File, compressed or not
+enum Output {
+    Plain(BufWriter<CountingFile>),
+    Gzip(GzEncoder<BufWriter<CountingFile>>),
+}
+
+impl Output {
+    fn finish(self) -> Result<()> {
+        let mut file = match self {
+            Output::Plain(file) => file,
+            Output::Gzip(encoder) => encoder.finish()?,
+        };
+        Ok(file.flush()?)
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Output::Plain(file) => file.write(buf),
+            Output::Gzip(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Output::Plain(file) => file.flush(),
+            Output::Gzip(encoder) => encoder.flush(),
+        }
+    }
+}
+
+enum TextWriter {
+    Csv(Box<csv::Writer<Output>>),
+    Ndjson(Output),
+}
+
+pub struct TextSink {
+    path: PathBuf,
+    writer: TextWriter,
+    null: String,
+    timestamp_format: String,
+    bytes_written: Arc<AtomicU64>,
+    stats: StatsCollector,
+}
+
+impl Sink for TextSink {
+    fn create(path: &Path, schema: SchemaRef, options: &SinkOptions) -> Result<Self> {
+        let (file, bytes_written) = CountingFile::create(path)?;
+        let file = BufWriter::new(file);
+        let text = &options.text;
+        let output = if text.gzip() {
+            Output::Gzip(GzEncoder::new(file, flate2::Compression::default()))
+        } else {
+            Output::Plain(file)
+        };
+        let writer = match options.format {
+            Format::Ndjson => TextWriter::Ndjson(output),
+            _ => {
+                let mut writer = csv::WriterBuilder::new()
+                    .delimiter(text.csv_delimiter.unwrap_or(',') as u8)
+                    .quote(text.csv_quote.unwrap_or('"') as u8)
+                    .quote_style(match text.csv_quote_style {
+                        Some(QuoteStyle::Always) => csv::QuoteStyle::Always,
+                        Some(QuoteStyle::NonNumeric) => csv::QuoteStyle::NonNumeric,
+                        Some(QuoteStyle::Never) => csv::QuoteStyle::Never,
+                        Some(QuoteStyle::Necessary) | None => csv::QuoteStyle::Necessary,
+                    })
+                    .from_writer(output);
+                if text.csv_header != Some(false) {
+                    writer.write_record(schema.fields().iter().map(|field| field.name()))?;
+                }
+                TextWriter::Csv(Box::new(writer))
+            }
+        };
+        Ok(TextSink {
+            path: path.to_path_buf(),
+            writer,
+            null: text.csv_null.clone().unwrap_or_default(),
+            timestamp_format: text
+                .timestamp_format
+                .clone()
+                .unwrap_or_else(|| DEFAULT_TIMESTAMP_FORMAT.to_string()),
+            bytes_written,
+            stats: StatsCollector::new(&schema),
+        })
+    }
+
+    fn write(&mut self, batch: &RecordBatch) -> Result<()> {
+        self.stats.update(batch);
+        let columns: Vec<ArrayRef> = batch.columns().iter().map(binary_to_base64).collect();
+        let format_options = FormatOptions::new()
+            .with_null(&self.null)
+            .with_timestamp_format(Some(&self.timestamp_format))
+            .with_datetime_format(Some(&self.timestamp_format));
+        let formatters = columns
+            .iter()
+            .map(|column| ArrayFormatter::try_new(column.as_ref(), &format_options))
+            .collect::<Result<Vec<_>, _>>()?;
+        match &mut self.writer {
+            TextWriter::Csv(writer) => {
+                let mut record = Vec::with_capacity(formatters.len());
+                for row in 0..batch.num_rows() {
+                    record.clear();
+                    record.extend(formatters.iter().map(|f| f.value(row).to_string()));
+                    writer.write_record(&record)?;
+                }
+            }
+            TextWriter::Ndjson(output) => {
+                let schema = batch.schema();
+                for row in 0..batch.num_rows() {
+                    write_json_object(output, &schema, &columns, &formatters, row)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Rows are written as they come
+    fn buffered_size(&self) -> usize {
+        0
+    }
+
+    fn bytes_written(&self) -> u64 {
+        self.bytes_written.load(Ordering::Relaxed)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<PartInfo> {
+        let output = match self.writer {
+            TextWriter::Csv(writer) => writer.into_inner().map_err(|e| e.into_error())?,
+            TextWriter::Ndjson(output) => output,
+        };
+        output.finish()?;
+        self.stats.finish(&self.path)
+    }
+}
+
+/// Write `row` as a JSON object on its own line, keeping the order of columns.
+///
+/// Numbers and booleans are JSON values, everything else (timestamps, decimals, base64
+/// binary) is a string.
+fn write_json_object(
+    output: &mut impl Write,
+    schema: &Schema,
+    columns: &[ArrayRef],
+    formatters: &[ArrayFormatter],
+    row: usize,
+) -> Result<()> {
+    output.write_all(b"{")?;
+    for (i, ((field, column), formatter)) in schema
+        .fields()
+        .iter()
+        .zip(columns)
+        .zip(formatters)
+        .enumerate()
+    {
+        if i > 0 {
+            output.write_all(b",")?;
+        }
+        serde_json::to_writer(&mut *output, field.name())?;
+        output.write_all(b":")?;
+        let value = formatter.value(row).to_string();
+        match column.data_type() {
+            _ if column.is_null(row) => output.write_all(b"null")?,
+            DataType::Float16 | DataType::Float32 | DataType::Float64
+                if !value.parse::<f64>().is_ok_and(f64::is_finite) =>
+            {
+                // NaN and infinities are not JSON
+                output.write_all(b"null")?
+            }
+            data_type if data_type.is_numeric() && !is_decimal(data_type) => {
+                output.write_all(value.as_bytes())?
+            }
+            DataType::Boolean => output.write_all(value.as_bytes())?,
+            _ => serde_json::to_writer(&mut *output, &value)?,
+        }
+    }
+    output.write_all(b"}\n")?;
+    Ok(())
+}
+
+fn is_decimal(data_type: &DataType) -> bool {
+    matches!(
+        data_type,
+        DataType::Decimal128(_, _) | DataType::Decimal256(_, _)
+    )
+}
+
+/// Base64 strings of binary arrays, other arrays being returned as is
+fn binary_to_base64(array: &ArrayRef) -> ArrayRef {
+    let encode = |values: &mut dyn Iterator<Item = Option<&[u8]>>| -> ArrayRef {
+        Arc::new(
+            values
+                .map(|value| value.map(|value| STANDARD.encode(value)))
+                .collect::<StringArray>(),
+        )
+    };
+    match array.data_type() {
+        DataType::Binary => encode(&mut array.as_binary::<i32>().iter()),
+        DataType::LargeBinary => encode(&mut array.as_binary::<i64>().iter()),
+        DataType::FixedSizeBinary(_) => encode(
+            &mut array
+                .as_any()
+                .downcast_ref::<FixedSizeBinaryArray>()
+                .unwrap()
+                .iter(),
+        ),
+        _ => array.clone(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::testing::TempDir;
+    use std::{io::Read, sync::Arc};
+
+    use arrow::{
+        array::{
+            BinaryArray, Decimal128Array, Float64Array, Int64Array, StringArray,
+            TimestampSecondArray,
+        },
+        datatypes::{DataType, Field, Schema, TimeUnit},
+        record_batch::RecordBatch,
+    };
+    use parquet::file::properties::WriterProperties;
+
+    use super::{QuoteStyle, TextOptions};
+    use crate::sink::{Format, SinkOptions};
+
+    fn batch() -> RecordBatch {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("name", DataType::Utf8, true),
+            Field::new("price", DataType::Decimal128(20, 4), true),
+            Field::new("score", DataType::Float64, true),
+            Field::new("data", DataType::Binary, true),
+            Field::new(
+                "created_at",
+                DataType::Timestamp(TimeUnit::Second, None),
+                true,
+            ),
+        ]));
+        RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(Int64Array::from(vec![1, 2])),
+                Arc::new(StringArray::from(vec![Some("a,\"b\""), None])),
+                Arc::new(
+                    Decimal128Array::from(vec![Some(123456789012345678), None])
+                        .with_precision_and_scale(20, 4)
+                        .unwrap(),
+                ),
+                Arc::new(Float64Array::from(vec![Some(0.5), Some(f64::NAN)])),
+                Arc::new(BinaryArray::from(vec![Some(&b"\x00\xff"[..]), None])),
+                Arc::new(TimestampSecondArray::from(vec![Some(1714557600), None])),
+            ],
+        )
+        .unwrap()
+    }
+
+    fn write(format: Format, text: TextOptions) -> String {
+        let dir = TempDir::new(&format!("text-{format:?}"));
+        let options = SinkOptions {
+            format,
+            parquet: WriterProperties::builder().build(),
+            key_value_metadata: Vec::new(),
+            text,
+            table_name: "user".into(),
+        };
+        let path = dir.join(format!("{format:?}.{}", options.extension()));
+        let mut sink = format
+            .create_table(&path, batch().schema(), &options)
+            .unwrap();
+        sink.write(&batch()).unwrap();
+        assert_eq!(sink.finish().unwrap().row_count, 2);
+        let mut content = String::new();
+        let file = std::fs::File::open(&path).unwrap();
+        if options.text.gzip() {
+            flate2::read::GzDecoder::new(file)
+                .read_to_string(&mut content)
+                .unwrap();
+        } else {
+            std::io::BufReader::new(file)
+                .read_to_string(&mut content)
+                .unwrap();
+        }
+        content
+    }
+
+    #[test]
+    fn csv() {
+        assert_eq!(
+            write(Format::Csv, TextOptions::default()),
+            "id,name,price,score,data,created_at\n\
+             1,\"a,\"\"b\"\"\",12345678901234.5678,0.5,AP8=,2024-05-01 10:00:00\n\
+             2,,,NaN,,\n"
+        );
+        let text = TextOptions {
+            csv_delimiter: Some(';'),
+            csv_quote_style: Some(QuoteStyle::NonNumeric),
+            csv_header: Some(false),
+            csv_null: Some("\\N".into()),
+            timestamp_format: Some("%Y-%m-%dT%H:%M:%SZ".into()),
+            gzip: Some(true),
+            ..Default::default()
+        };
+        assert_eq!(
+            write(Format::Csv, text),
+            "1;\"a,\"\"b\"\"\";12345678901234.5678;0.5;\"AP8=\";\"2024-05-01T10:00:00Z\"\n\
+             2;\"\\N\";\"\\N\";NaN;\"\\N\";\"\\N\"\n"
+        );
+    }
+
+    #[test]
+    fn ndjson() {
+        assert_eq!(
+            write(Format::Ndjson, TextOptions::default()),
+            "{\"id\":1,\"name\":\"a,\\\"b\\\"\",\"price\":\"12345678901234.5678\",\"score\":0.5,\
+             \"data\":\"AP8=\",\"created_at\":\"2024-05-01 10:00:00\"}\n\
+             {\"id\":2,\"name\":null,\"price\":null,\"score\":null,\"data\":null,\"created_at\":null}\n"
+        );
+    }
+}
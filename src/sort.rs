@@ -0,0 +1,281 @@
+//! External sort of record batches.
+//!
+//! Batches are buffered in memory; when the buffer is over budget it is sorted and
+//! spilled to disk as an Arrow IPC file (a run). Runs are merged when the sort is
+//! finished.
+
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    fs::{create_dir_all, remove_dir_all, File},
+    io::{BufReader, BufWriter},
+    path::PathBuf,
+};
+
+use arrow::{
+    array::{Array, ArrayRef},
+    compute::{concat_batches, interleave, lexsort_to_indices, take, SortColumn},
+    datatypes::SchemaRef,
+    ipc::{reader::FileReader, writer::FileWriter},
+    record_batch::RecordBatch,
+    row::{RowConverter, Rows, SortField},
+};
+use color_eyre::eyre::Result;
+
+/// rows per batch of spilled runs and merged output
+const BATCH_SIZE: usize = 8192;
+
+pub struct ExternalSorter {
+    schema: SchemaRef,
+    /// sort key: indices of columns, ascending, nulls first
+    sort_columns: Vec<usize>,
+    buffered: Vec<RecordBatch>,
+    buffered_size: usize,
+    spill_dir: PathBuf,
+    runs: Vec<PathBuf>,
+}
+
+impl ExternalSorter {
+    pub fn new(schema: SchemaRef, sort_columns: Vec<usize>, spill_dir: PathBuf) -> Self {
+        ExternalSorter {
+            schema,
+            sort_columns,
+            buffered: Vec::new(),
+            buffered_size: 0,
+            spill_dir,
+            runs: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, batch: RecordBatch) {
+        self.buffered_size += batch.get_array_memory_size();
+        self.buffered.push(batch);
+    }
+
+    /// Memory used by batches waiting to be sorted
+    pub fn buffered_size(&self) -> usize {
+        self.buffered_size
+    }
+
+    /// Sort buffered batches in memory
+    fn sort_buffered(&mut self) -> Result<RecordBatch> {
+        let batch = concat_batches(&self.schema, &self.buffered)?;
+        self.buffered.clear();
+        self.buffered_size = 0;
+        let sort_columns: Vec<_> = self
+            .sort_columns
+            .iter()
+            .map(|i| SortColumn {
+                values: batch.column(*i).clone(),
+                options: None,
+            })
+            .collect();
+        let indices = lexsort_to_indices(&sort_columns, None)?;
+        let columns = batch
+            .columns()
+            .iter()
+            .map(|column| take(column, &indices, None))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(RecordBatch::try_new(self.schema.clone(), columns)?)
+    }
+
+    /// Sort buffered batches and write them to disk
+    pub fn spill(&mut self) -> Result<()> {
+        if self.buffered.is_empty() {
+            return Ok(());
+        }
+        let batch = self.sort_buffered()?;
+        create_dir_all(&self.spill_dir)?;
+        let path = self
+            .spill_dir
+            .join(format!("run-{:05}.arrow", self.runs.len()));
+        let mut writer = FileWriter::try_new(BufWriter::new(File::create(&path)?), &self.schema)?;
+        for offset in (0..batch.num_rows()).step_by(BATCH_SIZE) {
+            writer.write(&batch.slice(offset, BATCH_SIZE.min(batch.num_rows() - offset)))?;
+        }
+        writer.finish()?;
+        self.runs.push(path);
+        Ok(())
+    }
+
+    /// Sort everything, calling `write` with sorted batches
+    pub fn finish(mut self, mut write: impl FnMut(&RecordBatch) -> Result<()>) -> Result<()> {
+        if self.runs.is_empty() {
+            if !self.buffered.is_empty() {
+                write(&self.sort_buffered()?)?;
+            }
+            return Ok(());
+        }
+        self.spill()?;
+        self.merge_runs(&mut write)?;
+        remove_dir_all(&self.spill_dir)?;
+        Ok(())
+    }
+
+    fn sort_key(&self, batch: &RecordBatch) -> Vec<ArrayRef> {
+        self.sort_columns
+            .iter()
+            .map(|i| batch.column(*i).clone())
+            .collect()
+    }
+
+    /// k-way merge of the sorted runs
+    fn merge_runs(&self, write: &mut impl FnMut(&RecordBatch) -> Result<()>) -> Result<()> {
+        struct Cursor {
+            reader: FileReader<BufReader<File>>,
+            /// index of the current batch in `batches`
+            batch: usize,
+            rows: Rows,
+            row: usize,
+        }
+        let converter = RowConverter::new(
+            self.sort_columns
+                .iter()
+                .map(|i| SortField::new(self.schema.field(*i).data_type().clone()))
+                .collect(),
+        )?;
+        let mut batches: Vec<RecordBatch> = Vec::new();
+        let mut cursors: Vec<Option<Cursor>> = Vec::new();
+        let mut heap = BinaryHeap::new();
+        for run in &self.runs {
+            let mut reader = FileReader::try_new(BufReader::new(File::open(run)?), None)?;
+            if let Some(batch) = reader.next().transpose()? {
+                let rows = converter.convert_columns(&self.sort_key(&batch))?;
+                heap.push(Reverse((rows.row(0).owned(), cursors.len())));
+                batches.push(batch);
+                cursors.push(Some(Cursor {
+                    reader,
+                    batch: batches.len() - 1,
+                    rows,
+                    row: 0,
+                }));
+            }
+        }
+
+        let mut indices = Vec::with_capacity(BATCH_SIZE);
+        while let Some(Reverse((_, run))) = heap.pop() {
+            let cursor = cursors[run].as_mut().unwrap();
+            indices.push((cursor.batch, cursor.row));
+            cursor.row += 1;
+            if cursor.row == cursor.rows.num_rows() {
+                // runs are written without empty batches
+                match cursor.reader.next().transpose()? {
+                    Some(batch) => {
+                        cursor.rows = converter.convert_columns(&self.sort_key(&batch))?;
+                        cursor.row = 0;
+                        batches.push(batch);
+                        cursor.batch = batches.len() - 1;
+                    }
+                    None => cursors[run] = None,
+                }
+            }
+            if let Some(cursor) = &cursors[run] {
+                heap.push(Reverse((cursor.rows.row(cursor.row).owned(), run)));
+            }
+            if indices.len() == BATCH_SIZE {
+                write(&self.interleave(&batches, &indices)?)?;
+                indices.clear();
+                // only keep batches still being read
+                let mut current_batches = Vec::new();
+                for cursor in cursors.iter_mut().flatten() {
+                    current_batches.push(batches[cursor.batch].clone());
+                    cursor.batch = current_batches.len() - 1;
+                }
+                batches = current_batches;
+            }
+        }
+        if !indices.is_empty() {
+            write(&self.interleave(&batches, &indices)?)?;
+        }
+        Ok(())
+    }
+
+    fn interleave(
+        &self,
+        batches: &[RecordBatch],
+        indices: &[(usize, usize)],
+    ) -> Result<RecordBatch> {
+        let columns = (0..self.schema.fields().len())
+            .map(|i| {
+                let arrays: Vec<&dyn Array> =
+                    batches.iter().map(|b| b.column(i).as_ref()).collect();
+                interleave(&arrays, indices)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(RecordBatch::try_new(self.schema.clone(), columns)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use arrow::{
+        array::{AsArray, Int64Array, StringArray},
+        datatypes::{DataType, Field, Int64Type, Schema},
+        record_batch::RecordBatch,
+    };
+
+    use super::ExternalSorter;
+
+    fn batch(schema: &Arc<Schema>, keys: &[Option<i64>]) -> RecordBatch {
+        let names: Vec<String> = keys.iter().map(|k| format!("{k:?}")).collect();
+        RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Int64Array::from(keys.to_vec())),
+                Arc::new(StringArray::from(names)),
+            ],
+        )
+        .unwrap()
+    }
+
+    fn sort(spill: bool) -> Vec<Option<i64>> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("key", DataType::Int64, true),
+            Field::new("name", DataType::Utf8, false),
+        ]));
+        let spill_dir = std::env::temp_dir().join(format!(
+            "mysqldump-to-parquet-sort-{spill}-{}",
+            std::process::id()
+        ));
+        let mut sorter = ExternalSorter::new(schema.clone(), vec![0], spill_dir.clone());
+        for keys in [
+            vec![Some(5), None, Some(1)],
+            vec![Some(3), Some(3)],
+            (0..20_000).rev().map(Some).collect(),
+        ] {
+            sorter.push(batch(&schema, &keys));
+            if spill {
+                sorter.spill().unwrap();
+            }
+        }
+        let mut keys = Vec::new();
+        sorter
+            .finish(|batch| {
+                let names = batch.column(1).as_string::<i32>();
+                for (i, key) in batch
+                    .column(0)
+                    .as_primitive::<Int64Type>()
+                    .iter()
+                    .enumerate()
+                {
+                    assert_eq!(names.value(i), format!("{key:?}"));
+                    keys.push(key);
+                }
+                Ok(())
+            })
+            .unwrap();
+        assert!(!spill_dir.exists());
+        keys
+    }
+
+    #[test]
+    fn sort_in_memory_and_spilled() {
+        let mut expected: Vec<Option<i64>> = vec![None, Some(1), Some(3), Some(3), Some(5)];
+        expected.extend((0..20_000).map(Some));
+        expected.sort();
+        assert_eq!(sort(false), expected);
+        assert_eq!(sort(true), expected);
+    }
+}
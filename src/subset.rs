@@ -0,0 +1,405 @@
+//! Referentially consistent subsets of a dump, following its foreign keys.
+//!
+//! A first pass reads the `CREATE TABLE` statements and the key columns of every row.
+//! Starting from the rows of a seed table matching a condition, the subset holds:
+//! - the rows referencing a row of the subset, recursively: the orders of a company, the
+//!   lines of these orders...
+//! - the rows referenced by a row of the subset, recursively, so that every foreign key
+//!   of the subset resolves: the products of these order lines, their suppliers...
+//!
+//! The second pass writes the rows of the subset, told apart by their position in their
+//! table.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+use color_eyre::eyre::{bail, Context, Result};
+use indicatif::ProgressBar;
+
+use crate::{
+    filter::TableFilter,
+    input::{self, Compression},
+    line_parser::{self, ColumnKey, ColumnValue, Line, Schema},
+    predicate::Predicate,
+    read_statements,
+};
+
+/// Rows of every table in the subset, shared by the readers and the writer
+#[derive(Clone, Debug, Default)]
+pub struct Subset(Arc<Mutex<HashMap<String, Selection>>>);
+
+#[derive(Debug)]
+struct Selection {
+    /// whether each row of the table is in the subset, in dump order
+    kept: Vec<bool>,
+    /// rows of the table seen by the second pass
+    seen: usize,
+}
+
+/// First pass over the `inputs`: read the keys of every row of the `tables`, then select
+/// the rows reachable from the rows of the `seed` table matching its condition.
+pub fn collect(
+    inputs: &[String],
+    compression: Compression,
+    tables: &TableFilter,
+    seed: &(String, String),
+    progress_bar: &ProgressBar,
+) -> Result<Subset> {
+    if inputs.is_empty() {
+        bail!("--subset reads the dump twice: it cannot be read from the standard input");
+    }
+    let mut keys = Keys::new(seed);
+    let mut current_statement = String::with_capacity(8192);
+    for file in inputs {
+        progress_bar.set_message(format!("Collecting keys: {file}"));
+        let mut reader = input::open(Some(file), compression)?;
+        read_statements(
+            &mut reader,
+            &mut current_statement,
+            progress_bar,
+            tables,
+            |statement| keys.add(line_parser::parse_line(&statement)?),
+        )
+        .with_context(|| format!("Unable to collect the keys of {file}"))?;
+    }
+    progress_bar.reset();
+    keys.select()
+}
+
+impl Subset {
+    /// Rows of `table_name` in the subset, see [`SubsetRows::rows`]
+    pub fn table(&self, table_name: &str) -> SubsetRows {
+        SubsetRows {
+            table_name: table_name.to_string(),
+            subset: self.clone(),
+        }
+    }
+
+    /// Whether no row of `table_name` is in the subset
+    pub fn is_empty(&self, table_name: &str) -> bool {
+        self.0
+            .lock()
+            .unwrap()
+            .get(table_name)
+            .is_none_or(|selection| !selection.kept.contains(&true))
+    }
+}
+
+/// Rows of a table in the subset
+#[derive(Clone, Debug)]
+pub struct SubsetRows {
+    table_name: String,
+    subset: Subset,
+}
+
+impl SubsetRows {
+    /// Keep the rows of the subset among the next `rows` of the table
+    pub fn rows(&self, rows: &mut Vec<Vec<ColumnValue>>) {
+        let mut subset = self.subset.0.lock().unwrap();
+        let Some(selection) = subset.get_mut(&self.table_name) else {
+            rows.clear();
+            return;
+        };
+        rows.retain(|_| {
+            selection.seen += 1;
+            selection.kept.get(selection.seen - 1) == Some(&true)
+        });
+    }
+}
+
+/// Keys of the rows of every table, read by the first pass
+struct Keys<'a> {
+    seed_table: &'a str,
+    seed_condition: &'a str,
+    tables: HashMap<String, TableKeys>,
+}
+
+struct TableKeys {
+    schema: Schema,
+    /// positions of the columns read: keys, foreign keys, and columns referenced by
+    /// foreign keys of the tables read before
+    columns: Vec<usize>,
+    /// values of `columns` by row, `None` for `NULL`
+    rows: Vec<Vec<Option<String>>>,
+    /// condition of the seed table
+    predicate: Option<Predicate>,
+    /// rows of the seed table matching its condition
+    seeds: Vec<usize>,
+}
+
+/// Foreign key, between columns read
+struct Edge<'a> {
+    table: &'a str,
+    columns: Vec<usize>,
+    referenced_table: &'a str,
+    referenced_columns: Vec<usize>,
+}
+
+impl<'a> Keys<'a> {
+    fn new((seed_table, seed_condition): &'a (String, String)) -> Keys<'a> {
+        Keys {
+            seed_table,
+            seed_condition,
+            tables: HashMap::new(),
+        }
+    }
+
+    fn add(&mut self, line: Line) -> Result<()> {
+        match line {
+            Line::CreateTable(table_name, schema) => {
+                if self.tables.contains_key(&table_name) {
+                    // same table found in another input file
+                    return Ok(());
+                }
+                let referenced = |column_name: &str| {
+                    schema.foreign_keys.iter().any(|fk| {
+                        fk.columns
+                            .iter()
+                            .any(|c| c.eq_ignore_ascii_case(column_name))
+                    }) || self.tables.values().any(|table| {
+                        table.schema.foreign_keys.iter().any(|fk| {
+                            fk.referenced_table == table_name
+                                && fk
+                                    .referenced_columns
+                                    .iter()
+                                    .any(|c| c.eq_ignore_ascii_case(column_name))
+                        })
+                    })
+                };
+                let columns = schema
+                    .columns
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, column)| {
+                        column.key != ColumnKey::None || referenced(&column.column_name)
+                    })
+                    .map(|(i, _)| i)
+                    .collect();
+                let predicate = (table_name == self.seed_table)
+                    .then(|| Predicate::new(self.seed_condition, &schema))
+                    .transpose()
+                    .with_context(|| format!("Invalid --subset condition on `{table_name}`"))?;
+                self.tables.insert(
+                    table_name,
+                    TableKeys {
+                        schema,
+                        columns,
+                        rows: Vec::new(),
+                        predicate,
+                        seeds: Vec::new(),
+                    },
+                );
+            }
+            Line::InsertInto(table_name, rows) => {
+                let Some(table) = self.tables.get_mut(&table_name) else {
+                    bail!("Rows of `{table_name}` come before its CREATE TABLE statement");
+                };
+                for row in rows {
+                    if table.predicate.as_ref().is_some_and(|p| p.matches(&row)) {
+                        table.seeds.push(table.rows.len());
+                    }
+                    table
+                        .rows
+                        .push(table.columns.iter().map(|&i| key(&row[i])).collect());
+                }
+            }
+            Line::NOP => {}
+        }
+        Ok(())
+    }
+
+    /// Rows reachable from the seed rows
+    fn select(self) -> Result<Subset> {
+        let Some(seed) = self.tables.get(self.seed_table) else {
+            bail!("No table `{}` to start the subset from", self.seed_table);
+        };
+        let edges = self.edges()?;
+        let mut kept: HashMap<&str, Vec<bool>> = self
+            .tables
+            .iter()
+            .map(|(table_name, table)| (table_name.as_str(), vec![false; table.rows.len()]))
+            .collect();
+        for &row in &seed.seeds {
+            kept.get_mut(self.seed_table).unwrap()[row] = true;
+        }
+        // rows referencing the subset, then rows referenced by it
+        for referencing in [true, false] {
+            let mut changed = true;
+            while changed {
+                changed = false;
+                for edge in &edges {
+                    changed |= if referencing {
+                        self.propagate(&mut kept, edge.referenced(), edge.referencing())
+                    } else {
+                        self.propagate(&mut kept, edge.referencing(), edge.referenced())
+                    };
+                }
+            }
+        }
+        Ok(Subset(Arc::new(Mutex::new(
+            kept.into_iter()
+                .map(|(table_name, kept)| (table_name.to_string(), Selection { kept, seen: 0 }))
+                .collect(),
+        ))))
+    }
+
+    /// Foreign keys between the tables read
+    fn edges(&self) -> Result<Vec<Edge<'_>>> {
+        let mut edges = Vec::new();
+        for (table_name, table) in &self.tables {
+            for fk in &table.schema.foreign_keys {
+                let Some(referenced) = self.tables.get(&fk.referenced_table) else {
+                    // table not converted
+                    continue;
+                };
+                edges.push(Edge {
+                    table: table_name,
+                    columns: table.positions(table_name, &fk.columns)?,
+                    referenced_table: &fk.referenced_table,
+                    referenced_columns: referenced
+                        .positions(&fk.referenced_table, &fk.referenced_columns)?,
+                });
+            }
+        }
+        Ok(edges)
+    }
+
+    /// Add to the subset the rows of `to` whose columns match the columns of a row of
+    /// `from` in the subset. Returns whether rows were added.
+    fn propagate(
+        &self,
+        kept: &mut HashMap<&str, Vec<bool>>,
+        (from, from_columns): (&str, &[usize]),
+        (to, to_columns): (&str, &[usize]),
+    ) -> bool {
+        let keys: HashSet<Vec<&str>> = self.tables[from]
+            .rows
+            .iter()
+            .zip(&kept[from])
+            .filter(|(_, kept)| **kept)
+            .filter_map(|(row, _)| values(row, from_columns))
+            .collect();
+        if keys.is_empty() {
+            return false;
+        }
+        let mut changed = false;
+        let to_kept = kept.get_mut(to).unwrap();
+        for (row, kept) in self.tables[to].rows.iter().zip(to_kept) {
+            if !*kept && values(row, to_columns).is_some_and(|v| keys.contains(&v)) {
+                *kept = true;
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+impl Edge<'_> {
+    fn referencing(&self) -> (&str, &[usize]) {
+        (self.table, &self.columns)
+    }
+
+    fn referenced(&self) -> (&str, &[usize]) {
+        (self.referenced_table, &self.referenced_columns)
+    }
+}
+
+impl TableKeys {
+    /// Positions of `column_names` among the columns read
+    fn positions(&self, table_name: &str, column_names: &[String]) -> Result<Vec<usize>> {
+        column_names
+            .iter()
+            .map(|column_name| {
+                let Some(column) = self
+                    .schema
+                    .columns
+                    .iter()
+                    .position(|c| c.column_name.eq_ignore_ascii_case(column_name))
+                else {
+                    bail!("Unknown column `{column_name}` of `{table_name}` in a foreign key");
+                };
+                match self.columns.iter().position(|&i| i == column) {
+                    Some(position) => Ok(position),
+                    None => bail!(
+                        "Column `{column_name}` of `{table_name}` is referenced by a foreign key \
+                         of a table created after it, but is not a key of `{table_name}`"
+                    ),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Text of a key value, `None` for `NULL` which references nothing
+fn key(value: &ColumnValue) -> Option<String> {
+    match value {
+        ColumnValue::Null => None,
+        ColumnValue::String(s) | ColumnValue::Decimal(s) => Some(s.clone()),
+        ColumnValue::Integer(n) => Some(n.to_string()),
+        ColumnValue::Float(f) => Some(f.to_string()),
+        ColumnValue::Boolean(b) => Some(if *b { "1" } else { "0" }.to_string()),
+    }
+}
+
+/// Values of `columns` in `row`, `None` when one is `NULL`
+fn values<'r>(row: &'r [Option<String>], columns: &[usize]) -> Option<Vec<&'r str>> {
+    columns.iter().map(|&i| row[i].as_deref()).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::Keys;
+    use crate::line_parser::{parse_line, ColumnValue};
+
+    #[test]
+    fn select_subset() {
+        let seed = ("company".to_string(), "id IN (1, 2)".to_string());
+        let mut keys = Keys::new(&seed);
+        for statement in [
+            // referenced before being created, on its primary key
+            "CREATE TABLE `comment` (`id` INT, `user_id` INT, PRIMARY KEY (`id`), \
+             CONSTRAINT `c_user` FOREIGN KEY (`user_id`) REFERENCES `user` (`id`))",
+            "INSERT INTO `comment` VALUES (1,10),(2,11),(3,12),(4,NULL)",
+            "CREATE TABLE `company` (`id` INT, `name` TEXT, `country_id` INT, PRIMARY KEY (`id`), \
+             CONSTRAINT `c_country` FOREIGN KEY (`country_id`) REFERENCES `country` (`id`))",
+            "INSERT INTO `company` VALUES (1,'a',100),(2,'b',NULL),(3,'c',101)",
+            "CREATE TABLE `country` (`id` INT, PRIMARY KEY (`id`))",
+            "INSERT INTO `country` VALUES (100),(101),(102)",
+            "CREATE TABLE `user` (`id` INT, `company_id` INT, `manager_id` INT, PRIMARY KEY (`id`), \
+             CONSTRAINT `u_company` FOREIGN KEY (`company_id`) REFERENCES `company` (`id`), \
+             CONSTRAINT `u_manager` FOREIGN KEY (`manager_id`) REFERENCES `user` (`id`))",
+            // user 13 is managed by user 10 of company 1, user 12 manages user 10
+            "INSERT INTO `user` VALUES (10,1,12),(11,2,NULL),(12,3,NULL),(13,3,10)",
+            "CREATE TABLE `log` (`message` TEXT)",
+            "INSERT INTO `log` VALUES ('unrelated')",
+        ] {
+            keys.add(parse_line(statement).unwrap()).unwrap();
+        }
+        let subset = keys.select().unwrap();
+        let kept = |table_name: &str, count: usize| -> Vec<i64> {
+            let mut rows: Vec<Vec<ColumnValue>> = (0..count)
+                .map(|i| vec![ColumnValue::Integer(i as i64)])
+                .collect();
+            subset.table(table_name).rows(&mut rows);
+            rows.into_iter()
+                .map(|row| match row[0] {
+                    ColumnValue::Integer(i) => i,
+                    _ => unreachable!(),
+                })
+                .collect()
+        };
+        // company 3 of users 12 and 13
+        assert_eq!(kept("company", 3), [0, 1, 2]);
+        // referenced by company 1 and user 12
+        assert_eq!(kept("country", 3), [0, 1]);
+        // users of the companies and their subordinates, then the manager of user 10
+        assert_eq!(kept("user", 4), [0, 1, 2, 3]);
+        // not the comment of user 12, only there as a manager
+        assert_eq!(kept("comment", 4), [0, 1]);
+        assert_eq!(kept("log", 1), Vec::<i64>::new());
+        assert!(subset.is_empty("log"));
+        assert!(!subset.is_empty("user"));
+    }
+}
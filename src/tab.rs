@@ -0,0 +1,446 @@
+//! Conversion of `mysqldump --tab` directories.
+//!
+//! `mysqldump --tab=dir` writes one `table.sql` file holding the `CREATE TABLE` statement
+//! and one `table.txt` file of delimited rows, using `\N` for NULL and backslash escaping.
+
+use std::{
+    fs::read_dir,
+    io::BufRead,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::eyre::{bail, eyre, Context, Result};
+use indicatif::ProgressBar;
+
+use crate::{
+    filter::TableFilter,
+    input::{self, Compression},
+    line_parser::{ColumnType, ColumnValue, Line, Schema},
+    memory, read_schema,
+};
+
+/// rows sent to the parquet writer at once
+const BATCH_SIZE: usize = 10_000;
+
+/// `FIELDS` options used when the data files were written
+#[derive(Clone, Debug)]
+pub struct TabOptions {
+    /// `FIELDS TERMINATED BY`
+    pub fields_terminated_by: String,
+    /// `FIELDS [OPTIONALLY] ENCLOSED BY`
+    pub fields_enclosed_by: Option<char>,
+}
+
+impl Default for TabOptions {
+    fn default() -> Self {
+        TabOptions {
+            fields_terminated_by: "\t".into(),
+            fields_enclosed_by: None,
+        }
+    }
+}
+
+/// Parse a `FIELDS TERMINATED BY` / `ENCLOSED BY` option, accepting the escape sequences
+/// of MySQL (`\t`, `\n`, `\\`...).
+pub fn parse_field_option(value: &str) -> Result<String, String> {
+    let mut ret = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            ret.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some(c) => ret.push(unescape(c)),
+            None => return Err(format!("Dangling escape in `{value}`")),
+        }
+    }
+    if ret.is_empty() {
+        return Err("Field option must not be empty".into());
+    }
+    Ok(ret)
+}
+
+/// Character represented by `\c` in MySQL text formats
+fn unescape(c: char) -> char {
+    match c {
+        '0' => '\0',
+        'b' => '\x08',
+        'n' => '\n',
+        'r' => '\r',
+        't' => '\t',
+        'Z' => '\x1a',
+        c => c,
+    }
+}
+
+/// Splitter of records into fields, `None` being NULL.
+///
+/// Records are fed line by line, the state of the current field being kept across the
+/// lines of a record whose newlines are escaped or enclosed, so that each line is only
+/// read once.
+#[derive(Debug, Default)]
+struct RecordSplitter {
+    fields: Vec<Option<String>>,
+    field: String,
+    /// NULL is only an unenclosed \N
+    null: bool,
+    enclosed: bool,
+    /// a character of the current field was read
+    field_started: bool,
+    /// the last line ended with a backslash
+    escaped: bool,
+    /// lines of an incomplete record were read
+    incomplete: bool,
+}
+
+impl RecordSplitter {
+    /// Read a `line` ending with its terminator, returning the fields of the record it
+    /// completes, or `None` when its last newline is escaped or enclosed.
+    fn split_line(&mut self, line: &str, options: &TabOptions) -> Option<Vec<Option<String>>> {
+        let mut chars = line.char_indices().peekable();
+        if std::mem::take(&mut self.escaped) {
+            if let Some((_, c)) = chars.next() {
+                self.field.push(unescape(c));
+            }
+        }
+        while let Some((i, c)) = chars.next() {
+            if !self.field_started && Some(c) == options.fields_enclosed_by {
+                self.field_started = true;
+                self.enclosed = true;
+                continue;
+            }
+            self.field_started = true;
+            if c == '\\' {
+                match chars.next() {
+                    Some((_, 'N')) if self.field.is_empty() && !self.enclosed => self.null = true,
+                    Some((_, c)) => self.field.push(unescape(c)),
+                    None => self.escaped = true,
+                }
+            } else if self.enclosed {
+                if Some(c) == options.fields_enclosed_by {
+                    if chars.peek().map(|(_, c)| *c) == options.fields_enclosed_by {
+                        // doubled quote
+                        chars.next();
+                        self.field.push(c);
+                    } else {
+                        self.enclosed = false;
+                    }
+                } else {
+                    self.field.push(c);
+                }
+            } else if c == '\n' {
+                let mut record = std::mem::take(self);
+                record.fields.push((!record.null).then_some(record.field));
+                return Some(record.fields);
+            } else if line[i..].starts_with(&options.fields_terminated_by) {
+                for _ in 1..options.fields_terminated_by.chars().count() {
+                    chars.next();
+                }
+                let field = std::mem::take(&mut self.field);
+                self.fields.push((!self.null).then_some(field));
+                self.null = false;
+                self.field_started = false;
+            } else {
+                self.field.push(c);
+            }
+        }
+        self.incomplete = true;
+        None
+    }
+}
+
+fn to_column_value(field: Option<String>, column_type: &ColumnType) -> Result<ColumnValue> {
+    let Some(field) = field else {
+        return Ok(ColumnValue::Null);
+    };
+    Ok(match column_type {
+        ColumnType::Decimal(_, _) => {
+            field
+                .parse::<f64>()
+                .with_context(|| format!("Invalid number {field}"))?;
+            ColumnValue::Decimal(field)
+        }
+        ColumnType::Integer => match field.parse() {
+            Ok(value) => ColumnValue::Integer(value),
+            Err(_) => ColumnValue::Float(
+                field
+                    .parse()
+                    .with_context(|| format!("Invalid number {field}"))?,
+            ),
+        },
+        ColumnType::Float => ColumnValue::Float(
+            field
+                .parse()
+                .with_context(|| format!("Invalid number {field}"))?,
+        ),
+        ColumnType::Boolean => ColumnValue::Boolean(field != "0"),
+        ColumnType::String | ColumnType::Timestamp => ColumnValue::String(field),
+    })
+}
+
+/// Stream the rows of `data_file` to the parquet writer.
+fn read_rows(
+    table_name: &str,
+    schema: &Schema,
+    data_file: &Path,
+    options: &TabOptions,
+    tables: &TableFilter,
+    writer_sender: &memory::Sender<Line>,
+    read_progress_bar: &ProgressBar,
+) -> Result<()> {
+    let mut reader = input::open(Some(&data_file.to_string_lossy()), Compression::Auto)?;
+    let mut line = String::new();
+    let mut splitter = RecordSplitter::default();
+    let mut rows = Vec::with_capacity(BATCH_SIZE);
+    let mut line_number = 0;
+    loop {
+        // like INSERT statements, stop reading batches once the limit is reached
+        if rows.is_empty() && tables.limits.is_reached(table_name) {
+            return Ok(());
+        }
+        line.clear();
+        let eof = reader.read_line(&mut line)? == 0;
+        line_number += 1;
+        if eof {
+            if splitter.incomplete {
+                bail!("Unterminated record at line {line_number}");
+            }
+            break;
+        }
+        if !line.ends_with('\n') {
+            // last line without terminator
+            line.push('\n');
+        }
+        let Some(fields) = splitter.split_line(&line, options) else {
+            continue;
+        };
+        read_progress_bar.inc(1);
+        if fields.len() != schema.columns.len() {
+            bail!(
+                "Line {line_number} has {} fields, table `{table_name}` has {} columns",
+                fields.len(),
+                schema.columns.len()
+            );
+        }
+        rows.push(
+            fields
+                .into_iter()
+                .zip(&schema.columns)
+                .map(|(field, column)| to_column_value(field, &column.column_type))
+                .collect::<Result<Vec<_>>>()
+                .with_context(|| format!("Invalid value at line {line_number}"))?,
+        );
+        if rows.len() == BATCH_SIZE {
+            writer_sender
+                .send(Line::InsertInto(
+                    table_name.into(),
+                    std::mem::take(&mut rows),
+                ))
+                .map_err(|_| eyre!("Cannot send rows to parquet writer"))?;
+        }
+    }
+    if !rows.is_empty() {
+        writer_sender
+            .send(Line::InsertInto(table_name.into(), rows))
+            .map_err(|_| eyre!("Cannot send rows to parquet writer"))?;
+    }
+    Ok(())
+}
+
+/// Convert the `tables` of the `mysqldump --tab` directory `dir`, `jobs` tables at a time.
+pub fn convert(
+    dir: &Path,
+    options: &TabOptions,
+    tables: &TableFilter,
+    jobs: usize,
+    writer_sender: memory::Sender<Line>,
+    read_progress_bar: &ProgressBar,
+) -> Result<()> {
+    let mut schema_files: Vec<PathBuf> = read_dir(dir)
+        .with_context(|| format!("Cannot read {}", dir.display()))?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<Result<Vec<_>>>()?
+        .into_iter()
+        .filter(|path| path.extension().is_some_and(|ext| ext == "sql"))
+        .filter(|path| {
+            path.file_stem()
+                .is_some_and(|table| tables.accepts(&table.to_string_lossy()))
+        })
+        .collect();
+    schema_files.sort();
+
+    let (file_sender, file_receiver) = crossbeam::channel::unbounded();
+    for schema_file in schema_files {
+        file_sender.send(schema_file)?;
+    }
+    drop(file_sender);
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..jobs.max(1))
+            .map(|_| {
+                scope.spawn(|| -> Result<()> {
+                    while let Ok(schema_file) = file_receiver.recv() {
+                        // views have no CREATE TABLE statement and no data
+                        let Some((table_name, schema)) =
+                            read_schema(&schema_file).with_context(|| {
+                                format!("Unable to read schema {}", schema_file.display())
+                            })?
+                        else {
+                            continue;
+                        };
+                        let data_file = schema_file.with_extension("txt");
+                        writer_sender
+                            .send(Line::CreateTable(table_name.clone(), schema.clone()))
+                            .map_err(|_| eyre!("Cannot send schema to parquet writer"))?;
+                        if !data_file.exists() {
+                            continue;
+                        }
+                        read_progress_bar.set_message(data_file.display().to_string());
+                        read_rows(
+                            &table_name,
+                            &schema,
+                            &data_file,
+                            options,
+                            tables,
+                            &writer_sender,
+                            read_progress_bar,
+                        )
+                        .with_context(|| format!("Unable to convert {}", data_file.display()))?;
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .try_for_each(|worker| worker.join().expect("Tab reader thread crashed!"))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::testing::TempDir;
+    use indicatif::ProgressBar;
+
+    use super::{parse_field_option, read_rows, RecordSplitter, TabOptions};
+    use crate::{
+        filter::TableFilter,
+        line_parser::{parse_line, Line},
+        memory,
+    };
+
+    /// Fields of the last line of `record`
+    fn split(record: &str, options: &TabOptions) -> Option<Vec<Option<String>>> {
+        let mut splitter = RecordSplitter::default();
+        let mut fields = None;
+        for line in record.split_inclusive('\n') {
+            fields = splitter.split_line(line, options);
+        }
+        fields
+    }
+
+    #[test]
+    fn tab_separated() {
+        let options = TabOptions::default();
+        assert_eq!(
+            split("1\tfoo\\tbar\t\\N\t\n", &options),
+            Some(vec![
+                Some("1".into()),
+                Some("foo\tbar".into()),
+                None,
+                Some("".into())
+            ])
+        );
+        assert_eq!(
+            split("1\tescaped \\\\N\n", &options),
+            Some(vec![Some("1".into()), Some("escaped \\N".into())])
+        );
+        // escaped newline: the record continues on the next line
+        assert_eq!(split("1\tfoo\\\n", &options), None);
+        assert_eq!(
+            split("1\tfoo\\\nbar\n", &options),
+            Some(vec![Some("1".into()), Some("foo\nbar".into())])
+        );
+    }
+
+    #[test]
+    fn enclosed_csv() {
+        let options = TabOptions {
+            fields_terminated_by: ",".into(),
+            fields_enclosed_by: Some('"'),
+        };
+        assert_eq!(
+            split("\"1\",\"a, \\\"b\\\"\",\"c\"\"d\",\\N\n", &options),
+            Some(vec![
+                Some("1".into()),
+                Some("a, \"b\"".into()),
+                Some("c\"d".into()),
+                None
+            ])
+        );
+        assert_eq!(split("\"1\",\"multi\n", &options), None);
+        assert_eq!(
+            split("\"1\",\"multi\nline\"\n", &options),
+            Some(vec![Some("1".into()), Some("multi\nline".into())])
+        );
+        // "\N" enclosed is a string
+        assert_eq!(split("\"\\N\"\n", &options), Some(vec![Some("N".into())]));
+    }
+
+    #[test]
+    fn consecutive_records() {
+        let options = TabOptions::default();
+        let mut splitter = RecordSplitter::default();
+        assert_eq!(splitter.split_line("1\ta\\\n", &options), None);
+        assert_eq!(
+            splitter.split_line("b\n", &options),
+            Some(vec![Some("1".into()), Some("a\nb".into())])
+        );
+        assert!(!splitter.incomplete);
+        assert_eq!(
+            splitter.split_line("\\N\tc\n", &options),
+            Some(vec![None, Some("c".into())])
+        );
+    }
+
+    #[test]
+    fn stop_at_limit() {
+        let dir = TempDir::new("tab");
+        let path = dir.join("t.txt");
+        std::fs::write(&path, "1\n2\n3").unwrap();
+        let Line::CreateTable(_, schema) = parse_line("CREATE TABLE t (id INT);").unwrap() else {
+            panic!("not a CREATE TABLE");
+        };
+        let tables = TableFilter::default();
+        let read = |tables: &TableFilter| {
+            let (sender, receiver) = memory::bounded(1 << 20);
+            read_rows(
+                "t",
+                &schema,
+                &path,
+                &TabOptions::default(),
+                tables,
+                &sender,
+                &ProgressBar::hidden(),
+            )
+            .unwrap();
+            drop(sender);
+            let mut rows = 0;
+            while let Ok(Line::InsertInto(_, batch)) = receiver.recv() {
+                rows += batch.len();
+            }
+            rows
+        };
+        assert_eq!(read(&tables), 3);
+        tables.limits.start("t", 0);
+        assert_eq!(read(&tables), 0);
+    }
+
+    #[test]
+    fn field_options() {
+        assert_eq!(parse_field_option("\\t"), Ok("\t".into()));
+        assert_eq!(parse_field_option(",;"), Ok(",;".into()));
+        assert!(parse_field_option("").is_err());
+        assert!(parse_field_option("\\").is_err());
+    }
+}
@@ -0,0 +1,45 @@
+//! Helpers shared by tests.
+
+use std::{
+    fs::{create_dir_all, remove_dir_all},
+    ops::Deref,
+    path::{Path, PathBuf},
+};
+
+/// Empty directory of a test, removed with its files when dropped
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Create `mysqldump-to-parquet-{name}-{pid}` in the temporary directory, `name`
+    /// telling apart the tests running in parallel
+    pub fn new(name: &str) -> TempDir {
+        let dir = std::env::temp_dir().join(format!(
+            "mysqldump-to-parquet-{name}-{}",
+            std::process::id()
+        ));
+        // left by an interrupted run
+        let _ = remove_dir_all(&dir);
+        create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = remove_dir_all(&self.0);
+    }
+}